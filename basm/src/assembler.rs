@@ -0,0 +1,309 @@
+use crate::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Everything that changes how a program is assembled, the same as the
+/// options of the same names given to `basm`
+#[derive(Debug, Clone, Default)]
+pub struct AssemblerOptions {
+    /// Emit a relocatable object file for bld instead of an executable (`-c`)
+    pub object: bool,
+    /// Emit a raw image, with `.start`/`.ssp`/`.sbp` encoded as instructions (`--raw`)
+    pub raw: bool,
+    /// Constants for conditional assembly, as `NAME` or `NAME=VALUE` (`-D`)
+    pub defines: Vec<String>,
+    /// Directories to search for `#include` files (`-I`)
+    pub include_dirs: Vec<PathBuf>,
+}
+
+/// Assembles BELLE programs without touching any global state.
+///
+/// ```no_run
+/// use basm::{Assembler, AssemblerOptions, SourceFiles};
+///
+/// let files = SourceFiles::memory([("lib.asm", "hlt")]);
+/// let assembler = Assembler::with_files(AssemblerOptions::default(), files);
+/// let assembly = assembler.assemble_source("main.asm", "#include \"lib.asm\"");
+/// if !assembly.has_errors() {
+///     std::fs::write("a.out", assembly.to_bytes()).unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Assembler {
+    pub options: AssemblerOptions,
+    files: SourceFiles,
+}
+
+/// The result of assembling a program. Everything but `diagnostics` and
+/// `sources` is empty when assembly stopped before any line was lexed.
+#[derive(Debug, Default)]
+pub struct Assembly {
+    pub options: AssemblerOptions,
+    /// Errors and warnings, resolved against `sources`
+    pub diagnostics: Vec<Diagnostic>,
+    pub symbols: SymbolTable,
+    pub executable: Executable,
+    pub object: ObjectFile,
+    /// Every word in the order it was emitted, directives included
+    pub raw: Vec<i16>,
+    pub listing: Listing,
+    pub sources: SourceMap,
+    /// The program after includes, macros and conditional assembly
+    pub lines: Vec<String>,
+    pub lexed: Vec<LexedLine>,
+    /// Address of the first line, set with `.start`
+    pub start: u32,
+}
+
+impl Assembler {
+    /// An assembler that reads source files from disk
+    #[must_use]
+    pub fn new(options: AssemblerOptions) -> Assembler {
+        Assembler::with_files(options, SourceFiles::Disk)
+    }
+
+    /// An assembler that reads source files, including `#include`s, from `files`
+    #[must_use]
+    pub fn with_files(options: AssemblerOptions, files: SourceFiles) -> Assembler {
+        Assembler { options, files }
+    }
+
+    /// Assembles the file at `path`
+    #[must_use]
+    pub fn assemble(&self, path: &str) -> Assembly {
+        self.run(path, None)
+    }
+
+    /// Assembles `text` as if it were read from a file called `name`, which
+    /// is also where its `#include`s are looked up from
+    #[must_use]
+    pub fn assemble_source(&self, name: &str, text: &str) -> Assembly {
+        self.run(name, Some(text))
+    }
+
+    fn run(&self, name: &str, text: Option<&str>) -> Assembly {
+        let mut assembly = Assembly {
+            options: self.options.clone(),
+            start: DEFAULT_START.into(),
+            ..Assembly::default()
+        };
+        let (lines, sources) =
+            process_includes(name, text, &self.files, &self.options.include_dirs);
+        assembly.sources = sources;
+        match lines {
+            Ok(lines) => assembly.assemble(&lines),
+            Err(e) => assembly.diagnostics.push(e),
+        }
+        let sources = &assembly.sources;
+        assembly.diagnostics = assembly
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| diagnostic.resolve(sources))
+            .collect();
+        assembly
+    }
+}
+
+impl Assembly {
+    fn assemble(&mut self, lines: &[String]) {
+        let lines: Vec<String> = lines.iter().map(|line| line.trim().to_string()).collect();
+        let lines = match assemble_conditionals(&lines, &self.options.defines, &self.sources) {
+            Ok(lines) => lines,
+            Err(errors) => {
+                self.diagnostics
+                    .extend(errors.into_iter().map(Diagnostic::from));
+                return;
+            }
+        };
+        let lines = match expand_macros(&lines) {
+            Ok((lines, origins)) => {
+                self.sources.set_origins(origins);
+                lines
+            }
+            Err(errors) => {
+                self.diagnostics
+                    .extend(errors.into_iter().map(Diagnostic::from));
+                return;
+            }
+        };
+        // conditions inside macro definitions, now that their parameters are filled in
+        self.lines = match assemble_conditionals(&lines, &self.options.defines, &self.sources) {
+            Ok(lines) => lines,
+            Err(errors) => {
+                self.diagnostics
+                    .extend(errors.into_iter().map(Diagnostic::from));
+                return;
+            }
+        };
+        if let Some(start) = start_location(&self.lines) {
+            self.start = start;
+        }
+
+        for (index, line) in self.lines.iter().enumerate() {
+            let mut lexer = Lexer::new(line, index as u32 + 1);
+            match lexer.lex() {
+                Ok(tokens) => self.lexed.push(LexedLine {
+                    number: index as u32 + 1,
+                    tokens: tokens.clone(),
+                    address: 0,
+                }),
+                Err(err) => self.diagnostics.push(Diagnostic::from(err)),
+            }
+        }
+
+        let mut failed_lines = HashSet::new();
+        if let Err(errors) = build_symbol_table(
+            &mut self.lexed,
+            &mut self.symbols,
+            &self.options,
+            self.start,
+        ) {
+            failed_lines.extend(errors.iter().map(|err| err.line));
+            self.diagnostics.extend(errors);
+        }
+
+        let mut hlt_seen = false;
+        let lexed_lines = std::mem::take(&mut self.lexed);
+        for lexed in &lexed_lines {
+            if failed_lines.contains(&lexed.number) {
+                continue;
+            }
+            let text = &self.lines[lexed.number as usize - 1];
+            self.listing
+                .begin_line(lexed.address, lexed.number, text, &self.sources);
+            let tokens = lexed.instruction();
+            if tokens.is_empty() {
+                continue;
+            }
+            hlt_seen |= tokens
+                .iter()
+                .any(|token| token.get_raw().to_lowercase() == "hlt");
+            if let Err(err) = self.encode_line(lexed.address, tokens) {
+                self.diagnostics.push(err.on_line(lexed.number));
+            }
+        }
+        self.lexed = lexed_lines;
+
+        if !hlt_seen {
+            self.diagnostics.push(
+                Diagnostic::warning(
+                    codes::NO_HLT,
+                    format!(
+                        "no HLT instruction found in program {}",
+                        self.sources.main_file
+                    ),
+                )
+                .with_help("without HLT the CPU runs past the end of the program"),
+            );
+        }
+        if self.options.object {
+            self.object.add_symbols(&self.symbols);
+        }
+    }
+
+    fn encode_line(&mut self, address: u32, tokens: &[Token]) -> Result<(), Diagnostic> {
+        let instruction = tokens.first();
+        let operand1 = tokens.get(1);
+        let operand2 = {
+            if let Some(Token::Comma) = tokens.get(2) {
+                tokens.get(3)
+            } else {
+                tokens.get(2)
+            }
+        };
+
+        if let Some(Token::Label(directive)) = instruction {
+            if is_data_directive(directive) {
+                let words = encode_data(tokens, address, &self.symbols, self.options.raw)?;
+                if self.options.object {
+                    self.object.push_data(&words, tokens, &self.symbols);
+                }
+                self.listing.push_words(&words, true);
+                self.executable
+                    .push_words(address, &words, SegmentKind::Data);
+                self.raw.extend(words);
+                return Ok(());
+            }
+        }
+        let Some(ins) = instruction else {
+            return Ok(());
+        };
+        let Some(encoded) = encode_instruction(ins, operand1, operand2, &self.symbols)? else {
+            return Ok(());
+        };
+        verify(ins, operand1, operand2)?;
+        if self.options.object {
+            self.add_to_object(encoded, ins, operand1, operand2)
+                .map_err(|e| Diagnostic::error(codes::OBJECT, e))?;
+        }
+        if let Token::Label(directive) = ins {
+            self.executable
+                .set_directive(directive, operand1.map_or(0, Token::get_num));
+        } else {
+            self.executable
+                .push_words(address, &[encoded], SegmentKind::Text);
+        }
+        let directive = matches!(ins, Token::Label(_));
+        // only raw images hold the words of CPU directives
+        if !directive || self.options.raw {
+            self.listing.push_words(&[encoded], !directive);
+        }
+        self.raw.push(encoded);
+        Ok(())
+    }
+
+    fn add_to_object(
+        &mut self,
+        encoded: i16,
+        ins: &Token,
+        operand1: Option<&Token>,
+        operand2: Option<&Token>,
+    ) -> Result<(), String> {
+        if let Token::Label(directive) = ins {
+            self.object
+                .set_directive(directive, operand1.map_or(0, Token::get_num));
+            return Ok(());
+        }
+        self.object.push_word(
+            encoded,
+            ins,
+            operand1,
+            operand2,
+            self.start as i16,
+            &self.symbols,
+        )
+    }
+
+    /// Whether any diagnostic is an error, in which case nothing should be written
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    /// The object file, raw image or executable, according to the options
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.options.object {
+            self.object.to_bytes()
+        } else if self.options.raw {
+            self.raw
+                .iter()
+                .flat_map(|word| word.to_be_bytes())
+                .collect()
+        } else {
+            self.executable.to_bytes()
+        }
+    }
+
+    /// The listing written with `basm -l`
+    #[must_use]
+    pub fn render_listing(&self) -> String {
+        self.listing.render(&self.symbols, &self.sources)
+    }
+
+    /// The symbol map written with `basm -g`
+    #[must_use]
+    pub fn render_symbol_map(&self) -> String {
+        render_symbol_map(&self.listing, &self.symbols, &self.sources, self.start)
+    }
+}
@@ -0,0 +1,96 @@
+/*
+ * Copyright (c) 2024 BlueGummi
+ * All rights reserved.
+ *
+ * This code is licensed under the BSD 3-Clause License.
+ */
+use basm::*;
+use clap::Parser;
+use colored::Colorize;
+use std::fs;
+use std::process;
+
+/// Command line arguments
+#[derive(Parser)]
+#[command(name = "bld")]
+#[command(version = "0.1.0")]
+#[command(author = "gummi")]
+#[command(about = "The linker for BELLE", long_about = None)]
+struct Args {
+    /// Object files made with `basm -c`, in the order they are loaded
+    #[clap(required = true)]
+    objects: Vec<String>,
+
+    /// Output file for binary
+    #[clap(short = 'o', long, default_value = "a.out")]
+    output: String,
+
+    /// Write a raw image without a header, as `basm --raw` does
+    #[clap(long, default_value_t = false)]
+    raw: bool,
+
+    /// Verbose output
+    #[clap(short = 'v', long, default_value_t = false)]
+    verbose: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut objects = Vec::new();
+    let mut failed = false;
+    for path in &args.objects {
+        match fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| ObjectFile::from_bytes(&bytes))
+        {
+            Ok(object) => objects.push((path.to_string(), object)),
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    Diagnostic::error(codes::BAD_OBJECT_FILE, format!("{path}: {e}"))
+                );
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+
+    let image = match link(&objects) {
+        Ok(image) => image,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{e}");
+            }
+            process::exit(1);
+        }
+    };
+
+    if args.verbose {
+        println!("{}", "Symbols:".blue());
+        for (name, address) in &image.symbols {
+            println!("{address:>5}  {name}");
+        }
+    }
+
+    let bytes = if args.raw {
+        image.to_flat()
+    } else {
+        image.to_executable().to_bytes()
+    };
+    if let Err(e) = fs::write(&args.output, bytes) {
+        eprintln!(
+            "{}",
+            Diagnostic::error(
+                codes::GENERAL,
+                format!("could not write {}: {e}", args.output)
+            )
+        );
+        process::exit(1);
+    }
+    if args.verbose {
+        println!("{}", "Wrote to file.".green());
+    }
+}
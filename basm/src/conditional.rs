@@ -0,0 +1,279 @@
+use crate::Error::*;
+use crate::*;
+use std::collections::HashMap;
+
+struct Block {
+    /// Line of the `.if`, `.ifdef` or `.ifndef` that opened the block
+    line: u32,
+    /// Whether the enclosing region is being assembled
+    outer: bool,
+    /// Whether the current branch is being assembled
+    active: bool,
+    seen_else: bool,
+}
+
+/// The constants given on the command line with `-D NAME` or `-D NAME=VALUE`.
+/// A name without a value is defined as 1.
+pub fn parse_defines(defines: &[String]) -> Result<Vec<(String, i16)>, String> {
+    defines
+        .iter()
+        .map(|define| {
+            let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+            let name = name.trim();
+            if name.is_empty() || Expr::parse(name) != Ok(Expr::Symbol(name.to_string())) {
+                return Err(format!("-D {define}: {name:?} is not a valid name"));
+            }
+            let value = Expr::parse(value)
+                .map_err(str::to_string)
+                .and_then(|expr| expr.eval(&|_| None))
+                .and_then(|value| {
+                    i16::try_from(value).map_err(|_| "value does not fit in 16 bits".to_string())
+                })
+                .map_err(|e| format!("-D {define}: {e}"))?;
+            Ok((name.to_string(), value))
+        })
+        .collect()
+}
+
+/// Removes the regions of the program excluded by conditional assembly.
+///
+/// ```asm
+/// .if LEVEL > 1
+///     int 0
+/// .else
+///     nop
+/// .endif
+/// ```
+///
+/// `.if` assembles its block when the expression is not zero, `.ifdef` and
+/// `.ifndef` when a constant is or is not defined. Conditions can use
+/// constants from `-D` and from any `.equ` above them that is not itself
+/// skipped. Skipped lines, and the directives themselves, are replaced with
+/// empty lines, so they are never lexed and line numbers stay the same.
+///
+/// This runs before macros are expanded, so macros in skipped regions are
+/// never defined or used. Conditions inside a macro definition are left
+/// alone, since they can depend on its parameters, and are worked out by
+/// running this again on the expanded program.
+pub fn assemble_conditionals(
+    lines: &[String],
+    defines: &[String],
+    sources: &SourceMap,
+) -> Result<Vec<String>, Vec<Error<'static>>> {
+    let mut errors = Vec::new();
+    let mut constants: HashMap<String, i32> = HashMap::new();
+    match parse_defines(defines) {
+        Ok(defines) => {
+            constants.extend(
+                defines
+                    .into_iter()
+                    .map(|(name, value)| (name, value.into())),
+            );
+        }
+        Err(e) => errors.push(ConditionalError(e, 0)),
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut output = Vec::new();
+    let mut in_macro = false;
+    for (index, text) in lines.iter().enumerate() {
+        let line = index as u32 + 1;
+        let code = strip_comment(text).trim();
+        let directive = code.split_whitespace().next().unwrap_or("").to_lowercase();
+        let operand = code[directive.len()..].trim();
+        let active = blocks.last().is_none_or(|block| block.active);
+        if directive == ".macro" {
+            in_macro = true;
+        }
+        let defining = in_macro;
+        if directive == ".endm" {
+            in_macro = false;
+        }
+
+        match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" if !defining => {
+                let taken = active
+                    && match directive.as_str() {
+                        ".if" => match evaluate(operand, &constants) {
+                            Ok(value) => value != 0,
+                            Err(e) => {
+                                errors.push(ExpressionError(e, line));
+                                false
+                            }
+                        },
+                        ".ifdef" => constants.contains_key(operand),
+                        _ => !constants.contains_key(operand),
+                    };
+                blocks.push(Block {
+                    line,
+                    outer: active,
+                    active: taken,
+                    seen_else: false,
+                });
+            }
+            ".else" if !defining => match blocks.last_mut() {
+                Some(block) if !block.seen_else => {
+                    block.active = block.outer && !block.active;
+                    block.seen_else = true;
+                }
+                Some(block) => {
+                    let opened = sources.origin_location(block.line).line;
+                    errors.push(ConditionalError(
+                        format!("second .else for the block opened on line {opened}"),
+                        line,
+                    ));
+                }
+                None => errors.push(ConditionalError(".else without .if".to_string(), line)),
+            },
+            ".endif" if !defining => {
+                if blocks.pop().is_none() {
+                    errors.push(ConditionalError(".endif without .if".to_string(), line));
+                }
+            }
+            _ => {
+                if active {
+                    if directive == ".equ" && !defining {
+                        define_constant(operand, &mut constants);
+                    }
+                    output.push(text.to_string());
+                    continue;
+                }
+            }
+        }
+        output.push(String::new());
+    }
+
+    for block in blocks {
+        errors.push(ConditionalError(
+            "conditional block is never closed with .endif".to_string(),
+            block.line,
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(output)
+    } else {
+        errors.sort_by_key(Error::line_number);
+        Err(errors)
+    }
+}
+
+fn evaluate(operand: &str, constants: &HashMap<String, i32>) -> Result<i32, String> {
+    let expr = Expr::parse(operand).map_err(|e| format!("{e} in .if"))?;
+    if let Some(name) = expr
+        .symbols()
+        .into_iter()
+        .find(|name| !constants.contains_key(*name))
+    {
+        return Err(format!("{name} is not a constant defined above this .if"));
+    }
+    expr.eval(&|name| constants.get(name).copied())
+}
+
+/// Records an `.equ` so later conditions can test it. Values that cannot be
+/// worked out here are left for the symbol table to report.
+fn define_constant(operand: &str, constants: &mut HashMap<String, i32>) {
+    let Some((name, value)) = operand.split_once(char::is_whitespace) else {
+        return;
+    };
+    if let Ok(value) = evaluate(value, constants) {
+        constants.entry(name.to_string()).or_insert(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn assemble(text: &str, defines: &[&str]) -> Assembly {
+        let options = AssemblerOptions {
+            defines: defines.iter().map(ToString::to_string).collect(),
+            ..AssemblerOptions::default()
+        };
+        Assembler::with_files(options, SourceFiles::memory::<&str, &str>([]))
+            .assemble_source("main.asm", text)
+    }
+
+    fn code(assembly: &Assembly) -> Vec<&str> {
+        assembly
+            .lines
+            .iter()
+            .map(String::as_str)
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    const TRACE: &str = "\
+.ifdef DEBUG
+.macro trace
+    int 4
+.endm
+.else
+.macro trace
+    nop
+.endm
+.endif
+    trace
+    hlt";
+
+    #[test]
+    fn macro_defined_in_each_branch() {
+        let release = assemble(TRACE, &[]);
+        assert!(!release.has_errors(), "{:?}", release.diagnostics);
+        assert_eq!(code(&release), ["nop", "hlt"]);
+
+        let debug = assemble(TRACE, &["DEBUG"]);
+        assert!(!debug.has_errors(), "{:?}", debug.diagnostics);
+        assert_eq!(code(&debug), ["int 4", "hlt"]);
+    }
+
+    #[test]
+    fn skipped_macro_call_is_not_expanded() {
+        let text = "\
+.macro one a
+    mov r0, \\a
+.endm
+.ifdef MISSING
+    one 1, 2, 3
+    undefined_macro
+.endif
+    one 5
+    hlt";
+        let assembly = assemble(text, &[]);
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        assert_eq!(code(&assembly), ["mov r0, 5", "hlt"]);
+
+        let assembly = assemble(text, &["MISSING"]);
+        assert!(assembly.has_errors());
+    }
+
+    #[test]
+    fn condition_on_macro_parameter() {
+        let text = "\
+.macro pick n
+.if \\n > 1
+    mov r0, \\n
+.else
+    mov r1, \\n
+.endif
+.endm
+    pick 1
+    pick 5
+    hlt";
+        let assembly = assemble(text, &[]);
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        assert_eq!(code(&assembly), ["mov r1, 1", "mov r0, 5", "hlt"]);
+    }
+
+    #[test]
+    fn unbalanced_blocks() {
+        for text in [
+            ".if 1\nhlt",
+            ".endif\nhlt",
+            ".else\nhlt",
+            ".if 1\n.else\n.else\n.endif",
+        ] {
+            assert!(assemble(text, &[]).has_errors(), "{text}");
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use basm::{AssemblerOptions, MessageFormat};
 use clap::Parser;
 pub use once_cell::sync::Lazy;
+use std::path::PathBuf;
 pub static CONFIG: Lazy<Args> = Lazy::new(declare_config);
 /// Command line arguments
 #[derive(Parser)]
@@ -27,11 +29,52 @@ pub struct Args {
     /// Display tips (may improve errors)
     #[clap(short = 't', long, default_value_t = true)]
     pub tips: bool,
+
+    /// Emit a relocatable object file for bld instead of a binary
+    #[clap(short = 'c', long, default_value_t = false)]
+    pub object: bool,
+
+    /// Define a constant for conditional assembly, as NAME or NAME=VALUE (repeatable)
+    #[clap(short = 'D', long = "define", value_name = "NAME[=VALUE]")]
+    pub define: Vec<String>,
+
+    /// Directory to search for #include files (repeatable)
+    #[clap(short = 'I', long = "include-dir", value_name = "DIR")]
+    pub include: Vec<String>,
+
+    /// Write a listing of addresses, encodings and source lines to this file
+    #[clap(short = 'l', long, value_name = "FILE")]
+    pub listing: Option<String>,
+
+    /// Write a map of addresses to labels and source lines next to the binary, for belle
+    #[clap(short = 'g', long = "symbols", default_value_t = false)]
+    pub symbols: bool,
+
+    /// Write a raw image without a header, with .start/.ssp/.sbp encoded as instructions
+    #[clap(long, default_value_t = false)]
+    pub raw: bool,
+
+    /// How to print errors and warnings
+    #[clap(long, value_enum, default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
     // /// Cow
     //#[clap(short = 'c', long, default_value_t = false)]
     //pub cow: bool,
 }
 
+impl Args {
+    /// The options that change what is assembled
+    #[must_use]
+    pub fn options(&self) -> AssemblerOptions {
+        AssemblerOptions {
+            object: self.object,
+            raw: self.raw,
+            defines: self.define.clone(),
+            include_dirs: self.include.iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
 /// Parse command line arguments and return the configuration
 #[must_use]
 pub fn declare_config() -> Args {
@@ -43,6 +86,13 @@ pub fn declare_config() -> Args {
         verbose: cli.verbose,
         debug: cli.debug,
         tips: cli.tips,
+        object: cli.object,
+        define: cli.define,
+        include: cli.include,
+        listing: cli.listing,
+        symbols: cli.symbols,
+        raw: cli.raw,
+        message_format: cli.message_format,
         // cow: cli.cow,
     }
 }
@@ -1,10 +1,7 @@
-use crate::CONFIG;
-use colored::Colorize;
-use std::fmt;
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
+use crate::*;
 
+/// The errors found while reading the program, before it is encoded. Each
+/// becomes a [`Diagnostic`] with its own error code when it is reported.
 #[derive(Debug)]
 pub enum Error<'a> {
     InvalidSyntax(&'a str, u32, Option<u32>),
@@ -12,75 +9,83 @@ pub enum Error<'a> {
     NonexistentData(&'a str, u32, Option<u32>),
     UnknownCharacter(String, u32, Option<u32>),
     OtherError(&'a str, u32, Option<u32>),
+    DuplicateLabel(String, u32, u32),
+    UndefinedLabel(String, u32),
+    ForwardReference(String, u32),
+    MacroError(String, u32),
+    ExpressionError(String, u32),
+    /// Line 0 is used for problems with the `-D` options
+    ConditionalError(String, u32),
     LineLessError(&'a str),
 }
 
 pub type AssemblerError<'a> = Result<(), Error<'a>>;
 
-impl std::error::Error for Error<'_> {}
-impl fmt::Display for Error<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let line_number = match self {
+impl Error<'_> {
+    #[must_use]
+    pub fn line_number(&self) -> Option<u32> {
+        match self {
             Error::InvalidSyntax(_, n, _)
             | Error::ExpectedArgument(_, n, _)
             | Error::NonexistentData(_, n, _)
             | Error::UnknownCharacter(_, n, _)
-            | Error::OtherError(_, n, _) => *n,
-
-            Error::LineLessError(_) => {
-                return write!(f, "error: {}", self.message());
-            }
-        };
-
-        let error_message = match self {
-            Error::InvalidSyntax(s, _, _) => format!("invalid syntax: \n{s}"),
-            Error::ExpectedArgument(s, _, _) => format!("expected an argument: \n{s}"),
-            Error::NonexistentData(s, _, _) => format!("nonexistent data: \n{s}"),
-            Error::UnknownCharacter(s, _, _) => format!("has unknown character: \n{s}"),
-            Error::OtherError(s, _, _) => (*s).to_string(),
-            _ => unreachable!(),
-        };
-
-        let location = match self {
-            Error::InvalidSyntax(_, _, n)
-            | Error::ExpectedArgument(_, _, n)
-            | Error::NonexistentData(_, _, n)
-            | Error::UnknownCharacter(_, _, n)
-            | Error::OtherError(_, _, n) => n,
-            _ => unreachable!(),
-        };
-
-        writeln!(
-            f,
-            "error on line {}: {}",
-            line_number.to_string().as_str().green(),
-            error_message
-        )?;
-
-        let input: &String = &CONFIG.file;
-        let path = Path::new(input);
-        for (current_line, line) in io::BufReader::new(File::open(path).unwrap())
-            .lines()
-            .enumerate()
-        {
-            if current_line + 1 == line_number.try_into().unwrap() {
-                writeln!(f, "{}", line.unwrap().trim().bright_white())?;
-            }
-        }
-        if let Some(place) = location {
-            let spaces = " ".repeat((*place as usize) - 1);
-            writeln!(f, "{}{}", spaces, "^^".red().bold())?;
+            | Error::OtherError(_, n, _)
+            | Error::DuplicateLabel(_, n, _)
+            | Error::UndefinedLabel(_, n)
+            | Error::ForwardReference(_, n)
+            | Error::MacroError(_, n)
+            | Error::ExpressionError(_, n)
+            | Error::ConditionalError(_, n) => Some(*n),
+            Error::LineLessError(_) => None,
         }
-
-        Ok(())
     }
 }
 
-impl Error<'_> {
-    fn message(&self) -> &str {
-        match self {
-            Error::LineLessError(s) => s,
-            _ => "",
-        }
+impl From<Error<'_>> for Diagnostic {
+    fn from(error: Error<'_>) -> Diagnostic {
+        use codes::*;
+        let line = error.line_number().unwrap_or(0);
+        let column = |column: Option<u32>| column.map_or(Span::Line, Span::Column);
+        let diagnostic = match error {
+            Error::InvalidSyntax(s, _, n) => {
+                Diagnostic::error(INVALID_SYNTAX, format!("invalid syntax: {s}"))
+                    .with_span(column(n))
+            }
+            Error::ExpectedArgument(s, _, n) => {
+                Diagnostic::error(EXPECTED_ARGUMENT, format!("expected an argument: {s}"))
+                    .with_span(column(n))
+            }
+            Error::NonexistentData(s, _, n) => {
+                Diagnostic::error(NONEXISTENT_DATA, format!("nonexistent data: {s}"))
+                    .with_span(column(n))
+            }
+            Error::UnknownCharacter(ref s, _, n) => {
+                Diagnostic::error(UNKNOWN_CHARACTER, format!("unknown character {s}"))
+                    .with_span(column(n))
+            }
+            Error::OtherError(s, _, n) => Diagnostic::error(GENERAL, s).with_span(column(n)),
+            Error::DuplicateLabel(ref s, _, first) => {
+                Diagnostic::error(DUPLICATE_LABEL, format!("duplicate label {s}"))
+                    .with_span(Span::Text(s.to_string()))
+                    .with_note_on_line("first defined here", first)
+            }
+            Error::UndefinedLabel(ref s, _) => {
+                Diagnostic::error(UNDEFINED_LABEL, format!("undefined label {s}"))
+                    .with_span(Span::Text(s.to_string()))
+            }
+            Error::ForwardReference(ref s, _) => Diagnostic::error(
+                FORWARD_REFERENCE,
+                format!("{s} is used before its value is known"),
+            )
+            .with_span(Span::Text(s.to_string()))
+            .with_help("move the .equ or label above the line that uses it"),
+            Error::MacroError(ref s, _) => Diagnostic::error(MACRO, format!("macro error: {s}")),
+            Error::ExpressionError(ref s, _) => {
+                Diagnostic::error(EXPRESSION, format!("invalid expression: {s}"))
+            }
+            Error::ConditionalError(ref s, _) => Diagnostic::error(CONDITIONAL, s.to_string()),
+            Error::LineLessError(s) => Diagnostic::error(GENERAL, s),
+        };
+        diagnostic.on_line(line)
     }
 }
@@ -1,8 +1,6 @@
 pub mod error_types;
 pub mod instruction_consts;
-pub mod tip_types;
 pub mod token_types;
 pub use error_types::*;
 pub use instruction_consts::*;
-pub use tip_types::*;
 pub use token_types::*;
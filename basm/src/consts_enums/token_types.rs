@@ -1,9 +1,9 @@
-use crate::CONFIG;
+use crate::Expr;
 use colored::Colorize;
 use std::fmt;
 
 // self explanatory, you got this
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Ident(String),
     Register(i16),
@@ -17,7 +17,17 @@ pub enum Token {
     Label(String),
     RegPointer(i16),
     MemPointer(i16),
+    Str(String),
+    Expression(Operand, Expr),
 }
+
+/// The kind of token an expression operand becomes once it is folded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Literal,
+    MemAddr,
+}
+
 impl Token {
     #[must_use]
     pub fn get_raw(&self) -> String {
@@ -34,6 +44,8 @@ impl Token {
             Token::Label(s) => s.to_string(),
             Token::RegPointer(n) => n.to_string(),
             Token::MemPointer(n) => n.to_string(),
+            Token::Str(s) => s.to_string(),
+            Token::Expression(_, expr) => expr.to_string(),
         }
     }
     pub fn get_num(&self) -> i16 {
@@ -49,25 +61,25 @@ impl Token {
 }
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if CONFIG.verbose || CONFIG.debug {
-            match self {
-                Token::Ident(s) => {
-                    write!(f, "{} (\"{}\") Length: [{}]", "Ident".green(), s, s.len())
-                }
-                Token::Register(n) => write!(f, "{} ({})", "Register".red(), n),
-                Token::Comma => write!(f, "{}", "Comma".blue()),
-                Token::Literal(n) => write!(f, "{} ({})", "Number Literal".yellow(), n),
-                Token::NewLine => write!(f, "{}", "Newline".magenta()),
-                Token::Eol => writeln!(f, "{}", "Eol".cyan()),
-                Token::SRCall(s) => write!(f, "{} ({})", "SRCall".purple(), s),
-                Token::SR(s) => write!(f, "{} ({})", "Subroutine".bright_purple(), s),
-                Token::MemAddr(n) => write!(f, "{} ({})", "MemAddr".bright_red(), n),
-                Token::Label(s) => write!(f, "{} ({})", "Label".bright_yellow(), s),
-                Token::RegPointer(n) => write!(f, "{} ({})", "Reg Pointer".bright_green(), n),
-                Token::MemPointer(n) => write!(f, "{} ({})", "Mem Pointer".bold().yellow(), n),
+        match self {
+            Token::Ident(s) => {
+                write!(f, "{} (\"{}\") Length: [{}]", "Ident".green(), s, s.len())
+            }
+            Token::Register(n) => write!(f, "{} ({})", "Register".red(), n),
+            Token::Comma => write!(f, "{}", "Comma".blue()),
+            Token::Literal(n) => write!(f, "{} ({})", "Number Literal".yellow(), n),
+            Token::NewLine => write!(f, "{}", "Newline".magenta()),
+            Token::Eol => writeln!(f, "{}", "Eol".cyan()),
+            Token::SRCall(s) => write!(f, "{} ({})", "SRCall".purple(), s),
+            Token::SR(s) => write!(f, "{} ({})", "Subroutine".bright_purple(), s),
+            Token::MemAddr(n) => write!(f, "{} ({})", "MemAddr".bright_red(), n),
+            Token::Label(s) => write!(f, "{} ({})", "Label".bright_yellow(), s),
+            Token::RegPointer(n) => write!(f, "{} ({})", "Reg Pointer".bright_green(), n),
+            Token::MemPointer(n) => write!(f, "{} ({})", "Mem Pointer".bold().yellow(), n),
+            Token::Str(s) => write!(f, "{} ({:?})", "String".bright_cyan(), s),
+            Token::Expression(kind, expr) => {
+                write!(f, "{} {:?} ({})", "Expression".bright_blue(), kind, expr)
             }
-        } else {
-            Ok(())
         }
     }
 }
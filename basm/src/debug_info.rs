@@ -0,0 +1,91 @@
+use crate::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Version of the symbol map format, bumped whenever a reader would misread a newer file
+pub const SYMBOL_MAP_VERSION: u32 = 1;
+
+/// The file `basm -g` writes the symbol map of `output` to
+#[must_use]
+pub fn symbol_map_path(output: &str) -> String {
+    format!("{output}.bsym")
+}
+
+/// Renders the symbol map that lets belle show labels and source lines.
+///
+/// ```text
+/// belle-symbols 1
+/// start 100
+/// file 0 /home/user/program.asm
+/// line 100 0 4
+/// label 100 main
+/// ```
+///
+/// The first line names the format and its version. `file` records give each
+/// source file an index, `line` records map every loaded address to a file
+/// index and line, and `label` records give the address of every label.
+/// Files are named by the full paths `sources` recorded when they were read.
+#[must_use]
+pub fn render_symbol_map(
+    listing: &Listing,
+    table: &SymbolTable,
+    sources: &SourceMap,
+    start: u32,
+) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "belle-symbols {SYMBOL_MAP_VERSION}");
+    let _ = writeln!(out, "start {start}");
+
+    let mut files: HashMap<&str, usize> = HashMap::new();
+    let mut lines = Vec::new();
+    for (address, location) in listing.loaded_words() {
+        let next = files.len();
+        let index = *files.entry(location.file.as_str()).or_insert_with(|| {
+            let path = sources.path(&location.file).map_or_else(
+                || location.file.clone(),
+                |p| p.to_string_lossy().to_string(),
+            );
+            let _ = writeln!(out, "file {next} {path}");
+            next
+        });
+        lines.push((address, index, location.line));
+    }
+    for (address, file, line) in lines {
+        let _ = writeln!(out, "line {address} {file} {line}");
+    }
+
+    for (name, symbol) in table.sorted() {
+        if symbol.kind != SymbolKind::Extern {
+            let _ = writeln!(out, "label {} {name}", symbol.address);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn memory_sources_keep_their_paths() {
+        let files = SourceFiles::memory([("dir/lib.asm", "lib: nop\nret")]);
+        let assembler = Assembler::with_files(AssemblerOptions::default(), files);
+        let assembly = assembler.assemble_source(
+            "dir/./main.asm",
+            ".start $100\n#include \"lib.asm\"\nmain: hlt",
+        );
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        assert_eq!(
+            assembly.render_symbol_map(),
+            "belle-symbols 1\n\
+             start 100\n\
+             file 0 dir/lib.asm\n\
+             file 1 dir/main.asm\n\
+             line 100 0 1\n\
+             line 101 0 2\n\
+             line 102 1 3\n\
+             label 100 lib\n\
+             label 102 main\n"
+        );
+    }
+}
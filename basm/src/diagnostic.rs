@@ -0,0 +1,633 @@
+use crate::*;
+use clap::ValueEnum;
+use colored::Colorize;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// How `basm` prints diagnostics, chosen with `--message-format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MessageFormat {
+    /// Readable text on stderr
+    Human,
+    /// One JSON object per line on stdout
+    Json,
+}
+
+/// The part of a line a diagnostic points at. Spans are resolved against the
+/// source text when the diagnostic is printed, so they only need to be
+/// worked out for lines that were not expanded from a macro.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Span {
+    #[default]
+    Line,
+    /// A single column, counted from 1 in the trimmed line
+    Column(u32),
+    /// The nth operand of the instruction or directive, counted from 0
+    Operand(usize),
+    /// The first occurrence of this text in the line
+    Text(String),
+}
+
+/// Context attached to a diagnostic
+#[derive(Debug, Clone, PartialEq)]
+pub enum Note {
+    /// Where else the problem involves, such as a macro definition
+    Note(String, Option<Box<Snippet>>),
+    /// A note about a line of the program, which becomes a `Note` when the
+    /// diagnostic is resolved
+    Line(String, u32),
+    /// Advice on fixing the problem, shown with `-t`
+    Help(String),
+}
+
+/// A replacement for the text under a diagnostic's span
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub message: String,
+    pub replacement: String,
+}
+
+/// A place in a source file, with its text once the diagnostic is resolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub location: SourceLocation,
+    pub text: Option<String>,
+    /// Columns of the span within the text, counted from 1 with the end excluded
+    pub columns: Option<(u32, u32)>,
+}
+
+impl Snippet {
+    fn new(location: SourceLocation) -> Box<Snippet> {
+        Box::new(Snippet {
+            location,
+            text: None,
+            columns: None,
+        })
+    }
+}
+
+/// An error or warning, with everything needed to print it or hand it to an editor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: Box<str>,
+    /// Line of the program after includes and macros, 0 if the diagnostic is not about one
+    pub line: u32,
+    /// Where the diagnostic is, set by [`Diagnostic::resolve`] or when it is
+    /// about a file that is not part of the program yet
+    pub snippet: Option<Box<Snippet>>,
+    pub span: Span,
+    pub notes: Vec<Note>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Error codes, documented in the assembler's documentation
+pub mod codes {
+    pub const GENERAL: &str = "E0000";
+    pub const INVALID_SYNTAX: &str = "E0001";
+    pub const EXPECTED_ARGUMENT: &str = "E0002";
+    pub const NONEXISTENT_DATA: &str = "E0003";
+    pub const UNKNOWN_CHARACTER: &str = "E0004";
+    pub const DUPLICATE_LABEL: &str = "E0005";
+    pub const UNDEFINED_LABEL: &str = "E0006";
+    pub const FORWARD_REFERENCE: &str = "E0007";
+    pub const MACRO: &str = "E0008";
+    pub const EXPRESSION: &str = "E0009";
+    pub const CONDITIONAL: &str = "E0010";
+    pub const INCLUDE: &str = "E0011";
+    pub const OPERANDS: &str = "E0012";
+    pub const OUT_OF_RANGE: &str = "E0013";
+    pub const OBJECT: &str = "E0014";
+    pub const BAD_OBJECT_FILE: &str = "E0015";
+    pub const DUPLICATE_SYMBOL: &str = "E0016";
+    pub const UNRESOLVED_SYMBOL: &str = "E0017";
+    pub const RELOCATION_OVERFLOW: &str = "E0018";
+    pub const NO_HLT: &str = "W0001";
+}
+
+impl Diagnostic {
+    #[must_use]
+    pub fn error(code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into().into_boxed_str(),
+            line: 0,
+            snippet: None,
+            span: Span::Line,
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message)
+        }
+    }
+
+    /// Places the diagnostic on a line of the program, keeping any line it already has
+    #[must_use]
+    pub fn on_line(mut self, line: u32) -> Diagnostic {
+        if self.line == 0 {
+            self.line = line;
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn at(mut self, location: SourceLocation) -> Diagnostic {
+        self.snippet = Some(Snippet::new(location));
+        self
+    }
+
+    #[must_use]
+    pub fn with_span(mut self, span: Span) -> Diagnostic {
+        self.span = span;
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(
+        mut self,
+        note: impl Into<String>,
+        location: Option<SourceLocation>,
+    ) -> Diagnostic {
+        self.notes
+            .push(Note::Note(note.into(), location.map(Snippet::new)));
+        self
+    }
+
+    /// Adds a note pointing at a line of the program
+    #[must_use]
+    pub fn with_note_on_line(mut self, note: impl Into<String>, line: u32) -> Diagnostic {
+        self.notes.push(Note::Line(note.into(), line));
+        self
+    }
+
+    #[must_use]
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.notes.push(Note::Help(help.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn with_suggestion(
+        mut self,
+        message: impl Into<String>,
+        replacement: impl Into<String>,
+    ) -> Diagnostic {
+        self.suggestions.push(Suggestion {
+            message: message.into(),
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// The file and line the diagnostic is reported at
+    #[must_use]
+    pub fn source_location(&self) -> Option<&SourceLocation> {
+        self.snippet.as_ref().map(|snippet| &snippet.location)
+    }
+
+    /// Looks up the diagnostic's line and notes in `sources`, so it can be
+    /// printed with the source text it is about and the macros it came from
+    #[must_use]
+    pub fn resolve(mut self, sources: &SourceMap) -> Diagnostic {
+        let origin = (self.line != 0).then(|| sources.origin(self.line));
+        if self.snippet.is_none() {
+            self.snippet = origin
+                .as_ref()
+                .map(|origin| Snippet::new(origin.location(sources)));
+        }
+        if let Some(snippet) = &mut self.snippet {
+            snippet.text = snippet
+                .text
+                .take()
+                .or_else(|| sources.text(&snippet.location));
+            let expanded = origin.as_ref().is_some_and(|o| !o.expansions.is_empty());
+            if let (Some(text), false) = (&snippet.text, expanded) {
+                snippet.columns = span_columns(text, &self.span);
+            }
+        }
+
+        let mut notes = Vec::new();
+        for note in std::mem::take(&mut self.notes) {
+            notes.push(match note {
+                Note::Line(message, line) => {
+                    Note::Note(message, Some(Snippet::new(sources.origin_location(line))))
+                }
+                note => note,
+            });
+        }
+        // the macros the line was expanded from, innermost first
+        if let Some(origin) = origin {
+            let mut line = origin.line;
+            for (name, invoked_at) in origin.expansions.iter().rev() {
+                notes.push(Note::Note(
+                    format!("in macro {name}"),
+                    Some(Snippet::new(sources.location(line))),
+                ));
+                line = *invoked_at;
+            }
+        }
+        for note in &mut notes {
+            if let Note::Note(_, Some(snippet)) = note {
+                snippet.text = snippet
+                    .text
+                    .take()
+                    .or_else(|| sources.text(&snippet.location));
+            }
+        }
+        self.notes = notes;
+        self
+    }
+
+    /// Renders the diagnostic as one line of JSON
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let _ = write!(
+            out,
+            "\"severity\":\"{severity}\",\"code\":\"{}\",\"message\":{}",
+            self.code,
+            json_string(&self.message)
+        );
+        match self.source_location() {
+            Some(location) => {
+                let _ = write!(
+                    out,
+                    ",\"file\":{},\"line\":{}",
+                    json_string(&location.file),
+                    location.line
+                );
+            }
+            None => out.push_str(",\"file\":null,\"line\":null"),
+        }
+        match self.snippet.as_ref().and_then(|snippet| snippet.columns) {
+            Some((start, end)) => {
+                let _ = write!(out, ",\"column_start\":{start},\"column_end\":{end}");
+            }
+            None => out.push_str(",\"column_start\":null,\"column_end\":null"),
+        }
+        match self
+            .snippet
+            .as_ref()
+            .and_then(|snippet| snippet.text.as_ref())
+        {
+            Some(text) => {
+                let _ = write!(out, ",\"source\":{}", json_string(text));
+            }
+            None => out.push_str(",\"source\":null"),
+        }
+
+        let notes: Vec<String> = self
+            .notes
+            .iter()
+            .filter_map(|note| match note {
+                Note::Note(message, snippet) => {
+                    let location = snippet.as_ref().map(|snippet| &snippet.location);
+                    Some(format!(
+                        "{{\"kind\":\"note\",\"message\":{},\"file\":{},\"line\":{}}}",
+                        json_string(message),
+                        location.map_or("null".to_string(), |l| json_string(&l.file)),
+                        location.map_or("null".to_string(), |l| l.line.to_string())
+                    ))
+                }
+                Note::Line(..) => None,
+                Note::Help(message) => Some(format!(
+                    "{{\"kind\":\"help\",\"message\":{},\"file\":null,\"line\":null}}",
+                    json_string(message)
+                )),
+            })
+            .collect();
+        let _ = write!(out, ",\"notes\":[{}]", notes.join(","));
+
+        let suggestions: Vec<String> = self
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                format!(
+                    "{{\"message\":{},\"replacement\":{}}}",
+                    json_string(&suggestion.message),
+                    json_string(&suggestion.replacement)
+                )
+            })
+            .collect();
+        let _ = write!(out, ",\"suggestions\":[{}]}}", suggestions.join(","));
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = match self.severity {
+            Severity::Error => format!("error[{}]", self.code).red().bold(),
+            Severity::Warning => format!("warning[{}]", self.code).yellow().bold(),
+        };
+        writeln!(f, "{header}: {}", self.message.bold())?;
+
+        let gutter = " ".repeat(
+            self.source_location()
+                .map_or(1, |l| l.line.to_string().len()),
+        );
+        if let Some(snippet) = &self.snippet {
+            let location = &snippet.location;
+            let column = snippet
+                .columns
+                .map_or(String::new(), |(start, _)| format!(":{start}"));
+            writeln!(f, "{gutter}{} {location}{column}", "-->".blue())?;
+            if let Some(text) = &snippet.text {
+                writeln!(f, "{gutter} {}", "|".blue())?;
+                writeln!(
+                    f,
+                    "{} {} {}",
+                    location.line.to_string().blue(),
+                    "|".blue(),
+                    text.trim_end()
+                )?;
+                if let Some((start, end)) = snippet.columns {
+                    let marker = "^".repeat((end - start).max(1) as usize);
+                    let label = match self.suggestions.first() {
+                        Some(suggestion) => format!(
+                            " {}: {} `{}`",
+                            "help".cyan(),
+                            suggestion.message,
+                            suggestion.replacement
+                        ),
+                        None => String::new(),
+                    };
+                    let marker = match self.severity {
+                        Severity::Error => marker.red().bold(),
+                        Severity::Warning => marker.yellow().bold(),
+                    };
+                    writeln!(
+                        f,
+                        "{gutter} {} {}{marker}{label}",
+                        "|".blue(),
+                        " ".repeat(start as usize - 1)
+                    )?;
+                }
+            }
+        }
+
+        for note in &self.notes {
+            match note {
+                Note::Note(message, Some(snippet)) => {
+                    writeln!(
+                        f,
+                        "{gutter} {} {message}, {}",
+                        "= note:".bold(),
+                        snippet.location
+                    )?;
+                    if let Some(text) = &snippet.text {
+                        writeln!(f, "{gutter}         {}", text.trim())?;
+                    }
+                }
+                Note::Note(message, None) | Note::Line(message, _) => {
+                    writeln!(f, "{gutter} {} {message}", "= note:".bold())?;
+                }
+                Note::Help(message) => {
+                    writeln!(f, "{gutter} {} {message}", "= tip:".yellow().bold())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints every diagnostic, sorted by where it occurs, leaving out tips
+/// unless `tips` is set. Returns whether any of them is an error.
+pub fn emit_diagnostics(diagnostics: Vec<Diagnostic>, format: MessageFormat, tips: bool) -> bool {
+    let diagnostics = prepare_diagnostics(diagnostics, tips);
+    for diagnostic in &diagnostics {
+        match format {
+            MessageFormat::Human => eprintln!("{diagnostic}"),
+            MessageFormat::Json => println!("{}", diagnostic.to_json()),
+        }
+    }
+    diagnostics.iter().any(Diagnostic::is_error)
+}
+
+/// Sorts diagnostics by line of the program, which follows the order files
+/// are included in, then by column with errors first. Duplicates are dropped,
+/// and so are tips unless `tips` is set.
+fn prepare_diagnostics(mut diagnostics: Vec<Diagnostic>, tips: bool) -> Vec<Diagnostic> {
+    diagnostics.sort_by_key(|d| {
+        let column = match d.span {
+            Span::Column(column) => column,
+            _ => 0,
+        };
+        (d.line, column, d.severity)
+    });
+    diagnostics.dedup();
+    if !tips {
+        for diagnostic in &mut diagnostics {
+            diagnostic
+                .notes
+                .retain(|note| !matches!(note, Note::Help(_)));
+        }
+    }
+    diagnostics
+}
+
+/// The candidate closest to `name`, if one is near enough to be a likely typo
+#[must_use]
+pub fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let name = name.to_lowercase();
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| (1..=limit).contains(distance))
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// The columns of a span in a source line, if it can be found in it
+fn span_columns(text: &str, span: &Span) -> Option<(u32, u32)> {
+    let indent = (text.len() - text.trim_start().len()) as u32;
+    let code = strip_comment(text.trim_start()).trim_end();
+    let columns = match span {
+        Span::Line => Some((1, code.len().max(1) as u32 + 1)),
+        Span::Column(column) => Some((*column, column + 1)),
+        Span::Operand(index) => operand_columns(code, *index),
+        Span::Text(needle) => code
+            .find(needle.as_str())
+            .map(|start| (start as u32 + 1, (start + needle.len()) as u32 + 1)),
+    };
+    columns.map(|(start, end)| (start + indent, end + indent))
+}
+
+/// The columns of the nth operand in an instruction or directive, which
+/// follow the mnemonic and any labels and are separated by commas
+fn operand_columns(code: &str, index: usize) -> Option<(u32, u32)> {
+    let mut start = 0;
+    let mut rest = code;
+    // skip labels and the mnemonic
+    loop {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..word_end];
+        let skipped = word_end + rest[word_end..].len() - rest[word_end..].trim_start().len();
+        start += skipped;
+        rest = &rest[skipped..];
+        if !word.ends_with(':') {
+            break;
+        }
+    }
+    let mut quote = None;
+    let mut depth = 0;
+    let mut operand = 0;
+    let mut operand_start = 0;
+    for (i, c) in rest.char_indices().chain([(rest.len(), ',')]) {
+        match c {
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            c if Some(c) == quote => quote = None,
+            '(' | '[' if quote.is_none() => depth += 1,
+            ')' | ']' if quote.is_none() => depth -= 1,
+            ',' if quote.is_none() && depth == 0 => {
+                if operand == index {
+                    let text = &rest[operand_start..i];
+                    let leading = text.len() - text.trim_start().len();
+                    let trimmed = text.trim();
+                    if trimmed.is_empty() {
+                        return None;
+                    }
+                    let first = start + operand_start + leading + 1;
+                    return Some((first as u32, (first + trimmed.len()) as u32));
+                }
+                operand += 1;
+                operand_start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(file: &str, line: u32) -> SourceLocation {
+        SourceLocation {
+            file: file.to_string(),
+            line,
+        }
+    }
+
+    #[test]
+    fn json_escapes_messages_and_paths() {
+        let diagnostic = Diagnostic::error(codes::INCLUDE, "cannot read \"a\\b\"\n\tnow\u{1}")
+            .at(location(r"C:\src\main.asm", 3))
+            .with_note("included from here", Some(location("say \"hi\".asm", 1)))
+            .with_help("check the path")
+            .with_suggestion("did you mean", "\\d");
+        assert_eq!(
+            diagnostic.to_json(),
+            concat!(
+                r#"{"severity":"error","code":"E0011","message":"cannot read \"a\\b\"\n\tnow\u0001","#,
+                r#""file":"C:\\src\\main.asm","line":3,"column_start":null,"column_end":null,"source":null,"#,
+                r#""notes":[{"kind":"note","message":"included from here","file":"say \"hi\".asm","line":1},"#,
+                r#"{"kind":"help","message":"check the path","file":null,"line":null}],"#,
+                r#""suggestions":[{"message":"did you mean","replacement":"\\d"}]}"#
+            )
+        );
+
+        let bare = Diagnostic::warning(codes::NO_HLT, "no hlt");
+        assert_eq!(
+            bare.to_json(),
+            concat!(
+                r#"{"severity":"warning","code":"W0001","message":"no hlt","file":null,"line":null,"#,
+                r#""column_start":null,"column_end":null,"source":null,"notes":[],"suggestions":[]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn diagnostics_are_sorted_by_file_and_line() {
+        let files = SourceFiles::memory([("lib.asm", "hlt\nbad r0\n")]);
+        let assembly = Assembler::with_files(AssemblerOptions::default(), files)
+            .assemble_source("main.asm", "nope\n#include \"lib.asm\"\nmov r0, #999\n");
+        let sorted = prepare_diagnostics(assembly.diagnostics, false);
+        let places: Vec<_> = sorted
+            .iter()
+            .map(|d| d.source_location().unwrap().to_string())
+            .collect();
+        assert_eq!(places, ["main.asm:1", "lib.asm:2", "main.asm:3"]);
+        assert!(sorted
+            .iter()
+            .all(|d| d.notes.iter().all(|n| !matches!(n, Note::Help(_)))));
+    }
+
+    #[test]
+    fn errors_come_before_warnings_on_a_line() {
+        let diagnostics = vec![
+            Diagnostic::warning(codes::NO_HLT, "b").on_line(2),
+            Diagnostic::error(codes::GENERAL, "c").on_line(2),
+            Diagnostic::error(codes::GENERAL, "a").on_line(1),
+            Diagnostic::error(codes::GENERAL, "a").on_line(1),
+            Diagnostic::warning(codes::NO_HLT, "d").on_line(3),
+        ];
+        let sorted = prepare_diagnostics(diagnostics, true);
+        let messages: Vec<_> = sorted.iter().map(|d| &*d.message).collect();
+        assert_eq!(messages, ["a", "c", "b", "d"]);
+        assert_eq!(sorted.iter().filter(|d| d.is_error()).count(), 2);
+        assert_eq!(sorted.iter().filter(|d| !d.is_error()).count(), 2);
+
+        let warnings = vec![Diagnostic::warning(codes::NO_HLT, "no hlt")];
+        assert!(!emit_diagnostics(warnings, MessageFormat::Json, false));
+    }
+}
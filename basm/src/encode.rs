@@ -1,35 +1,40 @@
 use crate::*;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Mutex;
 
-pub static SUBROUTINE_MAP: Lazy<Mutex<HashMap<String, u32>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-
-pub static MEMORY_COUNTER: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
-static START_LOCATION: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
-
-pub fn argument_to_binary(arg: Option<&Token>, line_num: u32) -> Result<i16, String> {
+pub fn argument_to_binary(arg: Option<&Token>, table: &SymbolTable) -> Result<i16, Diagnostic> {
     match arg {
         Some(Token::Register(num)) => {
             if *num > 7 {
-                return Err(format!(
-                    "Register number cannot be greater than 7 at line {}",
-                    line_num
-                ));
+                return Err(Diagnostic::error(
+                    codes::OUT_OF_RANGE,
+                    format!("register r{} does not exist", num),
+                )
+                .with_help("registers are numbered r0 to r7"));
             }
             Ok(*num)
         }
-        Some(Token::Literal(literal)) => Ok((1 << 8) | *literal),
+        Some(Token::Literal(literal)) => {
+            if !(-127..=127).contains(literal) {
+                return Err(Diagnostic::error(
+                    codes::OUT_OF_RANGE,
+                    format!("literal {} does not fit in a signed 7 bit field", literal),
+                )
+                .with_help(
+                    "literals range from -127 to 127, larger values can be loaded from a .word",
+                ));
+            }
+            Ok((1 << 8) | literal_bits(*literal))
+        }
         Some(Token::SR(sr) | Token::SRCall(sr)) => {
-            let map = SUBROUTINE_MAP.lock().unwrap();
-            if let Some(&address) = map.get(sr) {
-                Ok(address as i16)
+            if let Some(symbol) = table.get(sr) {
+                Ok(symbol.address as i16)
             } else {
-                Err(format!(
-                    "Subroutine \"{}\" does not exist at line {}",
-                    sr, line_num
-                ))
+                let mut diagnostic =
+                    Diagnostic::error(codes::UNDEFINED_LABEL, format!("undefined label {}", sr))
+                        .with_span(Span::Text(sr.to_string()));
+                if let Some(closest) = table.closest(sr) {
+                    diagnostic = diagnostic.with_suggestion("did you mean", closest);
+                }
+                Err(diagnostic)
             }
         }
         Some(Token::MemAddr(n)) => Ok(*n),
@@ -38,10 +43,16 @@ pub fn argument_to_binary(arg: Option<&Token>, line_num: u32) -> Result<i16, Str
                 "start" => 1,
                 "ssp" => 2,
                 "sbp" => 3,
+                d if is_data_directive(d) => {
+                    return Err(Diagnostic::error(
+                        codes::OPERANDS,
+                        format!("data directive .{} cannot be used as an argument", d),
+                    ))
+                }
                 _ => {
-                    return Err(format!(
-                        "Label not recognized after '.' at line {}",
-                        line_num
+                    return Err(Diagnostic::error(
+                        codes::INVALID_SYNTAX,
+                        format!("unknown directive .{}", keyword),
                     ))
                 }
             };
@@ -53,13 +64,157 @@ pub fn argument_to_binary(arg: Option<&Token>, line_num: u32) -> Result<i16, Str
     }
 }
 
+/// Packs a literal into the 8 bit sign-magnitude form instructions use
+#[must_use]
+pub fn literal_bits(value: i16) -> i16 {
+    let stored = if value < 0 {
+        (value.unsigned_abs() as u8 & 0x7F) | 0x80
+    } else {
+        value as u8
+    };
+    i16::from(stored)
+}
+
+/// Directives that place words in the program instead of configuring the CPU
+#[must_use]
+pub fn is_data_directive(directive: &str) -> bool {
+    matches!(directive, "word" | "ascii" | "asciiz" | "fill" | "org")
+}
+
+/// The operands of a directive line, without the commas between them
+#[must_use]
+pub fn data_operands(tokens: &[Token]) -> Vec<&Token> {
+    tokens
+        .iter()
+        .skip(1)
+        .filter(|tok| **tok != Token::Comma)
+        .collect()
+}
+
+/// Encodes a data directive placed at `address` into the words it emits.
+///
+/// `.word` takes any number of values or labels, `.ascii`/`.asciiz` store a
+/// string one character per word (`.asciiz` adds a terminating 0), `.fill`
+/// repeats a value (0 by default) and `.org` pads with zeros up to an address.
+///
+/// Values that a `raw` image loader would read as a CPU directive are rejected.
+pub fn encode_data(
+    tokens: &[Token],
+    address: u32,
+    table: &SymbolTable,
+    raw: bool,
+) -> Result<Vec<i16>, Diagnostic> {
+    let Some(Token::Label(directive)) = tokens.first() else {
+        return Ok(Vec::new());
+    };
+    let operands = data_operands(tokens);
+    let words = match directive.as_str() {
+        "word" => {
+            if operands.is_empty() {
+                return Err(Diagnostic::error(
+                    codes::EXPECTED_ARGUMENT,
+                    ".word requires a value",
+                ));
+            }
+            operands
+                .iter()
+                .enumerate()
+                .map(|(index, op)| data_value(op, index, table))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        "ascii" | "asciiz" => {
+            let [Token::Str(string)] = operands[..] else {
+                return Err(Diagnostic::error(
+                    codes::OPERANDS,
+                    format!(".{} requires a single string", directive),
+                ));
+            };
+            let mut words: Vec<i16> = string.chars().map(|c| c as i16).collect();
+            if directive == "asciiz" {
+                words.push(0);
+            }
+            words
+        }
+        "fill" => {
+            let count = match operands.first() {
+                Some(Token::Literal(n)) if *n >= 0 => *n as usize,
+                _ => {
+                    return Err(Diagnostic::error(
+                        codes::OPERANDS,
+                        ".fill requires a positive count",
+                    )
+                    .with_span(Span::Operand(0)))
+                }
+            };
+            let value = match operands.get(1) {
+                Some(op) => data_value(op, 1, table)?,
+                None => 0,
+            };
+            vec![value; count]
+        }
+        "org" => {
+            let target = org_target(tokens).ok_or_else(|| {
+                Diagnostic::error(codes::EXPECTED_ARGUMENT, ".org requires an address")
+            })?;
+            if target < address {
+                return Err(Diagnostic::error(
+                    codes::OUT_OF_RANGE,
+                    format!(".org cannot move back from {} to {}", address, target),
+                )
+                .with_span(Span::Operand(0)));
+            }
+            vec![0; (target - address) as usize]
+        }
+        _ => return Ok(Vec::new()),
+    };
+
+    // the raw image loader reads words with opcode 0 and bits 9-11 set as .start/.ssp/.sbp
+    if let Some(word) = words.iter().find(|w| raw && (1..=3).contains(&(**w >> 9))) {
+        return Err(Diagnostic::error(
+            codes::OUT_OF_RANGE,
+            format!("data value {} would be read as a CPU directive", word),
+        )
+        .with_help("raw images cannot hold this value, leave out --raw to write an executable"));
+    }
+    Ok(words)
+}
+
+/// The address a `.org` line moves the location counter to
+#[must_use]
+pub fn org_target(tokens: &[Token]) -> Option<u32> {
+    match data_operands(tokens).first() {
+        Some(Token::Literal(n) | Token::MemAddr(n)) if *n >= 0 => Some(*n as u32),
+        _ => None,
+    }
+}
+
+fn data_value(arg: &Token, operand: usize, table: &SymbolTable) -> Result<i16, Diagnostic> {
+    match arg {
+        Token::Literal(value) | Token::MemAddr(value) => Ok(*value),
+        Token::SRCall(_) => argument_to_binary(Some(arg), table),
+        _ => Err(Diagnostic::error(
+            codes::OPERANDS,
+            "data values must be literals or labels",
+        )),
+    }
+    .map_err(|e| e.with_span(Span::Operand(operand)))
+}
+
 pub fn encode_instruction(
     ins: &Token,
     arg1: Option<&Token>,
     arg2: Option<&Token>,
-    line_num: u32,
-) -> Result<Option<i16>, String> {
+    table: &SymbolTable,
+) -> Result<Option<i16>, Diagnostic> {
     let mut ins_type = "default";
+    if let Token::Label(directive) = ins {
+        if directive == "global" || directive == "extern" || directive == "equ" {
+            return Ok(None); // only used by the symbol table
+        }
+        if is_data_directive(directive) {
+            return Ok(None); // emitted by encode_data
+        }
+    }
     let instruction_bin = match ins {
         Token::Ident(ref instruction) => match instruction.to_uppercase().as_str() {
             "SSP" => {
@@ -73,6 +228,7 @@ pub fn encode_instruction(
             "HLT" => Ok(HLT_OP), // 0
             "ADD" => Ok(ADD_OP), // 1
             "JO" => {
+                check_address(arg1, JUMP_ADDRESS_BITS, 0, table)?;
                 ins_type = "one_arg";
                 if let Some(&Token::SRCall(_)) = arg1.or(arg2) {
                     ins_type = "call";
@@ -85,10 +241,14 @@ pub fn encode_instruction(
                 ins_type = "one_arg";
                 Ok(POP_OP) // 3
             }
-            "DIV" => Ok(DIV_OP), // 4
+            "DIV" => Ok(DIV_OP),        // 4
             "RET" | "ET" => Ok(RET_OP), // 5
-            "LD" => Ok(LD_OP),   // 6
+            "LD" => {
+                check_address(arg2, LD_ADDRESS_BITS, 1, table)?;
+                Ok(LD_OP) // 6
+            }
             "ST" => {
+                check_address(arg1, ST_ADDRESS_BITS, 0, table)?;
                 if let Some(&Token::RegPointer(_)) = arg1.or(arg2) {
                     ins_type = "sti";
                 } else {
@@ -97,6 +257,7 @@ pub fn encode_instruction(
                 Ok(ST_OP) // 7
             }
             "JMP" => {
+                check_address(arg1, JUMP_ADDRESS_BITS, 0, table)?;
                 ins_type = "one_arg";
                 if let Some(&Token::SRCall(_)) = arg1.or(arg2) {
                     ins_type = "call";
@@ -106,6 +267,7 @@ pub fn encode_instruction(
                 Ok(JMP_OP)
             }
             "JZ" => {
+                check_address(arg1, JUMP_ADDRESS_BITS, 0, table)?;
                 ins_type = "one_arg";
                 if let Some(&Token::SRCall(_)) = arg1.or(arg2) {
                     ins_type = "call";
@@ -126,7 +288,17 @@ pub fn encode_instruction(
             }
             "NOP" => Ok(NOP_OP),
             "MOV" => Ok(MOV_OP), // 14
-            _ => Err(format!("Instruction not recognized at line {}", line_num)),
+            _ => {
+                let mut diagnostic = Diagnostic::error(
+                    codes::INVALID_SYNTAX,
+                    format!("unknown instruction `{}`", instruction),
+                )
+                .with_span(Span::Text(instruction.to_string()));
+                if let Some(closest) = closest_name(instruction, MNEMONICS.iter().copied()) {
+                    diagnostic = diagnostic.with_suggestion("did you mean", closest);
+                }
+                Err(diagnostic)
+            }
         },
         Token::SR(_) => {
             ins_type = "subr";
@@ -136,76 +308,131 @@ pub fn encode_instruction(
             ins_type = "label";
             Ok(HLT_OP)
         }
-        _ => Err(format!("Invalid instruction type at line {}", line_num)),
+        _ => Err(Diagnostic::error(
+            codes::INVALID_SYNTAX,
+            "expected an instruction, label or directive",
+        )),
     }?;
 
     match ins_type.trim().to_lowercase().as_str() {
         "one_arg" => {
-            let arg_bin = argument_to_binary(arg1, line_num)?;
+            let arg_bin = operand_to_binary(arg1, 0, table)?;
             Ok(Some((instruction_bin << 12) | arg_bin))
         }
         "st" => {
-            let arg1_bin = argument_to_binary(arg1, line_num)?;
-            let arg2_bin = argument_to_binary(arg2, line_num)?;
+            let arg1_bin = operand_to_binary(arg1, 0, table)?;
+            let arg2_bin = operand_to_binary(arg2, 1, table)?;
             Ok(Some((instruction_bin << 12) | (arg1_bin << 3) | arg2_bin))
         }
         "sti" => {
             let raw = arg1
-                .ok_or_else(|| format!("Missing argument for STI at line {}", line_num))?
+                .ok_or_else(|| {
+                    Diagnostic::error(codes::EXPECTED_ARGUMENT, "missing argument for STI")
+                })?
                 .get_raw();
-            let parsed_int = raw
-                .trim()
-                .parse::<i16>()
-                .map_err(|_| format!("Failed to parse integer at line {}", line_num))?;
+            let parsed_int = raw.trim().parse::<i16>().map_err(|_| {
+                Diagnostic::error(codes::INVALID_SYNTAX, "expected a register number")
+                    .with_span(Span::Operand(0))
+            })?;
             Ok(Some(
                 (instruction_bin << 12)
                     | (1 << 11)
-                    | (argument_to_binary(Some(&Token::Register(parsed_int)), line_num)? << 7)
-                    | argument_to_binary(arg2, line_num)?,
+                    | (operand_to_binary(Some(&Token::Register(parsed_int)), 0, table)? << 7)
+                    | operand_to_binary(arg2, 1, table)?,
             ))
         }
         "label" => {
-            let arg_bin = argument_to_binary(Some(ins), line_num)?;
+            let arg_bin = argument_to_binary(Some(ins), table)?;
             Ok(Some(
-                (instruction_bin << 12) | (arg_bin << 9) | argument_to_binary(arg1, line_num)?,
+                (instruction_bin << 12) | (arg_bin << 9) | operand_to_binary(arg1, 0, table)?,
             ))
         }
         "default" => {
-            let arg1_bin = argument_to_binary(arg1, line_num)?;
-            let arg2_bin = argument_to_binary(arg2, line_num)?;
+            let arg1_bin = operand_to_binary(arg1, 0, table)?;
+            let arg2_bin = operand_to_binary(arg2, 1, table)?;
             Ok(Some((instruction_bin << 12) | (arg1_bin << 9) | arg2_bin))
         }
         "call" => {
-            let address = argument_to_binary(arg1, line_num)?;
+            let address = operand_to_binary(arg1, 0, table)?;
             Ok(Some((instruction_bin << 12) | address))
         }
         "jwr" => {
             let raw_str = arg1
-                .ok_or_else(|| format!("Missing argument for JWR at line {}", line_num))?
+                .ok_or_else(|| {
+                    Diagnostic::error(codes::EXPECTED_ARGUMENT, "missing argument for JWR")
+                })?
                 .get_raw();
-            let parsed_int = raw_str
-                .trim()
-                .parse::<i16>()
-                .map_err(|_| format!("Failed to parse integer for JWR at line {}", line_num))?;
+            let parsed_int = raw_str.trim().parse::<i16>().map_err(|_| {
+                Diagnostic::error(codes::INVALID_SYNTAX, "expected a register number")
+                    .with_span(Span::Operand(0))
+            })?;
             Ok(Some(
                 (instruction_bin << 12)
                     | 1 << 11
-                    | argument_to_binary(Some(&Token::Register(parsed_int)), line_num)?,
+                    | operand_to_binary(Some(&Token::Register(parsed_int)), 0, table)?,
             ))
         }
         "sp" => {
             Ok(Some((instruction_bin << 11) | arg1.unwrap().get_num())) // this was verified in verify.rs
                                                                         // unwrapping is safe
         }
-        _ => Err(format!(
-            "Instruction type not recognized at line {}",
-            line_num
+        _ => Err(Diagnostic::error(
+            codes::GENERAL,
+            "instruction type not recognized",
         )),
     }
 }
 
-pub fn process_start(lines: &[String]) -> Result<(), String> {
-    let mut start_number: Option<i32> = None;
+const LD_ADDRESS_BITS: u32 = 9;
+// the bit above a store's address says whether it goes through a register
+const ST_ADDRESS_BITS: u32 = 8;
+const JUMP_ADDRESS_BITS: u32 = 12;
+
+/// Checks that a memory address or label fits in an instruction's address field
+fn check_address(
+    arg: Option<&Token>,
+    bits: u32,
+    operand: usize,
+    table: &SymbolTable,
+) -> Result<(), Diagnostic> {
+    let address = match arg {
+        Some(Token::MemAddr(n)) => *n,
+        Some(Token::SRCall(_)) => operand_to_binary(arg, operand, table)?,
+        _ => return Ok(()),
+    };
+    if !(0..1 << bits).contains(&address) {
+        return Err(Diagnostic::error(
+            codes::OUT_OF_RANGE,
+            format!("address {} does not fit in {} bits", address, bits),
+        )
+        .with_span(Span::Operand(operand))
+        .with_help(format!(
+            "this instruction can reach addresses 0 to {}",
+            (1 << bits) - 1
+        )));
+    }
+    Ok(())
+}
+
+/// `argument_to_binary` for the operand at `index`, pointing errors at it
+fn operand_to_binary(
+    arg: Option<&Token>,
+    index: usize,
+    table: &SymbolTable,
+) -> Result<i16, Diagnostic> {
+    argument_to_binary(arg, table).map_err(|e| e.with_span(Span::Operand(index)))
+}
+
+/// Every instruction mnemonic, for suggesting one when an unknown name is used
+const MNEMONICS: [&str; 19] = [
+    "hlt", "add", "jo", "pop", "div", "ret", "et", "ld", "st", "jmp", "jz", "cmp", "mul", "push",
+    "int", "nop", "mov", "ssp", "sbp",
+];
+
+/// The address given with `.start`, if the program has one
+#[must_use]
+pub fn start_location(lines: &[String]) -> Option<u32> {
+    let mut start_number: Option<u32> = None;
 
     for line in lines {
         let trimmed_line = line.trim();
@@ -224,63 +451,87 @@ pub fn process_start(lines: &[String]) -> Result<(), String> {
             start_number = line_before_comment.split_whitespace().nth(1).and_then(|s| {
                 let stripped = s.strip_prefix('$').unwrap_or(s);
                 if stripped.starts_with('[') && stripped.ends_with(']') {
-                    stripped[1..stripped.len() - 1].parse::<i32>().ok()
+                    stripped[1..stripped.len() - 1].parse::<u32>().ok()
                 } else {
-                    stripped.parse::<i32>().ok()
+                    stripped.parse::<u32>().ok()
                 }
             });
         }
     }
 
-    if let Some(num) = start_number {
-        let mut start_location = START_LOCATION
-            .lock()
-            .map_err(|_| "Failed to lock START_LOCATION")?;
-        *start_location = num;
-    }
-
-    Ok(())
+    start_number
 }
 
-pub fn load_subroutines(lines: &[String]) -> Result<(), String> {
-    let mut subroutine_counter = *START_LOCATION
-        .lock()
-        .map_err(|_| "Failed to lock START_LOCATION")? as u32;
-    let mut subroutine_map = SUBROUTINE_MAP
-        .lock()
-        .map_err(|_| "Failed to lock SUBROUTINE_MAP")?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for line in lines {
-        let trimmed_line = line.trim();
+    fn data(line: &str, address: u32) -> Result<Vec<i16>, Diagnostic> {
+        let mut table = SymbolTable::default();
+        table.define("here", 120, 1).unwrap();
+        let mut lexer = Lexer::new(line, 1);
+        let tokens = lexer.lex().unwrap();
+        encode_data(tokens, address, &table, false)
+    }
 
-        if trimmed_line.is_empty() || trimmed_line.starts_with(';') || trimmed_line.starts_with('.')
-        {
-            continue;
-        }
-        let line_before_comment = if trimmed_line.contains(';') {
-            trimmed_line.split(';').next().unwrap_or(trimmed_line)
-        } else {
-            trimmed_line
-        };
-        if line_before_comment.trim().ends_with(':') && !line_before_comment.trim().contains(' ') {
-            let subroutine_name = line_before_comment
-                .trim()
-                .trim_end_matches(':')
-                .trim()
-                .to_string();
-            subroutine_map.insert(subroutine_name.trim().to_string(), subroutine_counter);
-            subroutine_counter -= 1;
-        }
+    fn assemble(text: &str) -> Assembly {
+        Assembler::with_files(
+            AssemblerOptions::default(),
+            SourceFiles::memory::<&str, &str>([]),
+        )
+        .assemble_source("main.asm", text)
+    }
 
-        subroutine_counter += 1;
+    #[test]
+    fn data_directive_sizes_and_values() {
+        assert_eq!(
+            data(".word 1, #-2, 300, @here", 100).unwrap(),
+            [1, -2, 300, 120]
+        );
+        assert_eq!(data(".ascii \"hi\"", 100).unwrap(), [104, 105]);
+        assert_eq!(data(".asciiz \"a\\n\"", 100).unwrap(), [97, 10, 0]);
+        assert_eq!(data(".fill 3", 100).unwrap(), [0, 0, 0]);
+        assert_eq!(data(".fill 2, 7", 100).unwrap(), [7, 7]);
+        assert_eq!(data(".fill 0", 100).unwrap(), []);
+        assert_eq!(data(".org 104", 100).unwrap(), [0; 4]);
+        assert_eq!(data(".org 100", 100).unwrap(), []);
     }
 
-    Ok(())
-}
-pub fn update_memory_counter() -> Result<(), String> {
-    let mut counter = MEMORY_COUNTER
-        .lock()
-        .map_err(|_| "Failed to lock MEMORY_COUNTER")?;
-    *counter += 1;
-    Ok(())
+    #[test]
+    fn bad_data_directives() {
+        let message = |line| data(line, 100).unwrap_err().message.to_string();
+        assert_eq!(message(".word"), ".word requires a value");
+        assert_eq!(message(".ascii 5"), ".ascii requires a single string");
+        assert_eq!(message(".fill #-1"), ".fill requires a positive count");
+        assert_eq!(message(".org 99"), ".org cannot move back from 100 to 99");
+    }
+
+    #[test]
+    fn data_follows_code_in_its_own_segment() {
+        let assembly =
+            assemble(".start $100\nmov r0, #1\nhlt\ndata: .word 5, 6\n.org $106\n.word @data\n");
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        let segments: Vec<_> = assembly
+            .executable
+            .segments
+            .iter()
+            .map(|s| (s.kind, s.address, s.words.clone()))
+            .collect();
+        assert_eq!(
+            segments[1..],
+            [(SegmentKind::Data, 102, vec![5, 6, 0, 0, 102])]
+        );
+    }
+
+    #[test]
+    fn data_cannot_overlap_code() {
+        let assembly = assemble(".start $100\nmov r0, #1\nhlt\n.org $101\n.word 5\n");
+        let errors: Vec<_> = assembly
+            .diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| &*d.message)
+            .collect();
+        assert_eq!(errors, [".org cannot move the location counter backwards"]);
+    }
 }
@@ -0,0 +1,227 @@
+use crate::*;
+
+/// Executables written by `basm` and `bld`, read by `belle::bin_to_vec`.
+///
+/// Everything is big-endian and a whole number of 16 bit words:
+///
+/// ```text
+/// magic      "BIMG"
+/// version    u16
+/// flags      u16    bit 0: ssp present, bit 1: sbp present, bit 2: checksum present
+/// entry      u16    where execution begins
+/// ssp        u16
+/// sbp        u16
+/// checksum   u32    CRC-32 of the bytes of every segment's words, in order
+/// segments   u16 count, then per segment: u16 kind (0 text, 1 data), u16 load address, u16 word count
+/// words      the words of every segment, in order
+/// ```
+///
+/// Version 1 was the image `bld --headered` wrote, with one segment at the
+/// entry point and no checksum.
+pub const IMAGE_MAGIC: &[u8; 4] = b"BIMG";
+pub const IMAGE_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentKind {
+    Text = 0,
+    Data = 1,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub address: u16,
+    pub words: Vec<i16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Executable {
+    pub entry: u16,
+    pub ssp: Option<u16>,
+    pub sbp: Option<u16>,
+    pub segments: Vec<Segment>,
+    pub checksum: bool,
+}
+
+impl Default for Executable {
+    fn default() -> Self {
+        Executable {
+            entry: DEFAULT_START,
+            ssp: None,
+            sbp: None,
+            segments: Vec::new(),
+            checksum: true,
+        }
+    }
+}
+
+impl Executable {
+    /// Records a `.start`, `.ssp` or `.sbp` directive
+    pub fn set_directive(&mut self, directive: &str, value: i16) {
+        let value = value as u16;
+        match directive {
+            "start" => self.entry = value,
+            "ssp" => self.ssp = Some(value),
+            "sbp" => self.sbp = Some(value),
+            _ => (),
+        }
+    }
+
+    /// Places words at `address`, extending the last segment when they follow
+    /// on from it and are of the same kind, and starting a new one otherwise
+    pub fn push_words(&mut self, address: u32, words: &[i16], kind: SegmentKind) {
+        if words.is_empty() {
+            return;
+        }
+        match self.segments.last_mut() {
+            Some(last)
+                if last.kind == kind
+                    && u32::from(last.address) + last.words.len() as u32 == address =>
+            {
+                last.words.extend(words);
+            }
+            _ => self.segments.push(Segment {
+                kind,
+                address: address as u16,
+                words: words.to_vec(),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let words: Vec<u8> = self
+            .segments
+            .iter()
+            .flat_map(|segment| &segment.words)
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        let flags = u16::from(self.ssp.is_some())
+            | u16::from(self.sbp.is_some()) << 1
+            | u16::from(self.checksum) << 2;
+        let checksum = if self.checksum { crc32(&words) } else { 0 };
+
+        let mut bytes = IMAGE_MAGIC.to_vec();
+        for value in [
+            IMAGE_VERSION,
+            flags,
+            self.entry,
+            self.ssp.unwrap_or(0),
+            self.sbp.unwrap_or(0),
+        ] {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes.extend(checksum.to_be_bytes());
+        bytes.extend((self.segments.len() as u16).to_be_bytes());
+        for segment in &self.segments {
+            for value in [
+                segment.kind as u16,
+                segment.address,
+                segment.words.len() as u16,
+            ] {
+                bytes.extend(value.to_be_bytes());
+            }
+        }
+        bytes.extend(words);
+        bytes
+    }
+}
+
+/// The CRC-32 (as used by zip and PNG) of `bytes`
+#[must_use]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(bytes: &[u8]) -> Vec<u16> {
+        bytes
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn crc32_known_answer() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn words_that_follow_on_share_a_segment() {
+        let mut executable = Executable::default();
+        executable.push_words(100, &[1, 2], SegmentKind::Text);
+        executable.push_words(102, &[3], SegmentKind::Text);
+        executable.push_words(103, &[4], SegmentKind::Data);
+        executable.push_words(110, &[5], SegmentKind::Data);
+        executable.push_words(111, &[], SegmentKind::Text);
+        let layout: Vec<_> = executable
+            .segments
+            .iter()
+            .map(|s| (s.kind, s.address, s.words.len()))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (SegmentKind::Text, 100, 3),
+                (SegmentKind::Data, 103, 1),
+                (SegmentKind::Data, 110, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn header_layout() {
+        let mut executable = Executable {
+            entry: 200,
+            sbp: Some(7),
+            ..Executable::default()
+        };
+        executable.push_words(200, &[0x1234, -1], SegmentKind::Text);
+        executable.push_words(300, &[5], SegmentKind::Data);
+        let bytes = executable.to_bytes();
+        let checksum = crc32(&[0x12, 0x34, 0xFF, 0xFF, 0x00, 0x05]);
+        assert_eq!(&bytes[..4], IMAGE_MAGIC);
+        assert_eq!(
+            words(&bytes[4..]),
+            [
+                IMAGE_VERSION,
+                0b110,
+                200,
+                0,
+                7,
+                (checksum >> 16) as u16,
+                checksum as u16,
+                2,
+                0,
+                200,
+                2,
+                1,
+                300,
+                1,
+                0x1234,
+                0xFFFF,
+                5
+            ]
+        );
+
+        executable.checksum = false;
+        let bytes = executable.to_bytes();
+        assert_eq!(words(&bytes[4..12]), [IMAGE_VERSION, 0b010, 200, 0]);
+        assert_eq!(words(&bytes[14..18]), [0, 0]);
+    }
+}
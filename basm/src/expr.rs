@@ -0,0 +1,356 @@
+use std::fmt;
+
+/// An operand expression, such as `(WIDTH*2+1)` or `loop+2`, kept unevaluated
+/// until the symbol table knows every name it refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i32),
+    Symbol(String),
+    Unary(Op, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Rem => "%",
+            Op::Shl => "<<",
+            Op::Shr => ">>",
+            Op::And => "&",
+            Op::Or => "|",
+            Op::Not => "~",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+/// Binary operators from loosest to tightest binding, as in C except that
+/// comparisons bind looser than `&` and `|`
+const PRECEDENCE: &[&[Op]] = &[
+    &[Op::Eq, Op::Ne, Op::Le, Op::Ge, Op::Lt, Op::Gt],
+    &[Op::Or],
+    &[Op::And],
+    &[Op::Shl, Op::Shr],
+    &[Op::Add, Op::Sub],
+    &[Op::Mul, Op::Div, Op::Rem],
+];
+
+impl Expr {
+    /// Parses an expression made of numbers (decimal, `0x` or `0b`), character
+    /// literals, names (optionally written `@name`), the operators
+    /// `+ - * / % << >> & | ~`, the comparisons `== != < <= > >=`, which
+    /// give 1 or 0, and `()` or `[]` for grouping.
+    pub fn parse(text: &str) -> Result<Expr, &'static str> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let expr = parser.binary(0)?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err("unexpected character in expression");
+        }
+        Ok(expr)
+    }
+
+    /// Every name the expression refers to
+    #[must_use]
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Symbol(name) => vec![name.as_str()],
+            Expr::Unary(_, expr) => expr.symbols(),
+            Expr::Binary(_, lhs, rhs) => {
+                let mut symbols = lhs.symbols();
+                symbols.extend(rhs.symbols());
+                symbols
+            }
+        }
+    }
+
+    /// Folds the expression, looking names up with `lookup`
+    pub fn eval(&self, lookup: &dyn Fn(&str) -> Option<i32>) -> Result<i32, String> {
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Symbol(name) => lookup(name).ok_or_else(|| format!("unknown name {name}")),
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(lookup)?;
+                match op {
+                    Op::Sub => value.checked_neg().ok_or_else(overflow),
+                    Op::Not => Ok(!value),
+                    _ => Ok(value),
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(lookup)?, rhs.eval(lookup)?);
+                match op {
+                    Op::Add => lhs.checked_add(rhs).ok_or_else(overflow),
+                    Op::Sub => lhs.checked_sub(rhs).ok_or_else(overflow),
+                    Op::Mul => lhs.checked_mul(rhs).ok_or_else(overflow),
+                    Op::Div | Op::Rem if rhs == 0 => Err("division by zero".to_string()),
+                    Op::Div => lhs.checked_div(rhs).ok_or_else(overflow),
+                    Op::Rem => lhs.checked_rem(rhs).ok_or_else(overflow),
+                    Op::Shl | Op::Shr if !(0..32).contains(&rhs) => {
+                        Err(format!("cannot shift by {rhs}"))
+                    }
+                    Op::Shl => Ok(lhs << rhs),
+                    Op::Shr => Ok(lhs >> rhs),
+                    Op::And => Ok(lhs & rhs),
+                    Op::Or => Ok(lhs | rhs),
+                    Op::Eq => Ok(i32::from(lhs == rhs)),
+                    Op::Ne => Ok(i32::from(lhs != rhs)),
+                    Op::Lt => Ok(i32::from(lhs < rhs)),
+                    Op::Le => Ok(i32::from(lhs <= rhs)),
+                    Op::Gt => Ok(i32::from(lhs > rhs)),
+                    Op::Ge => Ok(i32::from(lhs >= rhs)),
+                    Op::Not => Ok(lhs),
+                }
+            }
+        }
+    }
+}
+
+fn overflow() -> String {
+    "arithmetic overflow".to_string()
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{n}"),
+            Expr::Symbol(name) => write!(f, "{name}"),
+            Expr::Unary(op, expr) => write!(f, "{}{expr}", op.symbol()),
+            Expr::Binary(op, lhs, rhs) => write!(f, "({lhs}{}{rhs})", op.symbol()),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Consumes the operator at the cursor if it is one of `ops`
+    fn operator(&mut self, ops: &[Op]) -> Option<Op> {
+        self.skip_whitespace();
+        let rest: String = self.chars[self.pos..].iter().take(2).collect();
+        let op = ops.iter().find(|op| rest.starts_with(op.symbol()))?;
+        self.pos += op.symbol().len();
+        Some(*op)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, &'static str> {
+        let Some(ops) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.operator(ops) {
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, &'static str> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Expr::Unary(Op::Sub, Box::new(self.unary()?)))
+            }
+            Some('~') => {
+                self.pos += 1;
+                Ok(Expr::Unary(Op::Not, Box::new(self.unary()?)))
+            }
+            Some('+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, &'static str> {
+        match self.peek() {
+            Some(open @ ('(' | '[')) => {
+                self.pos += 1;
+                let expr = self.binary(0)?;
+                let close = if open == '(' { ')' } else { ']' };
+                if self.peek() != Some(close) {
+                    return Err("unclosed parenthesis or bracket in expression");
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some('\'') => {
+                let (Some(&c), Some('\'')) =
+                    (self.chars.get(self.pos + 1), self.chars.get(self.pos + 2))
+                else {
+                    return Err("character literal must hold one character");
+                };
+                self.pos += 3;
+                Ok(Expr::Number(c as i32))
+            }
+            Some('0'..='9') => {
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_alphanumeric())
+                {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                let (digits, radix) = match text.get(..2) {
+                    Some("0x" | "0X") => (&text[2..], 16),
+                    Some("0b" | "0B") => (&text[2..], 2),
+                    _ => (text.as_str(), 10),
+                };
+                i32::from_str_radix(digits, radix)
+                    .map(Expr::Number)
+                    .map_err(|_| "invalid number in expression")
+            }
+            Some(c) if c == '@' || c == '_' || c.is_alphabetic() => {
+                if c == '@' {
+                    self.pos += 1;
+                }
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+                {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Err("expected a name after @");
+                }
+                Ok(Expr::Symbol(self.chars[start..self.pos].iter().collect()))
+            }
+            Some(_) => Err("expected a number, name or ( in expression"),
+            None => Err("expression ends too early"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<i32, String> {
+        Expr::parse(text)
+            .map_err(str::to_string)?
+            .eval(&|name| match name {
+                "WIDTH" => Some(80),
+                "loop" => Some(104),
+                _ => None,
+            })
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("[1 + 2] * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("6 & 3 | 8"), Ok(10));
+        assert_eq!(eval("1 | 2 == 3"), Ok(1));
+        assert_eq!(eval("2 < 3 == 1"), Ok(1));
+        assert_eq!(eval("-2 * -3"), Ok(6));
+        assert_eq!(eval("~0"), Ok(-1));
+        assert_eq!(eval("17 % 5 * 2"), Ok(4));
+    }
+
+    #[test]
+    fn numbers_and_names() {
+        assert_eq!(eval("0x10 + 0b11"), Ok(19));
+        assert_eq!(eval("'A' + 1"), Ok(66));
+        assert_eq!(eval("WIDTH*2+1"), Ok(161));
+        assert_eq!(eval("@loop + 2"), Ok(106));
+        assert_eq!(eval("missing"), Err("unknown name missing".to_string()));
+        assert_eq!(
+            Expr::parse("loop + WIDTH * loop").unwrap().symbols(),
+            ["loop", "WIDTH", "loop"]
+        );
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let overflow = Err("arithmetic overflow".to_string());
+        assert_eq!(eval("2147483647 + 1"), overflow);
+        assert_eq!(eval("-2147483647 - 2"), overflow);
+        assert_eq!(eval("65536 * 65536"), overflow);
+        assert_eq!(eval("-(-2147483647 - 1)"), overflow);
+        assert_eq!(eval("(-2147483647 - 1) / -1"), overflow);
+        assert_eq!(eval("1 / 0"), Err("division by zero".to_string()));
+        assert_eq!(eval("1 % 0"), Err("division by zero".to_string()));
+        assert_eq!(eval("1 << 32"), Err("cannot shift by 32".to_string()));
+        assert_eq!(eval("1 >> -1"), Err("cannot shift by -1".to_string()));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            Expr::parse("(1 + 2"),
+            Err("unclosed parenthesis or bracket in expression")
+        );
+        assert_eq!(Expr::parse("1 +"), Err("expression ends too early"));
+        assert_eq!(
+            Expr::parse("1 2"),
+            Err("unexpected character in expression")
+        );
+        assert_eq!(Expr::parse("0x"), Err("invalid number in expression"));
+        assert_eq!(Expr::parse("@"), Err("expected a name after @"));
+        assert_eq!(
+            Expr::parse("'ab'"),
+            Err("character literal must hold one character")
+        );
+    }
+
+    #[test]
+    fn display_shows_grouping() {
+        assert_eq!(Expr::parse("1 + 2 * x").unwrap().to_string(), "(1+(2*x))");
+        assert_eq!(Expr::parse("-(a - b)").unwrap().to_string(), "-(a-b)");
+    }
+}
@@ -0,0 +1,383 @@
+use crate::*;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Where every line of a program came from, and the text of every file it was read from
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// The file given to the assembler
+    pub main_file: String,
+    /// Where each line of the flattened program came from, indexed by its line number - 1
+    locations: Vec<SourceLocation>,
+    /// Where each line handed to the lexer came from, indexed by its line number - 1
+    origins: Vec<LineOrigin>,
+    files: HashMap<String, Vec<String>>,
+    /// The full path of each file, worked out once when it was read. Files
+    /// held in memory keep the path they were given, without `.` or `..`.
+    paths: HashMap<String, PathBuf>,
+}
+
+impl SourceMap {
+    /// The file and line a line of the flattened program was read from
+    #[must_use]
+    pub fn location(&self, line: u32) -> SourceLocation {
+        line.checked_sub(1)
+            .and_then(|index| self.locations.get(index as usize))
+            .cloned()
+            .unwrap_or(SourceLocation {
+                file: self.main_file.to_string(),
+                line,
+            })
+    }
+
+    /// The source text at a location, so it can be shown with an error
+    #[must_use]
+    pub fn text(&self, location: &SourceLocation) -> Option<String> {
+        let index = location.line.checked_sub(1)? as usize;
+        self.files.get(&location.file)?.get(index).cloned()
+    }
+
+    /// The full path of a file the program was read from
+    #[must_use]
+    pub fn path(&self, file: &str) -> Option<&Path> {
+        self.paths.get(file).map(PathBuf::as_path)
+    }
+
+    /// The origin of a lexed line, or the line itself when no macros were expanded
+    #[must_use]
+    pub fn origin(&self, line: u32) -> LineOrigin {
+        line.checked_sub(1)
+            .and_then(|index| self.origins.get(index as usize))
+            .cloned()
+            .unwrap_or(LineOrigin {
+                line,
+                expansions: Vec::new(),
+            })
+    }
+
+    /// The file and line to report a lexed line at, see [`LineOrigin::location`]
+    #[must_use]
+    pub fn origin_location(&self, line: u32) -> SourceLocation {
+        self.origin(line).location(self)
+    }
+
+    /// Records where each line left by [`expand_macros`] came from
+    pub fn set_origins(&mut self, origins: Vec<LineOrigin>) {
+        self.origins = origins;
+    }
+}
+
+/// Where the assembler reads source files from
+#[derive(Debug, Clone, Default)]
+pub enum SourceFiles {
+    /// The file system
+    #[default]
+    Disk,
+    /// Files held in memory, by path
+    Memory(HashMap<PathBuf, String>),
+}
+
+impl SourceFiles {
+    /// A set of files held in memory, so nothing is read from disk
+    pub fn memory<P: Into<PathBuf>, S: Into<String>>(
+        files: impl IntoIterator<Item = (P, S)>,
+    ) -> SourceFiles {
+        SourceFiles::Memory(
+            files
+                .into_iter()
+                .map(|(path, text)| (normalize(&path.into()), text.into()))
+                .collect(),
+        )
+    }
+
+    fn read(&self, path: &Path) -> io::Result<String> {
+        match self {
+            SourceFiles::Disk => fs::read_to_string(path),
+            SourceFiles::Memory(files) => files
+                .get(&normalize(path))
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match self {
+            SourceFiles::Disk => path.is_file(),
+            SourceFiles::Memory(files) => files.contains_key(&normalize(path)),
+        }
+    }
+
+    /// A name that is the same for every path to one file
+    fn canonical(&self, path: &Path) -> PathBuf {
+        match self {
+            SourceFiles::Disk => fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            SourceFiles::Memory(_) => normalize(path),
+        }
+    }
+}
+
+/// Removes `.` and `..` from a path without looking at the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir
+                if matches!(normal.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                normal.pop();
+            }
+            _ => normal.push(component),
+        }
+    }
+    normal
+}
+
+struct Includer<'a> {
+    files: &'a SourceFiles,
+    /// The text of the input file, when it was given instead of read
+    input: Option<(&'a Path, &'a str)>,
+    search: Vec<PathBuf>,
+    /// Files currently being included, outermost first
+    stack: Vec<(PathBuf, String)>,
+    /// Files marked with `#pragma once` that have already been included
+    once: HashSet<PathBuf>,
+    lines: Vec<String>,
+    map: SourceMap,
+}
+
+/// Reads the input file and every file it includes into one list of lines.
+///
+/// `#include "file"` is looked up relative to the directory of the file it
+/// appears in, then in each `-I` directory in order. Includes can be nested,
+/// a file that includes itself (directly or not) is an error, and a file
+/// containing `#pragma once` is only included the first time. The file and
+/// line of every line are recorded in the returned [`SourceMap`], which holds
+/// the files read so far even when there is an error.
+///
+/// `text` is the text of `input`, when it does not need to be read from `files`.
+pub fn process_includes(
+    input: &str,
+    text: Option<&str>,
+    files: &SourceFiles,
+    include_dirs: &[PathBuf],
+) -> (Result<Vec<String>, Diagnostic>, SourceMap) {
+    let mut includer = Includer {
+        files,
+        input: text.map(|text| (Path::new(input), text)),
+        search: include_dirs.to_vec(),
+        stack: Vec::new(),
+        once: HashSet::new(),
+        lines: Vec::new(),
+        map: SourceMap {
+            main_file: input.to_string(),
+            ..SourceMap::default()
+        },
+    };
+    let result = includer.include(Path::new(input), input);
+    (result.map(|()| includer.lines), includer.map)
+}
+
+impl Includer<'_> {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        match self.input {
+            Some((input, text)) if input == path => Ok(text.to_string()),
+            _ => self.files.read(path),
+        }
+    }
+
+    fn include(&mut self, path: &Path, name: &str) -> Result<(), Diagnostic> {
+        let key = self.files.canonical(path);
+        if self.once.contains(&key) {
+            return Ok(());
+        }
+        if self.stack.iter().any(|(open, _)| *open == key) {
+            let cycle: Vec<&str> = self
+                .stack
+                .iter()
+                .skip_while(|(open, _)| *open != key)
+                .map(|(_, name)| name.as_str())
+                .chain([name])
+                .collect();
+            return Err(Diagnostic::error(
+                codes::INCLUDE,
+                format!("include cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        let text = self
+            .read(path)
+            .map_err(|e| Diagnostic::error(codes::INCLUDE, format!("cannot read {name}: {e}")))?;
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        self.map.files.insert(name.to_string(), lines.clone());
+        self.map.paths.insert(name.to_string(), key.clone());
+
+        let include_regex = Regex::new(r#"^#include\s+"([^"]+)""#).unwrap();
+        self.stack.push((key.clone(), name.to_string()));
+        for (index, content) in lines.into_iter().enumerate() {
+            let location = SourceLocation {
+                file: name.to_string(),
+                line: index as u32 + 1,
+            };
+            let trimmed = content.trim();
+            if trimmed == "#pragma once" {
+                self.once.insert(key.clone());
+                continue;
+            }
+            if trimmed.starts_with("#include") {
+                let Some(captures) = include_regex.captures(trimmed) else {
+                    return Err(
+                        Diagnostic::error(codes::INCLUDE, "expected #include \"file\"")
+                            .at(location),
+                    );
+                };
+                let target = &captures[1];
+                let Some(found) = self.resolve(target, path) else {
+                    return Err(Diagnostic::error(
+                        codes::INCLUDE,
+                        format!("could not find included file \"{target}\""),
+                    )
+                    .at(location)
+                    .with_span(Span::Text(target.to_string()))
+                    .with_help("files are looked for next to the including file, then in each -I directory"));
+                };
+                let found_name = found.to_string_lossy().to_string();
+                self.include(&found, &found_name)
+                    .map_err(|e| e.with_note("included from here", Some(location.clone())))?;
+                continue;
+            }
+            self.lines.push(content);
+            self.map.locations.push(location);
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Looks for an included file next to the file including it, then in the `-I` directories
+    fn resolve(&self, target: &str, from: &Path) -> Option<PathBuf> {
+        let dir = from.parent().unwrap_or(Path::new("")).to_path_buf();
+        std::iter::once(dir)
+            .chain(self.search.iter().cloned())
+            .map(|dir| dir.join(target))
+            .find(|path| self.files.is_file(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn include(
+        files: &[(&str, &str)],
+        include_dirs: &[&str],
+    ) -> (Result<Vec<String>, Diagnostic>, SourceMap) {
+        let dirs: Vec<PathBuf> = include_dirs.iter().map(PathBuf::from).collect();
+        process_includes(
+            "main.asm",
+            None,
+            &SourceFiles::memory(files.iter().copied()),
+            &dirs,
+        )
+    }
+
+    #[test]
+    fn nested_includes_relative_to_the_including_file() {
+        let (lines, map) = include(
+            &[
+                ("main.asm", "#include \"lib/a.asm\"\nhlt"),
+                ("lib/a.asm", "#include \"b.asm\"\na: nop"),
+                ("lib/b.asm", "b: ret"),
+            ],
+            &[],
+        );
+        assert_eq!(lines.unwrap(), ["b: ret", "a: nop", "hlt"]);
+        assert_eq!(map.location(1).to_string(), "lib/b.asm:1");
+        assert_eq!(map.location(2).to_string(), "lib/a.asm:2");
+        assert_eq!(map.location(3).to_string(), "main.asm:2");
+        assert_eq!(map.text(&map.location(2)).as_deref(), Some("a: nop"));
+    }
+
+    #[test]
+    fn include_directories_are_searched_in_order() {
+        let (lines, _) = include(
+            &[
+                ("main.asm", "#include \"io.asm\""),
+                ("first/io.asm", "first: nop"),
+                ("second/io.asm", "second: nop"),
+            ],
+            &["first", "second"],
+        );
+        assert_eq!(lines.unwrap(), ["first: nop"]);
+
+        let (lines, _) = include(
+            &[
+                ("main.asm", "#include \"io.asm\""),
+                ("io.asm", "here: nop"),
+                ("first/io.asm", "first: nop"),
+            ],
+            &["first"],
+        );
+        assert_eq!(lines.unwrap(), ["here: nop"]);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let (lines, _) = include(
+            &[
+                ("main.asm", "#include \"a.asm\""),
+                ("a.asm", "#include \"b.asm\""),
+                ("b.asm", "#include \"a.asm\""),
+            ],
+            &[],
+        );
+        let error = lines.unwrap_err();
+        assert_eq!(error.code, codes::INCLUDE);
+        assert_eq!(&*error.message, "include cycle: a.asm -> b.asm -> a.asm");
+
+        let (lines, _) = include(&[("main.asm", "#include \"main.asm\"")], &[]);
+        assert!(lines.is_err());
+    }
+
+    #[test]
+    fn pragma_once_includes_a_file_once() {
+        let (lines, _) = include(
+            &[
+                (
+                    "main.asm",
+                    "#include \"a.asm\"\n#include \"a.asm\"\n#include \"b.asm\"",
+                ),
+                ("a.asm", "#pragma once\n#include \"b.asm\"\nonce: nop"),
+                ("b.asm", "twice: nop"),
+            ],
+            &[],
+        );
+        assert_eq!(lines.unwrap(), ["twice: nop", "once: nop", "twice: nop"]);
+    }
+
+    #[test]
+    fn missing_and_malformed_includes() {
+        let (lines, _) = include(&[("main.asm", "nop\n#include \"gone.asm\"")], &[]);
+        let error = lines.unwrap_err();
+        assert_eq!(&*error.message, "could not find included file \"gone.asm\"");
+        assert_eq!(error.snippet.unwrap().location.to_string(), "main.asm:2");
+
+        let (lines, _) = include(&[("main.asm", "#include gone.asm")], &[]);
+        assert_eq!(&*lines.unwrap_err().message, "expected #include \"file\"");
+    }
+}
@@ -42,11 +42,15 @@ impl<'a> Lexer<'a> {
                     self.location += 1;
                     self.lex_register(c)?;
                 }
+                '#' | '$' | '[' | '@' | '\'' | '0'..='9' if self.lex_expression(c)? => (),
                 '@' => self.lex_subroutine_call(),
-                'r' | 'R' => {
+                'r' | 'R' if self.chars.peek().is_some_and(char::is_ascii_digit) => {
                     self.lex_register(c)?;
                 }
-                'a'..='z' | 'A'..='Z' => {
+                'a'..='z' | 'A'..='Z' | '_' | '-' | '~' | '(' if self.in_data_directive() => {
+                    self.lex_expression(c)?;
+                }
+                'a'..='z' | 'A'..='Z' | '_' => {
                     self.lex_identifier(c)?;
                 }
                 '#' => {
@@ -57,11 +61,20 @@ impl<'a> Lexer<'a> {
                     self.location += 1;
                     self.lex_memory_address(c)?;
                 }
-                '.' => self.lex_label()?,
+                '.' => {
+                    self.lex_label()?;
+                    if self.tokens.last() == Some(&Token::Label("equ".to_string())) {
+                        self.lex_equ()?;
+                    }
+                }
                 '\'' => {
                     self.location += 1;
                     self.lex_ascii()?;
                 }
+                '"' => {
+                    self.location += 1;
+                    self.lex_string()?;
+                }
                 '0'..='9' => {
                     self.lex_literal(c)?;
                 }
@@ -121,6 +134,131 @@ impl<'a> Lexer<'a> {
         ))
     }
 
+    fn lex_string(&mut self) -> Result<(), Error<'a>> {
+        let mut string = String::new();
+
+        while let Some(next_char) = self.chars.next() {
+            self.location += 1;
+
+            match next_char {
+                '"' => {
+                    self.tokens.push(Token::Str(string));
+                    return Ok(());
+                }
+                '\\' => {
+                    self.location += 1;
+                    let escaped = match self.chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some(c @ ('\\' | '"' | '\'')) => c,
+                        _ => {
+                            return Err(InvalidSyntax(
+                                "unknown escape sequence in string",
+                                self.line_number,
+                                Some(self.location),
+                            ))
+                        }
+                    };
+                    string.push(escaped);
+                }
+                _ => string.push(next_char),
+            }
+        }
+        Err(InvalidSyntax(
+            "string is missing closing quote",
+            self.line_number,
+            Some(self.location),
+        ))
+    }
+
+    /// Lexes an operand such as `#(WIDTH*2)`, `$[BUF+3]`, `'a'+1` or `@loop+2`
+    /// as an expression, leaving plain numbers, addresses and labels to the
+    /// other lexers. Returns whether an expression was pushed.
+    fn lex_expression(&mut self, c: char) -> Result<bool, Error<'a>> {
+        let rest = self.operand_text(c == '\'');
+        let trimmed = rest.trim();
+        let is_number = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        let plain = match c {
+            '#' => is_number(trimmed.strip_prefix('-').unwrap_or(trimmed)),
+            '$' => is_number(trimmed),
+            '[' => trimmed.strip_suffix(']').is_some_and(is_number),
+            '@' => trimmed.chars().all(|c| c.is_alphanumeric() || c == '_'),
+            '\'' => trimmed
+                .split_once('\'')
+                .is_none_or(|(_, after)| after.trim().is_empty()),
+            '0'..='9' => is_number(trimmed),
+            _ => false,
+        };
+        if plain {
+            return Ok(false);
+        }
+
+        let (text, kind) = match c {
+            '#' => (rest.clone(), Operand::Literal),
+            '$' => (rest.clone(), Operand::MemAddr),
+            '[' | '@' => (format!("{c}{rest}"), Operand::MemAddr),
+            'a'..='z' | 'A'..='Z' | '_' => (format!("{c}{rest}"), Operand::Literal),
+            _ => (format!("{c}{rest}"), Operand::Literal),
+        };
+        let length = rest.chars().count();
+        self.chars.by_ref().take(length).for_each(drop);
+        self.location += length as u32;
+        let expr = Expr::parse(&text)
+            .map_err(|e| InvalidSyntax(e, self.line_number, Some(self.location)))?;
+        self.tokens.push(Token::Expression(kind, expr));
+        Ok(true)
+    }
+
+    /// Whether this line is a `.word`, `.fill` or `.org`, whose operands may
+    /// be bare expressions such as `WIDTH*2`
+    fn in_data_directive(&self) -> bool {
+        let directive = self.tokens.iter().find(|tok| !matches!(tok, Token::SR(_)));
+        matches!(directive, Some(Token::Label(d)) if matches!(d.as_str(), "word" | "fill" | "org"))
+    }
+
+    /// Lexes `NAME value` after `.equ`, where the value is an expression
+    fn lex_equ(&mut self) -> Result<(), Error<'a>> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {
+            self.location += 1;
+        }
+        match self.chars.next() {
+            Some(c) if c.is_alphabetic() || c == '_' => self.lex_identifier(c)?,
+            _ => {
+                return Err(ExpectedArgument(
+                    "expected a name after .equ",
+                    self.line_number,
+                    Some(self.location),
+                ))
+            }
+        }
+        let rest: String = self.chars.by_ref().collect();
+        let value = rest.split(';').next().unwrap_or_default();
+        let expr = Expr::parse(value)
+            .map_err(|e| InvalidSyntax(e, self.line_number, Some(self.location)))?;
+        self.tokens.push(Token::Expression(Operand::Literal, expr));
+        Ok(())
+    }
+
+    /// The rest of the current operand, up to a comma or comment outside of
+    /// quotes and brackets, without consuming it
+    fn operand_text(&self, in_quote: bool) -> String {
+        let mut text = String::new();
+        let mut in_quote = in_quote;
+        let mut depth = 0;
+        for c in self.chars.clone() {
+            match c {
+                '\'' => in_quote = !in_quote,
+                '(' | '[' if !in_quote => depth += 1,
+                ')' | ']' if !in_quote => depth -= 1,
+                ',' | ';' if !in_quote && depth <= 0 => break,
+                _ => (),
+            }
+            text.push(c);
+        }
+        text
+    }
+
     fn lex_pointer(&mut self, c: char) -> Result<(), Error<'a>> {
         let mut pointer = String::new();
         pointer.push(c);
@@ -230,11 +368,6 @@ impl<'a> Lexer<'a> {
 
     fn lex_register(&mut self, c: char) -> Result<(), Error<'a>> {
         let mut reg = String::new();
-
-        let remaining_chars: String = self.chars.clone().collect();
-        if remaining_chars.eq_ignore_ascii_case("ret") {
-            return Ok(());
-        }
         reg.push(c);
         if let Some(&next) = self.chars.peek() {
             if next == 'r' || next == 'R' {
@@ -302,6 +435,7 @@ impl<'a> Lexer<'a> {
         if let Some(&next) = self.chars.peek() {
             if next == ':' {
                 self.chars.next();
+                self.tokens.push(Token::SR(ident));
                 return Ok(());
             }
         }
@@ -344,13 +478,7 @@ impl<'a> Lexer<'a> {
                 Some(self.location),
             ));
         };
-        let stored_value = if num_value < 0 {
-            let positive_value = num_value.unsigned_abs() as u8;
-            (positive_value & 0x7F) | 0x80
-        } else {
-            num_value as u8
-        };
-        self.tokens.push(Token::Literal(i16::from(stored_value)));
+        self.tokens.push(Token::Literal(num_value));
         Ok(())
     }
 
@@ -421,11 +549,39 @@ impl<'a> Lexer<'a> {
     }
 }
 
-pub fn print_subroutine_map() {
-    let map = SUBROUTINE_MAP.lock().unwrap();
-    for (name, counter) in map.iter() {
-        if CONFIG.verbose | CONFIG.debug {
-            println!("Subroutine: {name}, Counter: {counter}");
-        }
+/// A lexed source line, kept around between the assembler's two passes
+#[derive(Debug, Clone)]
+pub struct LexedLine {
+    pub number: u32,
+    pub tokens: Vec<Token>,
+    /// Address of the first word this line emits, filled in by the first pass
+    pub address: u32,
+}
+
+impl LexedLine {
+    /// The tokens of this line with any leading label definitions removed
+    #[must_use]
+    pub fn instruction(&self) -> &[Token] {
+        &self.tokens[self.instruction_start()..]
+    }
+
+    pub fn instruction_mut(&mut self) -> &mut [Token] {
+        let labels = self.instruction_start();
+        &mut self.tokens[labels..]
+    }
+
+    fn instruction_start(&self) -> usize {
+        self.tokens
+            .iter()
+            .take_while(|tok| matches!(tok, Token::SR(_)))
+            .count()
+    }
+
+    /// The names of the labels defined on this line
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.tokens.iter().map_while(|tok| match tok {
+            Token::SR(name) => Some(name.as_str()),
+            _ => None,
+        })
     }
 }
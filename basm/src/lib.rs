@@ -1,10 +1,32 @@
-mod config;
+mod assembler;
+mod conditional;
 mod consts_enums;
+mod debug_info;
+mod diagnostic;
 mod encode;
+mod executable;
+mod expr;
+mod include;
 mod lexer;
+mod link;
+mod listing;
+mod macros;
+mod object;
+mod symbols;
 mod verify;
-pub use config::*;
+pub use assembler::*;
+pub use conditional::*;
 pub use consts_enums::*;
+pub use debug_info::*;
+pub use diagnostic::*;
 pub use encode::*;
+pub use executable::*;
+pub use expr::*;
+pub use include::*;
 pub use lexer::*;
+pub use link::*;
+pub use listing::*;
+pub use macros::*;
+pub use object::*;
+pub use symbols::*;
 pub use verify::*;
@@ -0,0 +1,353 @@
+use crate::*;
+use std::collections::HashMap;
+
+/// Where the CPU loads a program that has no `.start` directive
+pub const DEFAULT_START: u16 = 100;
+
+const DIRECTIVE_LIMIT: u16 = 0b1_1111_1111;
+
+#[derive(Debug, Default)]
+pub struct LinkedImage {
+    pub start: u16,
+    pub ssp: Option<u16>,
+    pub sbp: Option<u16>,
+    pub text: Vec<i16>,
+    pub symbols: Vec<(String, u16)>,
+}
+
+impl LinkedImage {
+    /// The raw image that `basm --raw` writes and `belle --raw` reads
+    #[must_use]
+    pub fn to_flat(&self) -> Vec<u8> {
+        let mut words = vec![(1 << 9) | self.start as i16];
+        if let Some(ssp) = self.ssp {
+            words.push((2 << 9) | ssp as i16);
+        }
+        if let Some(sbp) = self.sbp {
+            words.push((3 << 9) | sbp as i16);
+        }
+        words.extend(&self.text);
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    /// The executable that basm writes, with the whole image in one text segment
+    #[must_use]
+    pub fn to_executable(&self) -> Executable {
+        let mut executable = Executable {
+            entry: self.start,
+            ssp: self.ssp,
+            sbp: self.sbp,
+            ..Executable::default()
+        };
+        executable.push_words(self.start.into(), &self.text, SegmentKind::Text);
+        executable
+    }
+}
+
+/// Places the objects one after another, in the order given, and patches
+/// every relocation. All problems are collected and returned together.
+pub fn link(objects: &[(String, ObjectFile)]) -> Result<LinkedImage, Vec<Diagnostic>> {
+    let mut errors = Vec::new();
+    let mut image = LinkedImage::default();
+
+    let mut start: Option<(u16, &str)> = None;
+    let mut ssp: Option<(u16, &str)> = None;
+    let mut sbp: Option<(u16, &str)> = None;
+    for (name, object) in objects {
+        for (found, slot, directive) in [
+            (object.start, &mut start, ".start"),
+            (object.ssp, &mut ssp, ".ssp"),
+            (object.sbp, &mut sbp, ".sbp"),
+        ] {
+            let Some(value) = found else { continue };
+            match slot {
+                Some((_, first)) => errors.push(Diagnostic::error(
+                    codes::DUPLICATE_SYMBOL,
+                    format!("duplicate {directive} directives in {first} and {name}"),
+                )),
+                None => *slot = Some((value, name)),
+            }
+        }
+    }
+    image.start = start.map_or(DEFAULT_START, |(value, _)| value);
+    image.ssp = ssp.map(|(value, _)| value);
+    image.sbp = sbp.map(|(value, _)| value);
+    for value in [Some(image.start), image.ssp, image.sbp]
+        .into_iter()
+        .flatten()
+    {
+        if value > DIRECTIVE_LIMIT {
+            errors.push(Diagnostic::error(
+                codes::OUT_OF_RANGE,
+                format!("directive value {value} does not fit in 9 bits"),
+            ));
+        }
+    }
+
+    let mut bases = Vec::new();
+    let mut address = image.start;
+    for (name, object) in objects {
+        bases.push(address);
+        let end = u16::try_from(object.text.len())
+            .ok()
+            .and_then(|len| address.checked_add(len));
+        let Some(end) = end else {
+            errors.push(Diagnostic::error(
+                codes::OUT_OF_RANGE,
+                format!("program does not fit in memory, {name} ends past address 65535"),
+            ));
+            return Err(errors);
+        };
+        address = end;
+    }
+
+    let mut globals: HashMap<&str, (u16, &str)> = HashMap::new();
+    for ((name, object), &base) in objects.iter().zip(&bases) {
+        for symbol in &object.symbols {
+            if symbol.kind != SymbolKind::Global {
+                continue;
+            }
+            let Some(value) = base.checked_add(symbol.value) else {
+                errors.push(Diagnostic::error(
+                    codes::OUT_OF_RANGE,
+                    format!(
+                        "program does not fit in memory, {} in {name} is past address 65535",
+                        symbol.name
+                    ),
+                ));
+                continue;
+            };
+            if let Some((_, first)) = globals.get(symbol.name.as_str()) {
+                errors.push(Diagnostic::error(
+                    codes::DUPLICATE_SYMBOL,
+                    format!(
+                        "duplicate symbol {} defined in {first} and {name}",
+                        symbol.name
+                    ),
+                ));
+            } else {
+                globals.insert(&symbol.name, (value, name));
+                image.symbols.push((symbol.name.to_string(), value));
+            }
+        }
+    }
+
+    for ((name, object), base) in objects.iter().zip(bases) {
+        let mut text = object.text.clone();
+        for relocation in &object.relocations {
+            let target = match &relocation.kind {
+                RelocKind::Section => u32::from(base),
+                RelocKind::Symbol(symbol) => {
+                    let local = object
+                        .symbols
+                        .iter()
+                        .find(|sym| &sym.name == symbol && sym.kind != SymbolKind::Extern);
+                    if let Some(sym) = local {
+                        u32::from(base) + u32::from(sym.value)
+                    } else if let Some((value, _)) = globals.get(symbol.as_str()) {
+                        u32::from(*value)
+                    } else {
+                        errors.push(Diagnostic::error(
+                            codes::UNRESOLVED_SYMBOL,
+                            format!("unresolved symbol {symbol} referenced in {name}"),
+                        ));
+                        continue;
+                    }
+                }
+            };
+            let word = &mut text[relocation.offset as usize];
+            let field = relocation.field;
+            let mask = field.mask();
+            let address = u32::from((*word as u16 & mask) >> field.shift()) + target;
+            if address >= 1 << field.bits() {
+                errors.push(Diagnostic::error(
+                    codes::RELOCATION_OVERFLOW,
+                    format!(
+                        "address {address} at offset {} in {name} does not fit in {} bits",
+                        relocation.offset,
+                        field.bits()
+                    ),
+                ));
+                continue;
+            }
+            *word = (*word & !(mask as i16)) | ((address as i16) << field.shift());
+        }
+        image.text.extend(text);
+    }
+
+    image.symbols.sort_by_key(|(_, value)| *value);
+    if errors.is_empty() {
+        Ok(image)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JMP: i16 = JMP_OP << 12;
+
+    fn symbol(name: &str, kind: SymbolKind, value: u16) -> ObjectSymbol {
+        ObjectSymbol {
+            name: name.to_string(),
+            kind,
+            value,
+        }
+    }
+
+    fn relocation(offset: u16, kind: RelocKind) -> Relocation {
+        Relocation {
+            offset,
+            kind,
+            field: AddressField::Jump,
+        }
+    }
+
+    fn codes_of(result: Result<LinkedImage, Vec<Diagnostic>>) -> Vec<&'static str> {
+        result.unwrap_err().iter().map(|e| e.code).collect()
+    }
+
+    /// `main` calls `helper` in `lib` and jumps back to one of its own labels
+    fn objects() -> Vec<(String, ObjectFile)> {
+        let main = ObjectFile {
+            start: Some(200),
+            text: vec![JMP, JMP | 1, 0],
+            symbols: vec![
+                symbol("main", SymbolKind::Global, 0),
+                symbol("again", SymbolKind::Local, 1),
+                symbol("helper", SymbolKind::Extern, 0),
+            ],
+            relocations: vec![
+                relocation(0, RelocKind::Symbol("helper".to_string())),
+                relocation(1, RelocKind::Section),
+            ],
+            ..ObjectFile::default()
+        };
+        let lib = ObjectFile {
+            text: vec![0x1234, JMP],
+            symbols: vec![symbol("helper", SymbolKind::Global, 1)],
+            relocations: vec![relocation(1, RelocKind::Symbol("helper".to_string()))],
+            ..ObjectFile::default()
+        };
+        vec![("main.o".to_string(), main), ("lib.o".to_string(), lib)]
+    }
+
+    #[test]
+    fn relocations_are_patched() {
+        let image = link(&objects()).unwrap();
+        assert_eq!(image.start, 200);
+        assert_eq!(image.text, [JMP | 204, JMP | 201, 0, 0x1234, JMP | 204]);
+        assert_eq!(
+            image.symbols,
+            [("main".to_string(), 200), ("helper".to_string(), 204)]
+        );
+        assert_eq!(&image.to_flat()[..2], &[2, 200]);
+    }
+
+    #[test]
+    fn start_defaults_when_no_object_sets_it() {
+        let mut objects = objects();
+        objects[0].1.start = None;
+        let image = link(&objects).unwrap();
+        assert_eq!(image.start, DEFAULT_START);
+        assert_eq!(image.text[0], JMP | (DEFAULT_START as i16 + 4));
+    }
+
+    #[test]
+    fn duplicate_symbols_and_directives() {
+        let mut objects = objects();
+        objects[1].1.start = Some(300);
+        objects[1]
+            .1
+            .symbols
+            .push(symbol("main", SymbolKind::Global, 0));
+        let errors = link(&objects).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.code == codes::DUPLICATE_SYMBOL));
+        assert_eq!(
+            &*errors[0].message,
+            "duplicate .start directives in main.o and lib.o"
+        );
+        assert_eq!(
+            &*errors[1].message,
+            "duplicate symbol main defined in main.o and lib.o"
+        );
+    }
+
+    #[test]
+    fn unresolved_symbols() {
+        let objects = objects();
+        let errors = link(&objects[..1]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::UNRESOLVED_SYMBOL);
+        assert_eq!(
+            &*errors[0].message,
+            "unresolved symbol helper referenced in main.o"
+        );
+    }
+
+    #[test]
+    fn out_of_range_addresses() {
+        let mut objects = objects();
+        objects[0].1.text[0] = JMP | 4000;
+        assert_eq!(codes_of(link(&objects)), [codes::RELOCATION_OVERFLOW]);
+
+        let mut too_late = self::objects();
+        too_late[0].1.start = Some(DIRECTIVE_LIMIT + 1);
+        assert_eq!(codes_of(link(&too_late)), [codes::OUT_OF_RANGE]);
+    }
+
+    fn assemble_object(text: &str) -> ObjectFile {
+        let options = AssemblerOptions {
+            object: true,
+            ..AssemblerOptions::default()
+        };
+        let assembly = Assembler::with_files(options, SourceFiles::memory::<&str, &str>([]))
+            .assemble_source("main.asm", text);
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        assembly.object
+    }
+
+    #[test]
+    fn loads_and_stores_reach_data_in_other_objects() {
+        let main = assemble_object(
+            ".start $20\n.extern count\nld r0, @count\nadd r0, #1\nst @count, r0\nld r1, [25]\nhlt\n",
+        );
+        let data = assemble_object(".global count\ncount: .word 41\n");
+        let image = link(&[("main.o".to_string(), main), ("data.o".to_string(), data)]).unwrap();
+        assert_eq!(image.symbols, [("count".to_string(), 25)]);
+        assert_eq!(image.text[0], (LD_OP << 12) | 25);
+        assert_eq!(image.text[2], (ST_OP << 12) | (25 << 3));
+        assert_eq!(image.text[3], (LD_OP << 12) | (1 << 9) | 25);
+        assert_eq!(image.text[5], 41);
+    }
+
+    #[test]
+    fn loads_and_stores_must_fit_their_field() {
+        let main = assemble_object(".start $300\n.extern count\nst @count, r0\nhlt\n");
+        let data = assemble_object(".global count\ncount: .word 0\n");
+        let errors =
+            link(&[("main.o".to_string(), main), ("data.o".to_string(), data)]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            &*errors[0].message,
+            "address 302 at offset 0 in main.o does not fit in 8 bits"
+        );
+    }
+
+    #[test]
+    fn programs_must_fit_in_memory() {
+        let mut objects = objects();
+        objects[0].1.start = Some(500);
+        objects[1].1.text = vec![0; usize::from(u16::MAX - 502)];
+        let errors = link(&objects).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, codes::OUT_OF_RANGE);
+        assert_eq!(
+            &*errors[0].message,
+            "program does not fit in memory, lib.o ends past address 65535"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::*;
+use std::fmt::Write;
+
+#[derive(Debug)]
+struct ListingLine {
+    address: u32,
+    location: SourceLocation,
+    text: String,
+    words: Vec<i16>,
+    /// CPU directives are read by the loader and never placed in memory
+    loaded: bool,
+}
+
+/// A listing of the assembled program, written with `basm -l`.
+///
+/// Every source line is shown with the address its first word is loaded at,
+/// each word it emits in hex and binary, and the file and line it came from.
+/// Lines expanded from a macro are marked with `+`. The symbol table follows.
+#[derive(Debug, Default)]
+pub struct Listing {
+    lines: Vec<ListingLine>,
+}
+
+impl Listing {
+    /// Starts the entry for the lexed `line`, placed at `address`
+    pub fn begin_line(&mut self, address: u32, line: u32, expanded: &str, sources: &SourceMap) {
+        let origin = sources.origin(line);
+        let location = origin.location(sources);
+        let text = if origin.expansions.is_empty() {
+            sources
+                .text(&location)
+                .unwrap_or_else(|| expanded.to_string())
+        } else {
+            format!("+ {}", expanded.trim())
+        };
+        self.lines.push(ListingLine {
+            address,
+            location,
+            text,
+            words: Vec::new(),
+            loaded: true,
+        });
+    }
+
+    /// Adds the words emitted by the current line
+    pub fn push_words(&mut self, words: &[i16], loaded: bool) {
+        if let Some(line) = self.lines.last_mut() {
+            line.words.extend(words);
+            line.loaded = loaded;
+        }
+    }
+
+    /// The address of every word placed in memory, with the source line it came from
+    pub fn loaded_words(&self) -> impl Iterator<Item = (u32, &SourceLocation)> {
+        self.lines
+            .iter()
+            .filter(|line| line.loaded)
+            .flat_map(|line| {
+                (0..line.words.len() as u32)
+                    .map(move |offset| (line.address + offset, &line.location))
+            })
+    }
+
+    #[must_use]
+    pub fn render(&self, table: &SymbolTable, sources: &SourceMap) -> String {
+        let width = self
+            .lines
+            .iter()
+            .map(|line| line.location.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let mut out = String::new();
+        let _ = writeln!(out, "BELLE assembler listing of {}\n", sources.main_file);
+        let _ = writeln!(
+            out,
+            "{:>5}  {:4}  {:16}  {:width$}  source",
+            "addr", "hex", "binary", "line"
+        );
+
+        for line in &self.lines {
+            if line.words.is_empty() && line.text.trim().is_empty() {
+                continue;
+            }
+            let location = line.location.to_string();
+            let mut words = line.words.iter().enumerate();
+            match words.next() {
+                Some((_, word)) => {
+                    let address = if line.loaded {
+                        line.address.to_string()
+                    } else {
+                        String::new()
+                    };
+                    let _ = writeln!(
+                        out,
+                        "{address:>5}  {:04x}  {:016b}  {location:width$}  {}",
+                        *word as u16,
+                        *word as u16,
+                        line.text.trim_end()
+                    );
+                }
+                None => {
+                    let _ = writeln!(out, "{:31}{location:width$}  {}", "", line.text.trim_end());
+                }
+            }
+            for (offset, word) in words {
+                let _ = writeln!(
+                    out,
+                    "{:>5}  {:04x}  {:016b}",
+                    line.address + offset as u32,
+                    *word as u16,
+                    *word as u16
+                );
+            }
+        }
+
+        let symbols = table.sorted();
+        let constants = table.constants();
+        let name_width = symbols
+            .iter()
+            .map(|(name, symbol)| {
+                name.len()
+                    + if symbol.kind == SymbolKind::Local {
+                        0
+                    } else {
+                        9
+                    }
+            })
+            .chain(constants.iter().map(|(name, _, _)| name.len()))
+            .max()
+            .unwrap_or(0);
+
+        let _ = writeln!(out, "\nSymbols:");
+        for (name, symbol) in symbols {
+            let name = match symbol.kind {
+                SymbolKind::Local => name.to_string(),
+                SymbolKind::Global => format!("{name} (global)"),
+                SymbolKind::Extern => format!("{name} (extern)"),
+            };
+            let location = sources.origin_location(symbol.line);
+            let _ = writeln!(out, "{:>5}  {name:name_width$}  {location}", symbol.address);
+        }
+
+        if !constants.is_empty() {
+            let _ = writeln!(out, "\nConstants:");
+            for (name, value, line) in constants {
+                let location = if line == 0 {
+                    "-D".to_string()
+                } else {
+                    sources.origin_location(line).to_string()
+                };
+                let _ = writeln!(out, "{value:>5}  {name:name_width$}  {location}");
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn listing(raw: bool) -> String {
+        let options = AssemblerOptions {
+            raw,
+            ..AssemblerOptions::default()
+        };
+        let assembly = Assembler::with_files(options, SourceFiles::memory::<&str, &str>([]))
+            .assemble_source("main.asm", ".start $100\nhlt");
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        assembly
+            .listing
+            .render(&assembly.symbols, &assembly.sources)
+    }
+
+    #[test]
+    fn directive_words_only_in_raw_listings() {
+        let executable = listing(false);
+        assert!(!executable.contains("0264"), "{executable}");
+        assert!(executable.contains("  100  0000  "), "{executable}");
+
+        let raw = listing(true);
+        assert!(raw.contains("       0264  0000001001100100"), "{raw}");
+        assert!(raw.contains("  100  0000  "), "{raw}");
+    }
+}
@@ -0,0 +1,464 @@
+use crate::Error::*;
+use crate::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Default)]
+pub struct LineOrigin {
+    /// Line of the program after includes, inside the macro definition for expanded lines
+    pub line: u32,
+    /// Macros this line was expanded from, outermost first, with the line each was invoked on
+    pub expansions: Vec<(String, u32)>,
+}
+
+impl LineOrigin {
+    /// The file and line to report errors at: the outermost invocation for expanded lines
+    #[must_use]
+    pub fn location(&self, sources: &SourceMap) -> SourceLocation {
+        sources.location(self.expansions.first().map_or(self.line, |(_, line)| *line))
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(u32, String)>,
+    line: u32,
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    output: Vec<(String, LineOrigin)>,
+    errors: Vec<Error<'static>>,
+    expansions: u32,
+}
+
+/// Expands `.macro`/`.endm` definitions before anything is lexed.
+///
+/// ```asm
+/// .macro putc addr, ch
+///     mov r2, \ch
+///     st \addr, r2
+/// .endm
+///     putc $1, 'h'
+/// ```
+///
+/// Parameters are used as `\name` or by position as `\1`, `\2`, ...
+/// `\@` becomes a suffix unique to each expansion, so labels such as
+/// `loop\@:` can be defined inside a macro that is used more than once.
+/// Macros must be defined before they are invoked, and may invoke other macros.
+///
+/// Returns the expanded lines along with where each of them came from.
+pub fn expand_macros(
+    lines: &[String],
+) -> Result<(Vec<String>, Vec<LineOrigin>), Vec<Error<'static>>> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        output: Vec::new(),
+        errors: Vec::new(),
+        expansions: 0,
+    };
+    let mut defining: Option<(String, Macro)> = None;
+
+    for (index, text) in lines.iter().enumerate() {
+        let line = index as u32 + 1;
+        let code = strip_comment(text).trim();
+        let directive = code.split_whitespace().next().unwrap_or("");
+
+        if let Some((name, mut definition)) = defining.take() {
+            if directive.eq_ignore_ascii_case(".endm") {
+                let line = definition.line;
+                if let Entry::Vacant(entry) = expander.macros.entry(name.to_string()) {
+                    entry.insert(definition);
+                } else {
+                    expander
+                        .errors
+                        .push(MacroError(format!("macro {name} is already defined"), line));
+                }
+            } else if directive.eq_ignore_ascii_case(".macro") {
+                expander.errors.push(MacroError(
+                    "macros cannot be defined inside a macro".to_string(),
+                    line,
+                ));
+                defining = Some((name, definition));
+            } else {
+                definition.body.push((line, text.to_string()));
+                defining = Some((name, definition));
+            }
+            continue;
+        }
+
+        if directive.eq_ignore_ascii_case(".macro") {
+            let header = code[directive.len()..].trim();
+            let (name, params) = header
+                .split_once(char::is_whitespace)
+                .unwrap_or((header, ""));
+            if name.is_empty() || !is_identifier(name) {
+                expander
+                    .errors
+                    .push(MacroError("a macro needs a valid name".to_string(), line));
+            }
+            let params: Vec<String> = split_arguments(params)
+                .into_iter()
+                .map(|param| param.trim_start_matches('\\').to_string())
+                .collect();
+            defining = Some((
+                name.to_string(),
+                Macro {
+                    params,
+                    body: Vec::new(),
+                    line,
+                },
+            ));
+        } else if directive.eq_ignore_ascii_case(".endm") {
+            expander
+                .errors
+                .push(MacroError(".endm without .macro".to_string(), line));
+        } else {
+            expander.expand_line(text, line, &[]);
+        }
+    }
+
+    if let Some((name, definition)) = defining {
+        expander.errors.push(MacroError(
+            format!("macro {name} is missing .endm"),
+            definition.line,
+        ));
+    }
+
+    if !expander.errors.is_empty() {
+        return Err(expander.errors);
+    }
+    Ok(expander.output.into_iter().unzip())
+}
+
+impl Expander {
+    fn expand_line(&mut self, text: &str, line: u32, frames: &[(String, u32)]) {
+        let code = strip_comment(text).trim();
+        let (label, rest) = match code.split_once(':') {
+            Some((label, rest)) if is_identifier(label.trim()) => (Some(label.trim()), rest.trim()),
+            _ => (None, code),
+        };
+        let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+        let Some(definition) = self.macros.get(name) else {
+            let origin = LineOrigin {
+                line,
+                expansions: frames.to_vec(),
+            };
+            self.output.push((text.to_string(), origin));
+            return;
+        };
+
+        let reported = frames.first().map_or(line, |(_, line)| *line);
+        if frames.len() >= MAX_EXPANSION_DEPTH {
+            self.errors.push(MacroError(
+                format!("macro {name} is nested too deeply (is it recursive?)"),
+                reported,
+            ));
+            return;
+        }
+        let args = split_arguments(args);
+        if args.len() != definition.params.len() {
+            self.errors.push(MacroError(
+                format!(
+                    "macro {name} takes {} arguments but {} were given",
+                    definition.params.len(),
+                    args.len()
+                ),
+                reported,
+            ));
+            return;
+        }
+
+        self.expansions += 1;
+        let suffix = format!("__{}", self.expansions);
+        let body: Vec<_> = definition
+            .body
+            .iter()
+            .map(|(body_line, body)| {
+                (
+                    *body_line,
+                    substitute(body, &definition.params, &args, &suffix),
+                )
+            })
+            .collect();
+
+        if let Some(label) = label {
+            let origin = LineOrigin {
+                line,
+                expansions: frames.to_vec(),
+            };
+            self.output.push((format!("{label}:"), origin));
+        }
+        let mut frames = frames.to_vec();
+        frames.push((name.to_string(), line));
+        for (body_line, body) in body {
+            match body {
+                Ok(body) => self.expand_line(&body, body_line, &frames),
+                Err(e) => self.errors.push(MacroError(
+                    format!("{e} (macro {name}, line {body_line})"),
+                    reported,
+                )),
+            }
+        }
+    }
+}
+
+fn substitute(
+    body: &str,
+    params: &[String],
+    args: &[String],
+    suffix: &str,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = body.chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        if let Some(open) = quote {
+            // escapes such as \n inside strings and characters are left alone
+            result.push(c);
+            if c == '\\' {
+                result.extend(chars.next());
+            } else if c == open {
+                quote = None;
+            }
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            quote = Some(c);
+        }
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'@') {
+            chars.next();
+            result.push_str(suffix);
+            continue;
+        }
+        let mut name = String::new();
+        while let Some(&next) = chars.peek() {
+            if next.is_alphanumeric() || next == '_' {
+                name.push(chars.next().unwrap());
+            } else {
+                break;
+            }
+        }
+        let position = name.parse::<usize>().ok().and_then(|n| n.checked_sub(1));
+        match params.iter().position(|param| *param == name).or(position) {
+            Some(index) if index < args.len() => result.push_str(&args[index]),
+            _ => return Err(format!("unknown macro parameter \\{name}")),
+        }
+    }
+    Ok(result)
+}
+
+/// Splits macro arguments on commas that are not inside quotes or brackets
+fn split_arguments(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut depth = 0;
+    for c in args.chars() {
+        match c {
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            '[' | '(' if quote.is_none() => depth += 1,
+            ']' | ')' if quote.is_none() => depth -= 1,
+            ',' if quote.is_none() && depth == 0 => {
+                result.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !result.is_empty() {
+        result.push(current.trim().to_string());
+    }
+    result
+}
+
+/// The part of a line before its comment, ignoring `;` inside quotes
+#[must_use]
+pub fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' | '"' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            ';' if quote.is_none() => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str) -> Result<Vec<String>, Vec<(String, u32)>> {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        match expand_macros(&lines) {
+            Ok((lines, _)) => Ok(lines
+                .into_iter()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect()),
+            Err(errors) => Err(errors
+                .into_iter()
+                .map(|e| match e {
+                    MacroError(message, line) => (message, line),
+                    e => panic!("unexpected error {e:?}"),
+                })
+                .collect()),
+        }
+    }
+
+    #[test]
+    fn named_and_positional_parameters() {
+        let text = "\
+.macro putc addr, ch
+    mov r2, \\ch
+    st \\addr, \\2
+.endm
+    putc $1, 'h'
+    putc [r3], ','";
+        assert_eq!(
+            expand(text).unwrap(),
+            ["mov r2, 'h'", "st $1, 'h'", "mov r2, ','", "st [r3], ','"]
+        );
+    }
+
+    #[test]
+    fn escapes_in_strings_are_kept() {
+        let text = r#"
+.macro greet name
+\name: .asciiz "hi\n\t\"\name\""
+    mov r0, '\''
+    mov r1, '\\', \name
+.endm
+    greet hello"#;
+        assert_eq!(
+            expand(text).unwrap(),
+            [
+                r#"hello: .asciiz "hi\n\t\"\name\"""#,
+                r"mov r0, '\''",
+                r"mov r1, '\\', hello"
+            ]
+        );
+    }
+
+    #[test]
+    fn unique_suffix_for_every_expansion() {
+        let text = "\
+.macro spin
+loop\\@:
+    jmp @loop\\@
+.endm
+start: spin
+    spin";
+        assert_eq!(
+            expand(text).unwrap(),
+            [
+                "start:",
+                "loop__1:",
+                "jmp @loop__1",
+                "loop__2:",
+                "jmp @loop__2"
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_macros_record_their_origins() {
+        let lines: Vec<String> = "\
+.macro inner
+    nop
+.endm
+.macro outer
+    inner
+.endm
+    outer"
+            .lines()
+            .map(str::to_string)
+            .collect();
+        let (lines, origins) = expand_macros(&lines).unwrap();
+        let index = lines.iter().position(|line| line.trim() == "nop").unwrap();
+        assert_eq!(origins[index].line, 2);
+        assert_eq!(
+            origins[index].expansions,
+            [("outer".to_string(), 7), ("inner".to_string(), 5)]
+        );
+    }
+
+    #[test]
+    fn recursion_stops_at_the_depth_limit() {
+        let errors = expand(".macro again\n    again\n.endm\n    again").unwrap_err();
+        assert_eq!(
+            errors,
+            [(
+                "macro again is nested too deeply (is it recursive?)".to_string(),
+                4
+            )]
+        );
+
+        // a chain exactly as deep as the limit is fine
+        let mut text = String::new();
+        for depth in 0..MAX_EXPANSION_DEPTH {
+            text += &format!(".macro m{depth}\n    m{}\n.endm\n", depth + 1);
+        }
+        text += &format!(".macro m{MAX_EXPANSION_DEPTH}\n    nop\n.endm\n    m1\n");
+        assert_eq!(expand(&text).unwrap(), ["nop"]);
+        text += "    m0\n";
+        assert!(expand(&text).is_err());
+    }
+
+    #[test]
+    fn bad_definitions_and_uses() {
+        let errors = |text| expand(text).unwrap_err();
+        assert_eq!(
+            errors(".macro two a, b\n.endm\n    two 1"),
+            [(
+                "macro two takes 2 arguments but 1 were given".to_string(),
+                3
+            )]
+        );
+        assert_eq!(
+            errors(".macro m\n.endm\n.macro m\n.endm"),
+            [("macro m is already defined".to_string(), 3)]
+        );
+        assert_eq!(
+            errors(".macro m\n    nop"),
+            [("macro m is missing .endm".to_string(), 1)]
+        );
+        assert_eq!(errors(".endm"), [(".endm without .macro".to_string(), 1)]);
+        assert_eq!(
+            errors(".macro m a\n    mov r0, \\b\n.endm\n    m 1"),
+            [(
+                "unknown macro parameter \\b (macro m, line 2)".to_string(),
+                4
+            )]
+        );
+    }
+
+    #[test]
+    fn arguments_split_outside_quotes_and_brackets() {
+        assert_eq!(
+            split_arguments("',', [r1, 2], (1, 2), \"a,b\""),
+            ["','", "[r1, 2]", "(1, 2)", "\"a,b\""]
+        );
+        assert!(split_arguments("  ").is_empty());
+        assert_eq!(strip_comment("mov r0, ';' ; comment"), "mov r0, ';' ");
+    }
+}
@@ -0,0 +1,355 @@
+use crate::*;
+
+/// Relocatable object files written by `basm -c` and read by `bld`.
+///
+/// Everything is big-endian, like the flat binaries:
+///
+/// ```text
+/// magic      "BOBJ"
+/// version    u16
+/// present    u16    bit 0: .start, bit 1: .ssp, bit 2: .sbp
+/// start      u16
+/// ssp        u16
+/// sbp        u16
+/// text       u16 count, then count words
+/// symbols    u16 count, then per symbol: u8 kind, u16 value, u16 name length, name
+/// relocs     u16 count, then per relocation: u16 offset, u8 kind, u16 symbol index, u8 field
+/// ```
+///
+/// Symbol values and relocated fields are offsets from the first word of the
+/// object's text. A relocation patches the address field of a JMP/JZ/JO, LD
+/// or ST word, or the 12 bits of a `.word` holding a label.
+pub const OBJECT_MAGIC: &[u8; 4] = b"BOBJ";
+pub const OBJECT_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocKind {
+    /// Add the address the object's text is placed at
+    Section,
+    /// Add the final address of the named symbol
+    Symbol(String),
+}
+
+/// The bits of a word that hold a relocated address
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressField {
+    /// The low 12 bits, of a JMP/JZ/JO or a `.word`
+    #[default]
+    Jump,
+    /// The low 9 bits of an LD
+    Load,
+    /// Bits 3 to 10 of an ST to memory
+    Store,
+}
+
+impl AddressField {
+    #[must_use]
+    pub fn bits(self) -> u32 {
+        match self {
+            AddressField::Jump => 12,
+            AddressField::Load => 9,
+            AddressField::Store => 8,
+        }
+    }
+
+    #[must_use]
+    pub fn shift(self) -> u32 {
+        match self {
+            AddressField::Store => 3,
+            _ => 0,
+        }
+    }
+
+    /// The field's bits in place within the word
+    #[must_use]
+    pub fn mask(self) -> u16 {
+        ((1 << self.bits()) - 1) << self.shift()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub offset: u16,
+    pub kind: RelocKind,
+    pub field: AddressField,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ObjectFile {
+    pub start: Option<u16>,
+    pub ssp: Option<u16>,
+    pub sbp: Option<u16>,
+    pub text: Vec<i16>,
+    pub symbols: Vec<ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectFile {
+    /// Records a `.start`, `.ssp` or `.sbp` directive
+    pub fn set_directive(&mut self, directive: &str, value: i16) {
+        let value = Some(value as u16);
+        match directive {
+            "start" => self.start = value,
+            "ssp" => self.ssp = value,
+            "sbp" => self.sbp = value,
+            _ => (),
+        }
+    }
+
+    /// Appends an encoded word, making the addresses of jumps, loads and
+    /// stores relocatable.
+    ///
+    /// Labels are stored relative to the start of the text (or as 0 plus a
+    /// symbol relocation for `.extern` labels). A `$addr` is taken to be
+    /// relative to `origin`, the object's own `.start`. Jumps cannot go below
+    /// it, while loads and stores below it are left as absolute addresses.
+    pub fn push_word(
+        &mut self,
+        word: i16,
+        ins: &Token,
+        arg1: Option<&Token>,
+        arg2: Option<&Token>,
+        origin: i16,
+        table: &SymbolTable,
+    ) -> Result<(), String> {
+        let offset = self.text.len() as u16;
+        let (field, arg) = match ins.get_raw().to_uppercase().as_str() {
+            "JMP" | "JZ" | "JO" => (AddressField::Jump, arg1),
+            "LD" => (AddressField::Load, arg2),
+            "ST" => (AddressField::Store, arg1),
+            _ => {
+                self.text.push(word);
+                return Ok(());
+            }
+        };
+        let cleared = word & !(field.mask() as i16);
+        let relocation = match arg {
+            Some(Token::SRCall(name)) => match table.get(name) {
+                Some(symbol) if symbol.kind == SymbolKind::Extern => {
+                    Some((cleared, RelocKind::Symbol(name.to_string())))
+                }
+                _ => Some((word, RelocKind::Section)),
+            },
+            Some(Token::MemAddr(addr)) if *addr >= origin => Some((
+                cleared | ((addr - origin) << field.shift()),
+                RelocKind::Section,
+            )),
+            Some(Token::MemAddr(addr)) if field == AddressField::Jump => {
+                return Err(format!(
+                    "jump target ${} is below the object's start address {}",
+                    addr, origin
+                ));
+            }
+            _ => None,
+        };
+        match relocation {
+            Some((word, kind)) => {
+                self.text.push(word);
+                self.relocations.push(Relocation {
+                    offset,
+                    kind,
+                    field,
+                });
+            }
+            None => self.text.push(word),
+        }
+        Ok(())
+    }
+
+    /// Appends the words of a data directive, relocating labels stored by `.word`
+    pub fn push_data(&mut self, words: &[i16], tokens: &[Token], table: &SymbolTable) {
+        let offset = self.text.len();
+        self.text.extend(words);
+        if !matches!(tokens.first(), Some(Token::Label(directive)) if directive == "word") {
+            return;
+        }
+        for (index, operand) in data_operands(tokens).into_iter().enumerate() {
+            let Token::SRCall(name) = operand else {
+                continue;
+            };
+            let kind = match table.get(name) {
+                Some(symbol) if symbol.kind == SymbolKind::Extern => {
+                    RelocKind::Symbol(name.to_string())
+                }
+                _ => RelocKind::Section,
+            };
+            self.relocations.push(Relocation {
+                offset: (offset + index) as u16,
+                kind,
+                field: AddressField::Jump,
+            });
+        }
+    }
+
+    /// Copies every label in the symbol table into the object
+    pub fn add_symbols(&mut self, table: &SymbolTable) {
+        self.symbols = table
+            .sorted()
+            .into_iter()
+            .map(|(name, symbol)| ObjectSymbol {
+                name: name.to_string(),
+                kind: symbol.kind,
+                value: symbol.address as u16,
+            })
+            .collect();
+    }
+
+    #[must_use]
+    pub fn symbol_index(&self, name: &str) -> Option<usize> {
+        self.symbols.iter().position(|sym| sym.name == name)
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        let push = |bytes: &mut Vec<u8>, value: u16| bytes.extend(value.to_be_bytes());
+
+        push(&mut bytes, OBJECT_VERSION);
+        let present = u16::from(self.start.is_some())
+            | u16::from(self.ssp.is_some()) << 1
+            | u16::from(self.sbp.is_some()) << 2;
+        push(&mut bytes, present);
+        for directive in [self.start, self.ssp, self.sbp] {
+            push(&mut bytes, directive.unwrap_or(0));
+        }
+
+        push(&mut bytes, self.text.len() as u16);
+        for word in &self.text {
+            bytes.extend(word.to_be_bytes());
+        }
+
+        push(&mut bytes, self.symbols.len() as u16);
+        for symbol in &self.symbols {
+            bytes.push(match symbol.kind {
+                SymbolKind::Local => 0,
+                SymbolKind::Global => 1,
+                SymbolKind::Extern => 2,
+            });
+            push(&mut bytes, symbol.value);
+            push(&mut bytes, symbol.name.len() as u16);
+            bytes.extend(symbol.name.as_bytes());
+        }
+
+        push(&mut bytes, self.relocations.len() as u16);
+        for relocation in &self.relocations {
+            push(&mut bytes, relocation.offset);
+            match &relocation.kind {
+                RelocKind::Section => {
+                    bytes.push(0);
+                    push(&mut bytes, 0);
+                }
+                RelocKind::Symbol(name) => {
+                    bytes.push(1);
+                    push(&mut bytes, self.symbol_index(name).unwrap_or(0) as u16);
+                }
+            }
+            bytes.push(match relocation.field {
+                AddressField::Jump => 0,
+                AddressField::Load => 1,
+                AddressField::Store => 2,
+            });
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, String> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.take(4)? != OBJECT_MAGIC {
+            return Err("not a BELLE object file".to_string());
+        }
+        let version = reader.u16()?;
+        if version != OBJECT_VERSION {
+            return Err(format!("unsupported object file version {version}"));
+        }
+
+        let present = reader.u16()?;
+        let mut object = ObjectFile::default();
+        let mut directives = [None; 3];
+        for (bit, directive) in directives.iter_mut().enumerate() {
+            let value = reader.u16()?;
+            if present & (1 << bit) != 0 {
+                *directive = Some(value);
+            }
+        }
+        [object.start, object.ssp, object.sbp] = directives;
+
+        for _ in 0..reader.u16()? {
+            object.text.push(reader.u16()? as i16);
+        }
+
+        for _ in 0..reader.u16()? {
+            let kind = match reader.u8()? {
+                0 => SymbolKind::Local,
+                1 => SymbolKind::Global,
+                2 => SymbolKind::Extern,
+                n => return Err(format!("unknown symbol kind {n}")),
+            };
+            let value = reader.u16()?;
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| "symbol name is not valid UTF-8".to_string())?;
+            object.symbols.push(ObjectSymbol { name, kind, value });
+        }
+
+        for _ in 0..reader.u16()? {
+            let offset = reader.u16()?;
+            if offset as usize >= object.text.len() {
+                return Err(format!("relocation offset {offset} is outside the text"));
+            }
+            let kind = reader.u8()?;
+            let index = reader.u16()? as usize;
+            let kind = match kind {
+                0 => RelocKind::Section,
+                1 => match object.symbols.get(index) {
+                    Some(symbol) => RelocKind::Symbol(symbol.name.to_string()),
+                    None => return Err(format!("relocation refers to missing symbol {index}")),
+                },
+                n => return Err(format!("unknown relocation kind {n}")),
+            };
+            let field = match reader.u8()? {
+                0 => AddressField::Jump,
+                1 => AddressField::Load,
+                2 => AddressField::Store,
+                n => return Err(format!("unknown relocation field {n}")),
+            };
+            object.relocations.push(Relocation {
+                offset,
+                kind,
+                field,
+            });
+        }
+        Ok(object)
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let slice = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| "object file is truncated".to_string())?;
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
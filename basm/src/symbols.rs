@@ -0,0 +1,548 @@
+use crate::Error::*;
+use crate::*;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Local,
+    Global,
+    Extern,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub address: u32,
+    pub line: u32,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+    /// `.equ` constants, with the line each was defined on
+    constants: HashMap<String, (i16, u32)>,
+}
+
+impl SymbolTable {
+    pub fn define(&mut self, name: &str, address: u32, line: u32) -> Result<(), Error<'static>> {
+        self.check_unused(name, line)?;
+        self.symbols.insert(
+            name.to_string(),
+            Symbol {
+                address,
+                line,
+                kind: SymbolKind::Local,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn define_constant(
+        &mut self,
+        name: &str,
+        value: i16,
+        line: u32,
+    ) -> Result<(), Error<'static>> {
+        self.check_unused(name, line)?;
+        self.constants.insert(name.to_string(), (value, line));
+        Ok(())
+    }
+
+    fn check_unused(&self, name: &str, line: u32) -> Result<(), Error<'static>> {
+        let first = self
+            .symbols
+            .get(name)
+            .map(|symbol| symbol.line)
+            .or(self.constants.get(name).map(|(_, line)| *line));
+        match first {
+            Some(0) => Err(ExpressionError(
+                format!("{name} is already defined with -D"),
+                line,
+            )),
+            Some(first) => Err(DuplicateLabel(name.to_string(), line, first)),
+            None => Ok(()),
+        }
+    }
+
+    #[must_use]
+    pub fn constant(&self, name: &str) -> Option<i16> {
+        self.constants.get(name).map(|(value, _)| *value)
+    }
+
+    /// All constants and the line each was defined on (0 for `-D`), ordered by name
+    #[must_use]
+    pub fn constants(&self) -> Vec<(&String, i16, u32)> {
+        let mut constants: Vec<_> = self
+            .constants
+            .iter()
+            .map(|(name, (value, line))| (name, *value, *line))
+            .collect();
+        constants.sort();
+        constants
+    }
+
+    /// The value of a constant, or the address of a label
+    #[must_use]
+    pub fn value(&self, name: &str) -> Option<i32> {
+        self.constant(name)
+            .map(i32::from)
+            .or_else(|| self.get(name).map(|symbol| symbol.address as i32))
+    }
+
+    /// Declares a label that another object file will define
+    pub fn declare_extern(&mut self, name: &str, line: u32) -> Result<(), Error<'static>> {
+        self.define(name, 0, line)?;
+        self.symbols.get_mut(name).unwrap().kind = SymbolKind::Extern;
+        Ok(())
+    }
+
+    /// Marks a label defined in this file as visible to other object files
+    pub fn export(&mut self, name: &str, line: u32) -> Result<(), Error<'static>> {
+        match self.symbols.get_mut(name) {
+            Some(symbol) if symbol.kind != SymbolKind::Extern => {
+                symbol.kind = SymbolKind::Global;
+                Ok(())
+            }
+            _ => Err(UndefinedLabel(name.to_string(), line)),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+
+    /// The defined name closest to `name`, to suggest when it is misspelled
+    #[must_use]
+    pub fn closest(&self, name: &str) -> Option<String> {
+        let names = self.symbols.keys().chain(self.constants.keys());
+        closest_name(name, names.map(String::as_str))
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.symbols.contains_key(name)
+    }
+
+    /// All symbols, ordered by address
+    #[must_use]
+    pub fn sorted(&self) -> Vec<(&String, &Symbol)> {
+        let mut symbols: Vec<_> = self.symbols.iter().collect();
+        symbols.sort_by_key(|(name, sym)| (sym.address, name.to_string()));
+        symbols
+    }
+
+    pub fn clear(&mut self) {
+        self.symbols.clear();
+        self.constants.clear();
+    }
+}
+
+/// How many words a lexed line placed at `address` puts in the loaded program
+#[must_use]
+pub fn words_emitted(tokens: &[Token], address: u32) -> u32 {
+    let operands = data_operands(tokens);
+    match tokens.first() {
+        None => 0,
+        Some(Token::Label(directive)) => match directive.as_str() {
+            "word" => operands.len() as u32,
+            "ascii" | "asciiz" => match operands.first() {
+                Some(Token::Str(string)) => {
+                    string.chars().count() as u32 + u32::from(directive == "asciiz")
+                }
+                _ => 0,
+            },
+            "fill" => operands
+                .first()
+                .map_or(0, |count| count.get_num().max(0) as u32),
+            "org" => org_target(tokens).map_or(0, |target| target.saturating_sub(address)),
+            _ => 0, // CPU directives are consumed by the loader
+        },
+        Some(_) => 1,
+    }
+}
+
+/// First pass of the assembler.
+///
+/// Walks the lexed lines, gives every emitted word an address starting at
+/// the `.start` location and records where each label lands. Every reference
+/// to a label is checked against the finished table, so undefined labels are
+/// caught here instead of in the middle of encoding.
+///
+/// `.equ` constants are defined in order along the way. Expressions in
+/// directives are folded as soon as they are reached, since they can move
+/// the location counter, and every other expression is folded once all
+/// labels are known.
+///
+/// When assembling an object file, addresses start at 0 instead, and
+/// `.extern`/`.global` declare imported and exported labels.
+pub fn build_symbol_table(
+    lines: &mut [LexedLine],
+    table: &mut SymbolTable,
+    options: &AssemblerOptions,
+    start: u32,
+) -> Result<(), Vec<Diagnostic>> {
+    table.clear();
+    let mut errors = Vec::new();
+    for (name, value) in parse_defines(&options.defines).unwrap_or_default() {
+        let _ = table.define_constant(&name, value, 0);
+    }
+    let names: HashSet<String> = lines
+        .iter()
+        .flat_map(|line| {
+            let constant = match line.instruction() {
+                [Token::Label(directive), Token::Ident(name), ..] if directive == "equ" => {
+                    Some(name.as_str())
+                }
+                _ => None,
+            };
+            line.labels().chain(constant).map(str::to_string)
+        })
+        .collect();
+    let mut exports = Vec::new();
+    let mut address = if options.object { 0 } else { start };
+
+    for line in lines.iter_mut() {
+        line.address = address;
+        for label in line.labels() {
+            if let Err(e) = table.define(label, address, line.number) {
+                errors.push(e);
+            }
+        }
+        let number = line.number;
+        if is_directive(line.instruction()) && !stores_labels(line.instruction()) {
+            if let Err(e) = fold_expressions(
+                line.instruction_mut(),
+                table,
+                number,
+                &names,
+                options.object,
+            ) {
+                errors.push(e);
+            }
+        }
+        let tokens = line.instruction();
+        if let [Token::Label(directive), Token::Ident(name), Token::Literal(value)] = tokens {
+            if directive == "equ" {
+                if let Err(e) = table.define_constant(name, *value, number) {
+                    errors.push(e);
+                }
+            }
+        }
+        if let [Token::Label(directive), Token::Ident(name)] = tokens {
+            match directive.as_str() {
+                "extern" if options.object => {
+                    if let Err(e) = table.declare_extern(name, line.number) {
+                        errors.push(e);
+                    }
+                }
+                "global" => exports.push((name.to_string(), line.number)),
+                _ => (),
+            }
+        }
+        if let Some(Token::Label(directive)) = tokens.first() {
+            if directive == "org" && org_target(tokens).is_some_and(|target| target < address) {
+                errors.push(OtherError(
+                    ".org cannot move the location counter backwards",
+                    line.number,
+                    None,
+                ));
+            }
+        }
+        if is_directive(tokens) && !stores_labels(tokens) {
+            // a directive's value is needed before any label has an address
+            for tok in tokens {
+                if let Token::SRCall(name) = tok {
+                    errors.push(ForwardReference(name.to_string(), line.number));
+                }
+            }
+        }
+        address += words_emitted(tokens, address);
+    }
+
+    for (name, line) in exports {
+        if let Err(e) = table.export(&name, line) {
+            errors.push(e);
+        }
+    }
+
+    for line in lines.iter_mut() {
+        if is_directive(line.instruction()) && !stores_labels(line.instruction()) {
+            continue;
+        }
+        let number = line.number;
+        let later = HashSet::new();
+        if let Err(e) = fold_expressions(
+            line.instruction_mut(),
+            table,
+            number,
+            &later,
+            options.object,
+        ) {
+            errors.push(e);
+        }
+        let tokens = line.instruction();
+        for tok in tokens {
+            if let Token::SRCall(name) = tok {
+                if !table.contains(name) {
+                    errors.push(UndefinedLabel(name.to_string(), line.number));
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors
+            .into_iter()
+            .map(|error| {
+                let closest = match &error {
+                    UndefinedLabel(name, _) => table.closest(name),
+                    _ => None,
+                };
+                let diagnostic = Diagnostic::from(error);
+                match closest {
+                    Some(name) => diagnostic.with_suggestion("did you mean", name),
+                    None => diagnostic,
+                }
+            })
+            .collect())
+    }
+}
+
+fn is_directive(tokens: &[Token]) -> bool {
+    matches!(tokens.first(), Some(Token::Label(_)))
+}
+
+/// Replaces every expression operand with the literal or address it folds to.
+///
+/// `later` holds the names defined further down the file, which are
+/// reported as forward references rather than undefined labels. Labels
+/// cannot be used in expressions in `object` files, as they are relocated.
+fn fold_expressions(
+    tokens: &mut [Token],
+    table: &SymbolTable,
+    line: u32,
+    later: &HashSet<String>,
+    object: bool,
+) -> Result<(), Error<'static>> {
+    for tok in tokens.iter_mut() {
+        let Token::Expression(kind, expr) = tok else {
+            continue;
+        };
+        for name in expr.symbols() {
+            if table.constant(name).is_some() {
+                continue;
+            }
+            match table.get(name) {
+                Some(_) if object => {
+                    return Err(ExpressionError(
+                        format!(
+                        "{name} is a label, which cannot be used in expressions in object files"
+                    ),
+                        line,
+                    ))
+                }
+                Some(_) => (),
+                None if later.contains(name) => {
+                    return Err(ForwardReference(name.to_string(), line))
+                }
+                None => return Err(UndefinedLabel(name.to_string(), line)),
+            }
+        }
+        let value = expr
+            .eval(&|name| table.value(name))
+            .map_err(|e| ExpressionError(format!("{e} in {expr}"), line))?;
+        let Ok(value) = i16::try_from(value) else {
+            return Err(ExpressionError(
+                format!("{expr} = {value} does not fit in 16 bits"),
+                line,
+            ));
+        };
+        *tok = match kind {
+            Operand::Literal => Token::Literal(value),
+            Operand::MemAddr => Token::MemAddr(value),
+        };
+    }
+    Ok(())
+}
+
+/// `.word` is the only directive whose labels are resolved in the second pass
+fn stores_labels(tokens: &[Token]) -> bool {
+    matches!(tokens.first(), Some(Token::Label(directive)) if directive == "word")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn assemble(text: &str, options: AssemblerOptions) -> Assembly {
+        Assembler::with_files(options, SourceFiles::memory::<&str, &str>([]))
+            .assemble_source("main.asm", text)
+    }
+
+    fn errors(assembly: &Assembly) -> Vec<(&str, &str, String)> {
+        assembly
+            .diagnostics
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| {
+                let location = d
+                    .snippet
+                    .as_ref()
+                    .map_or(String::new(), |s| s.location.to_string());
+                (d.code, &*d.message, location)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn labels_get_the_address_of_the_next_word() {
+        let assembly = assemble(
+            "\
+.start [200]
+    jmp @end          ; a label used before it is defined
+data: .word 1, 2
+text: .asciiz \"ab\"
+gap:
+end: hlt
+",
+            AssemblerOptions::default(),
+        );
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        let symbols: Vec<_> = assembly
+            .symbols
+            .sorted()
+            .into_iter()
+            .map(|(name, symbol)| (name.as_str(), symbol.address, symbol.line))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("data", 201, 3),
+                ("text", 203, 4),
+                ("end", 206, 6),
+                ("gap", 206, 5)
+            ]
+        );
+        assert_eq!(assembly.symbols.value("end"), Some(206));
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let assembly = assemble("a: nop\nb: nop\na: hlt\n", AssemblerOptions::default());
+        assert_eq!(
+            errors(&assembly),
+            [(
+                codes::DUPLICATE_LABEL,
+                "duplicate label a",
+                "main.asm:3".to_string()
+            )]
+        );
+        assert!(matches!(
+            &assembly.diagnostics.iter().find(|d| d.is_error()).unwrap().notes[..],
+            [Note::Note(text, Some(snippet))]
+                if text.starts_with("first defined here")
+                    && snippet.location.to_string() == "main.asm:1"
+        ));
+
+        let options = AssemblerOptions {
+            defines: vec!["N=1".to_string()],
+            ..AssemblerOptions::default()
+        };
+        let assembly = assemble(".equ N 2\nhlt\n", options);
+        assert_eq!(
+            errors(&assembly),
+            [(
+                codes::EXPRESSION,
+                "invalid expression: N is already defined with -D",
+                "main.asm:1".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn undefined_labels_suggest_the_closest_name() {
+        let assembly = assemble(
+            "loop: jmp @lop\njmp @elsewhere\nhlt\n",
+            AssemblerOptions::default(),
+        );
+        assert_eq!(
+            errors(&assembly),
+            [
+                (
+                    codes::UNDEFINED_LABEL,
+                    "undefined label lop",
+                    "main.asm:1".to_string()
+                ),
+                (
+                    codes::UNDEFINED_LABEL,
+                    "undefined label elsewhere",
+                    "main.asm:2".to_string()
+                ),
+            ]
+        );
+        let suggestions: Vec<_> = assembly
+            .diagnostics
+            .iter()
+            .map(|d| {
+                d.suggestions
+                    .iter()
+                    .map(|s| s.replacement.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(suggestions, [vec!["loop"], vec![]]);
+    }
+
+    #[test]
+    fn forward_references_in_directives() {
+        let assembly = assemble(
+            ".equ N M + 1\n.equ M 2\n.start @main\nmain: hlt\n.word @later\nlater: .word 7\n",
+            AssemblerOptions::default(),
+        );
+        assert_eq!(
+            errors(&assembly),
+            [
+                (
+                    codes::FORWARD_REFERENCE,
+                    "M is used before its value is known",
+                    "main.asm:1".to_string()
+                ),
+                (
+                    codes::FORWARD_REFERENCE,
+                    "main is used before its value is known",
+                    "main.asm:3".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn exports_must_be_defined_here() {
+        let options = AssemblerOptions {
+            object: true,
+            ..AssemblerOptions::default()
+        };
+        let assembly = assemble(
+            ".extern helper\n.global helper\n.global missing\nmain: jmp @helper\nhlt\n",
+            options,
+        );
+        assert_eq!(
+            errors(&assembly),
+            [
+                (
+                    codes::UNDEFINED_LABEL,
+                    "undefined label helper",
+                    "main.asm:2".to_string()
+                ),
+                (
+                    codes::UNDEFINED_LABEL,
+                    "undefined label missing",
+                    "main.asm:3".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Token;
+use crate::{codes, Diagnostic, Span, Token};
 
 static MMAFAIL: &str = "memory address too large";
 static LITFAIL: &str = "literal value too large";
@@ -38,12 +38,7 @@ impl Token {
     }
 }
 
-pub fn verify(
-    ins: &Token,
-    arg1: Option<&Token>,
-    arg2: Option<&Token>,
-    line_num: u32,
-) -> Result<(), String> {
+pub fn verify(ins: &Token, arg1: Option<&Token>, arg2: Option<&Token>) -> Result<(), Diagnostic> {
     let instructions = [
         "ADD", "HLT", "JO", "POP", "DIV", "RET", "LD", "ST", "JMP", "JZ", "PUSH", "CMP", "MUL",
         "INT", "MOV",
@@ -52,7 +47,7 @@ pub fn verify(
 
     if let Token::Ident(_) = ins {
         if instructions.contains(&raw_token.as_str()) {
-            return check_instruction(&raw_token, arg1, arg2, line_num);
+            return check_instruction(&raw_token, arg1, arg2);
         }
     }
     Ok(())
@@ -62,25 +57,18 @@ fn check_instruction(
     raw_token: &str,
     arg1: Option<&Token>,
     arg2: Option<&Token>,
-    line_num: u32,
-) -> Result<(), String> {
+) -> Result<(), Diagnostic> {
     match raw_token {
-        "HLT" | "RET" => only_none(arg1, arg2, raw_token, line_num),
-        "LD" => {
-            only_two(arg1, arg2, raw_token, line_num).and_then(|_| ld_args(arg1, arg2, line_num))
-        }
-        "ST" => {
-            only_two(arg1, arg2, raw_token, line_num).and_then(|_| st_args(arg1, arg2, line_num))
-        }
+        "HLT" | "RET" => only_none(arg1, arg2, raw_token),
+        "LD" => only_two(arg1, arg2, raw_token).and_then(|_| ld_args(arg1, arg2)),
+        "ST" => only_two(arg1, arg2, raw_token).and_then(|_| st_args(arg1, arg2)),
         "MOV" | "MUL" | "DIV" | "ADD" | "CMP" => {
-            only_two(arg1, arg2, raw_token, line_num).and_then(|_| mov_args(arg1, arg2, line_num))
-        }
-        "INT" => one_none(arg1, arg2, raw_token, line_num).and_then(|_| int_args(arg1, line_num)),
-        "JZ" | "JO" | "JMP" => {
-            only_one(arg1, arg2, raw_token, line_num).and_then(|_| jump_args(arg1, line_num))
+            only_two(arg1, arg2, raw_token).and_then(|_| mov_args(arg1, arg2))
         }
+        "INT" => one_none(arg1, arg2, raw_token).and_then(|_| int_args(arg1)),
+        "JZ" | "JO" | "JMP" => only_one(arg1, arg2, raw_token).and_then(|_| jump_args(arg1)),
         "PUSH" | "POP" | "SSP" | "SBP " => {
-            only_one(arg1, arg2, raw_token, line_num).and_then(|_| push_args(arg1, line_num))
+            only_one(arg1, arg2, raw_token).and_then(|_| push_args(arg1))
         }
         _ => Ok(()),
     }
@@ -90,12 +78,11 @@ fn only_none(
     arg1: Option<&Token>,
     arg2: Option<&Token>,
     instruction: &str,
-    line_num: u32,
-) -> Result<(), String> {
+) -> Result<(), Diagnostic> {
     if arg1.is_some() || arg2.is_some() {
-        return Err(format!(
-            "{} requires no arguments at line {}",
-            instruction, line_num
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            format!("{} requires no arguments", instruction),
         ));
     }
     Ok(())
@@ -105,12 +92,11 @@ fn only_two(
     arg1: Option<&Token>,
     arg2: Option<&Token>,
     instruction: &str,
-    line_num: u32,
-) -> Result<(), String> {
+) -> Result<(), Diagnostic> {
     if arg1.is_none() || arg2.is_none() {
-        return Err(format!(
-            "{} requires two arguments at line {}",
-            instruction, line_num
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            format!("{} requires two arguments", instruction),
         ));
     }
     Ok(())
@@ -120,12 +106,11 @@ fn one_none(
     arg1: Option<&Token>,
     arg2: Option<&Token>,
     instruction: &str,
-    line_num: u32,
-) -> Result<(), String> {
+) -> Result<(), Diagnostic> {
     if arg1.is_some() && arg2.is_some() {
-        return Err(format!(
-            "{} requires one or no arguments at line {}",
-            instruction, line_num
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            format!("{} requires one or no arguments", instruction),
         ));
     }
     Ok(())
@@ -135,61 +120,64 @@ fn only_one(
     arg1: Option<&Token>,
     arg2: Option<&Token>,
     instruction: &str,
-    line_num: u32,
-) -> Result<(), String> {
+) -> Result<(), Diagnostic> {
     if arg1.is_none() || arg2.is_some() {
-        return Err(format!(
-            "{} requires one argument at line {}",
-            instruction, line_num
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            format!("{} requires one argument", instruction),
         ));
     }
     Ok(())
 }
 
-fn ld_args(arg1: Option<&Token>, arg2: Option<&Token>, line_num: u32) -> Result<(), String> {
+fn ld_args(arg1: Option<&Token>, arg2: Option<&Token>) -> Result<(), Diagnostic> {
     if !arg1.is_some_and(|tok| tok.is_register()) {
-        return Err(format!(
-            "LD requires LHS to be a Register at line {}",
-            line_num
-        ));
+        return Err(
+            Diagnostic::error(codes::OPERANDS, "LD requires LHS to be a Register")
+                .with_span(Span::Operand(0)),
+        );
     }
-    if !arg2.is_some_and(|tok| tok.is_memory_address()) {
-        return Err(format!(
-            "LD requires RHS to be a Memory address at line {}",
-            line_num
-        ));
+    if !arg2.is_some_and(|tok| tok.is_memory_address() || tok.is_srcall()) {
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            "LD requires RHS to be a Memory address or label",
+        )
+        .with_span(Span::Operand(1)));
     }
     if arg2.unwrap().get_num() > 2047 {
-        return Err(MMAFAIL.to_string());
+        return Err(Diagnostic::error(codes::OUT_OF_RANGE, MMAFAIL).with_span(Span::Operand(1)));
     }
     Ok(())
 }
 
-fn st_args(arg1: Option<&Token>, arg2: Option<&Token>, line_num: u32) -> Result<(), String> {
-    if !arg1.is_some_and(|tok| tok.is_register_pointer() || tok.is_memory_address()) {
-        return Err(format!(
-            "ST requires LHS to be a Register pointer or Memory address at line {}",
-            line_num
-        ));
+fn st_args(arg1: Option<&Token>, arg2: Option<&Token>) -> Result<(), Diagnostic> {
+    if !arg1
+        .is_some_and(|tok| tok.is_register_pointer() || tok.is_memory_address() || tok.is_srcall())
+    {
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            "ST requires LHS to be a Register pointer, Memory address or label",
+        )
+        .with_span(Span::Operand(0)));
     }
     if !arg2.is_some_and(|tok| tok.is_register()) {
-        return Err(format!(
-            "ST requires RHS to be a Register at line {}",
-            line_num
-        ));
+        return Err(
+            Diagnostic::error(codes::OPERANDS, "ST requires RHS to be a Register")
+                .with_span(Span::Operand(1)),
+        );
     }
     if arg1.unwrap().get_num() > 2047 {
-        return Err(MMAFAIL.to_string());
+        return Err(Diagnostic::error(codes::OUT_OF_RANGE, MMAFAIL).with_span(Span::Operand(0)));
     }
     Ok(())
 }
 
-fn mov_args(arg1: Option<&Token>, arg2: Option<&Token>, line_num: u32) -> Result<(), String> {
+fn mov_args(arg1: Option<&Token>, arg2: Option<&Token>) -> Result<(), Diagnostic> {
     if !arg1.is_some_and(|tok| tok.is_register()) {
-        return Err(format!(
-            "MOV requires LHS to be a Register at line {}",
-            line_num
-        ));
+        return Err(
+            Diagnostic::error(codes::OPERANDS, "MOV requires LHS to be a Register")
+                .with_span(Span::Operand(0)),
+        );
     }
     if !arg2.is_some_and(|tok| {
         tok.is_register()
@@ -197,20 +185,19 @@ fn mov_args(arg1: Option<&Token>, arg2: Option<&Token>, line_num: u32) -> Result
             || tok.is_register_pointer()
             || tok.is_memory_address_pointer()
     }) {
-        return Err(format!(
-            "MOV requires RHS to be a Register, literal, register pointer, or memory address pointer at line {}",
-            line_num
-        ));
+        return Err(Diagnostic::error(codes::OPERANDS, "MOV requires RHS to be a Register, literal, register pointer, or memory address pointer").with_span(Span::Operand(1)));
     }
     match arg2 {
         /*Some(tok) if tok.is_literal() => {
             if tok.get_num() > 127 || tok.get_num() < -127 {
-                return Err(LITFAIL.to_string());
+                return Err(Diagnostic::error(codes::OUT_OF_RANGE, LITFAIL).with_span(Span::Operand(1)));
             }
         }*/
         Some(tok) if tok.is_memory_address_pointer() => {
             if tok.get_num() > 127 {
-                return Err(MMAFAIL.to_string());
+                return Err(
+                    Diagnostic::error(codes::OUT_OF_RANGE, MMAFAIL).with_span(Span::Operand(1))
+                );
             }
         }
         _ => {
@@ -220,49 +207,52 @@ fn mov_args(arg1: Option<&Token>, arg2: Option<&Token>, line_num: u32) -> Result
     Ok(())
 }
 
-fn int_args(arg1: Option<&Token>, line_num: u32) -> Result<(), String> {
+fn int_args(arg1: Option<&Token>) -> Result<(), Diagnostic> {
     if !arg1.is_some_and(|tok| tok.is_literal()) {
-        return Err(format!(
-            "INT requires SRC to be a Literal at line {}",
-            line_num
-        ));
+        return Err(
+            Diagnostic::error(codes::OPERANDS, "INT requires SRC to be a Literal")
+                .with_span(Span::Operand(0)),
+        );
     }
     if arg1.unwrap().get_num() > 2047 || arg1.unwrap().get_num() < -1 {
-        return Err("invalid interrupt number".to_string());
+        return Err(
+            Diagnostic::error(codes::OUT_OF_RANGE, "invalid interrupt number")
+                .with_span(Span::Operand(0)),
+        );
     }
     Ok(())
 }
 
-fn push_args(arg1: Option<&Token>, line_num: u32) -> Result<(), String> {
+fn push_args(arg1: Option<&Token>) -> Result<(), Diagnostic> {
     if !arg1.is_some_and(|tok| tok.is_register() || tok.is_literal()) {
-        return Err(format!(
-            "PUSH requires SRC to be a Register or Literal at line {}",
-            line_num
-        ));
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            "PUSH requires SRC to be a Register or Literal",
+        )
+        .with_span(Span::Operand(0)));
     }
     match arg1 {
-        Some(tok) if tok.is_literal() => {
-            if tok.get_num() > 1023 || tok.get_num() < -1023 {
-                return Err(LITFAIL.to_string());
-            }
+        Some(tok) if tok.is_literal() && (tok.get_num() > 1023 || tok.get_num() < -1023) => {
+            return Err(Diagnostic::error(codes::OUT_OF_RANGE, LITFAIL).with_span(Span::Operand(0)));
         }
         _ => (),
     }
     Ok(())
 }
 
-fn jump_args(arg1: Option<&Token>, line_num: u32) -> Result<(), String> {
+fn jump_args(arg1: Option<&Token>) -> Result<(), Diagnostic> {
     if !arg1
         .is_some_and(|tok| tok.is_register_pointer() || tok.is_memory_address() || tok.is_srcall())
     {
-        return Err(format!(
-            "JMP/JZ/JO requires DEST to be a Register pointer, Memory address, or SRCall at line {}",
-            line_num
-        ));
+        return Err(Diagnostic::error(
+            codes::OPERANDS,
+            "JMP/JZ/JO requires DEST to be a Register pointer, Memory address, or SRCall",
+        )
+        .with_span(Span::Operand(0)));
     }
     match arg1 {
         Some(tok) if tok.is_memory_address() && tok.get_num() > 2047 => {
-            return Err(MMAFAIL.to_string());
+            return Err(Diagnostic::error(codes::OUT_OF_RANGE, MMAFAIL).with_span(Span::Operand(0)));
         }
         _ => (),
     }
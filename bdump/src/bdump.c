@@ -21,6 +21,8 @@ int main(int argc, char *argv[]) {
         return EXIT_FAILURE;
     }
 
+    skip_header(input);
+
     uint8_t buffer[2]; // Buffer to hold two bytes
     while (fread(buffer, sizeof(uint8_t), 2, input) == 2) {
         uint16_t instruction = (buffer[0] << 8) | buffer[1];
@@ -142,3 +144,48 @@ CLI parse_arguments(int argc, char *argv[]) {
     }
     return opts;
 }
+
+int read_word(FILE *input) {
+    uint8_t buffer[2];
+    if (fread(buffer, sizeof(uint8_t), 2, input) != 2) {
+        return -1;
+    }
+    return (buffer[0] << 8) | buffer[1];
+}
+
+/*
+ * Executables written by basm start with "BIMG" and a header giving the entry
+ * point, stack and segments. The header is printed as .start/.ssp/.sbp
+ * directives and skipped, leaving the words of the segments. Raw images have
+ * no header and are read from the start.
+ */
+void skip_header(FILE *input) {
+    char magic[4];
+    if (fread(magic, 1, 4, input) != 4 || memcmp(magic, "BIMG", 4) != 0) {
+        rewind(input);
+        return;
+    }
+    int version = read_word(input);
+    int flags = read_word(input);
+    int entry = read_word(input);
+    int ssp = read_word(input);
+    int sbp = read_word(input);
+    int directives[3] = {1 << 9 | entry, 2 << 9 | ssp, 3 << 9 | sbp};
+    int present[3] = {1, flags & 1, flags & 2};
+    for (int i = 0; i < 3; i++) {
+        if (present[i]) {
+            Instruction ins = parse_instruction(directives[i]);
+            print_instruction(&ins);
+        }
+    }
+    if (version == 1) {
+        read_word(input); // word count
+        return;
+    }
+    read_word(input); // checksum
+    read_word(input);
+    int segments = read_word(input);
+    for (int i = 0; i < segments * 3; i++) {
+        read_word(input);
+    }
+}
@@ -38,6 +38,8 @@ void print_instruction(Instruction *s);
 void print_help(char *bin);
 void print(const char *format, ...);
 char *match_opcode(Instruction *s);
+int read_word(FILE *input);
+void skip_header(FILE *input);
 int main(int argc, char *argv[]);
 #pragma once
 #endif
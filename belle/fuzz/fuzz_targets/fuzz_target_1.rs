@@ -1,12 +1,12 @@
 #![no_main]
 
-use belle::CPU;
+use belle::Machine;
 use libfuzzer_sys::fuzz_target;
 extern crate belle;
 
 fuzz_target!(|data: [i16; 2]| {
     if !data.is_empty() {
-        let mut cpu = CPU::new();
+        let mut cpu = Machine::new().fuzz(true).build();
 
         for instruction in &data {
             cpu.ir = *instruction;
@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) 2024 BlueGummi
+ * All rights reserved.
+ *
+ * This code is licensed under the BSD 3-Clause License.
+ */
+use belle::{executable_source, raw_source, Executable};
+use clap::Parser;
+use colored::Colorize;
+use std::fs;
+use std::process;
+
+/// Command line arguments
+#[derive(Parser)]
+#[command(name = "belle-dis")]
+#[command(version = "0.1.0")]
+#[command(author = "gummi")]
+#[command(about = "The disassembler for BELLE", long_about = None)]
+struct Args {
+    /// Executable to disassemble
+    file: String,
+
+    /// Output file for source, printed if not given
+    #[clap(short = 'o', long)]
+    output: Option<String>,
+
+    /// Read a raw image written with `basm --raw`
+    #[clap(short = 'r', long, default_value_t = false)]
+    raw: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let bytes = match fs::read(&args.file) {
+        Ok(bytes) => bytes,
+        Err(e) => fail(&format!("cannot read {}: {e}", args.file)),
+    };
+    if bytes.len() % 2 != 0 {
+        fail(&format!("{} is not a whole number of words", args.file));
+    }
+    let words: Vec<i16> = bytes
+        .chunks(2)
+        .map(|chunk| i16::from_be_bytes([chunk[0], chunk[1]]))
+        .collect();
+
+    let source = if Executable::is_executable(&words) {
+        match Executable::parse(&words) {
+            Ok(executable) => executable_source(&executable),
+            Err(e) => fail(&format!("{}: {e}", args.file)),
+        }
+    } else if args.raw {
+        raw_source(&words)
+    } else {
+        fail(&format!(
+            "{} is not a BELLE executable, use --raw to read a raw image",
+            args.file
+        ))
+    };
+
+    match &args.output {
+        Some(output) => {
+            if let Err(e) = fs::write(output, source) {
+                fail(&format!("could not write {output}: {e}"));
+            }
+        }
+        None => print!("{source}"),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{} {message}", "error:".red());
+    process::exit(1);
+}
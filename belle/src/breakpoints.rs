@@ -0,0 +1,407 @@
+use crate::{parse_address, DebugSymbols, CPU};
+use std::fmt;
+
+/// What a condition compares a number with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    /// `r0` to `r7`
+    Register(u8),
+    Pc,
+    Sp,
+    Bp,
+    /// `$N` or `[N]`
+    Memory(u16),
+}
+
+impl Operand {
+    fn parse(text: &str, symbols: Option<&DebugSymbols>) -> Option<Operand> {
+        let text = text.trim().to_lowercase();
+        match text.as_str() {
+            "pc" => return Some(Operand::Pc),
+            "sp" => return Some(Operand::Sp),
+            "bp" => return Some(Operand::Bp),
+            _ => (),
+        }
+        if let Some(n) = text.strip_prefix('r') {
+            return n.parse().ok().filter(|&n| n < 8).map(Operand::Register);
+        }
+        let address = text
+            .strip_prefix('$')
+            .or_else(|| text.strip_prefix('[').and_then(|t| t.strip_suffix(']')))?;
+        parse_address(address, symbols).map(Operand::Memory)
+    }
+
+    /// The operand's value, or `None` for an empty memory address
+    fn value(self, cpu: &CPU) -> Option<f32> {
+        match self {
+            Operand::Register(n @ 0..=3) => Some(cpu.int_reg[usize::from(n)].into()),
+            Operand::Register(n @ 4..=5) => Some(cpu.uint_reg[usize::from(n) - 4].into()),
+            Operand::Register(n) => Some(cpu.float_reg[usize::from(n) - 6]),
+            Operand::Pc => Some(cpu.pc.into()),
+            Operand::Sp => Some(cpu.sp.into()),
+            Operand::Bp => Some(cpu.bp.into()),
+            Operand::Memory(address) => cpu.read_memory(usize::from(address)).map(f32::from),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(n) => write!(f, "r{n}"),
+            Operand::Pc => write!(f, "pc"),
+            Operand::Sp => write!(f, "sp"),
+            Operand::Bp => write!(f, "bp"),
+            Operand::Memory(address) => write!(f, "${address}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    GreaterOrEqual,
+    Less,
+    Greater,
+}
+
+// two character operators come first, so `<=` is not read as `<`
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (symbol, _) = COMPARISONS.iter().find(|(_, c)| c == self).unwrap();
+        write!(f, "{symbol}")
+    }
+}
+
+/// A comparison that must hold for a breakpoint to stop the program, such as
+/// `r0 == 5` or `$200 >= -3`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: f32,
+}
+
+impl Condition {
+    /// Reads a condition, looking up labels used as addresses in `symbols`
+    pub fn parse(text: &str, symbols: Option<&DebugSymbols>) -> Result<Condition, String> {
+        let Some((index, symbol, comparison)) = COMPARISONS
+            .iter()
+            .find_map(|&(symbol, c)| text.find(symbol).map(|index| (index, symbol, c)))
+        else {
+            return Err(format!(
+                "'{text}' is not a condition, expected a comparison such as r0 == 5"
+            ));
+        };
+        let (left, right) = (&text[..index], &text[index + symbol.len()..]);
+        let operand = Operand::parse(left, symbols).ok_or_else(|| {
+            format!(
+                "'{}' is not a register, pc, sp, bp or memory address",
+                left.trim()
+            )
+        })?;
+        let value = right
+            .trim()
+            .parse()
+            .map_err(|_| format!("'{}' is not a number", right.trim()))?;
+        Ok(Condition {
+            operand,
+            comparison,
+            value,
+        })
+    }
+
+    #[must_use]
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        let Some(left) = self.operand.value(cpu) else {
+            return false;
+        };
+        match self.comparison {
+            Comparison::Equal => left == self.value,
+            Comparison::NotEqual => left != self.value,
+            Comparison::LessOrEqual => left <= self.value,
+            Comparison::GreaterOrEqual => left >= self.value,
+            Comparison::Less => left < self.value,
+            Comparison::Greater => left > self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.operand, self.comparison, self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakpointKind {
+    /// Stops before the instruction at the address runs
+    Breakpoint,
+    /// Stops after an instruction stores to the address
+    Watchpoint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakpointKind,
+    pub address: u16,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+    /// Times the breakpoint has stopped the program
+    pub hits: u32,
+}
+
+impl Breakpoint {
+    fn fires(&self, cpu: &CPU) -> bool {
+        self.enabled && self.condition.is_none_or(|c| c.holds(cpu))
+    }
+
+    /// The breakpoint as the debugger shows it, with the source line it is
+    /// on when `symbols` know it
+    #[must_use]
+    pub fn describe(&self, symbols: Option<&DebugSymbols>) -> String {
+        let kind = match self.kind {
+            BreakpointKind::Breakpoint => "Breakpoint",
+            BreakpointKind::Watchpoint => "Watchpoint",
+        };
+        let mut text = format!("{kind} {} at {}", self.id, self.address);
+        if let Some(source) = symbols.and_then(|s| s.describe(self.address)) {
+            text += &format!(" ({source})");
+        }
+        if let Some(condition) = &self.condition {
+            text += &format!(" if {condition}");
+        }
+        text
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(None))
+    }
+}
+
+/// The breakpoints and watchpoints set in the debugger, numbered from 1 in
+/// the order they were set
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    #[must_use]
+    pub fn new() -> Breakpoints {
+        Breakpoints::default()
+    }
+
+    /// Adds a breakpoint, returning its number
+    pub fn add(
+        &mut self,
+        kind: BreakpointKind,
+        address: u16,
+        condition: Option<Condition>,
+    ) -> usize {
+        self.next_id += 1;
+        self.list.push(Breakpoint {
+            id: self.next_id,
+            kind,
+            address,
+            condition,
+            enabled: true,
+            hits: 0,
+        });
+        self.next_id
+    }
+
+    /// Turns a breakpoint on or off
+    pub fn toggle(&mut self, id: usize) -> Option<&Breakpoint> {
+        let breakpoint = self.list.iter_mut().find(|b| b.id == id)?;
+        breakpoint.enabled = !breakpoint.enabled;
+        Some(breakpoint)
+    }
+
+    pub fn delete(&mut self, id: usize) -> Option<Breakpoint> {
+        let index = self.list.iter().position(|b| b.id == id)?;
+        Some(self.list.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// The breakpoint that stops the program before the instruction at the
+    /// program counter runs
+    #[must_use]
+    pub fn stops_before(&self, cpu: &CPU) -> Option<&Breakpoint> {
+        self.list
+            .iter()
+            .find(|b| b.kind == BreakpointKind::Breakpoint && b.address == cpu.pc && b.fires(cpu))
+    }
+
+    /// The watchpoint that stops the program after the clock cycle it just
+    /// ran stored to a watched address
+    #[must_use]
+    pub fn stops_after(&self, cpu: &CPU) -> Option<&Breakpoint> {
+        self.list.iter().find(|b| {
+            b.kind == BreakpointKind::Watchpoint
+                && cpu.stores.contains(&usize::from(b.address))
+                && b.fires(cpu)
+        })
+    }
+
+    /// Like [`Breakpoints::stops_before`], counting the hit
+    pub fn hit_before(&mut self, cpu: &CPU) -> Option<&Breakpoint> {
+        let id = self.stops_before(cpu)?.id;
+        self.count_hit(id)
+    }
+
+    /// Like [`Breakpoints::stops_after`], counting the hit
+    pub fn hit_after(&mut self, cpu: &CPU) -> Option<&Breakpoint> {
+        let id = self.stops_after(cpu)?.id;
+        self.count_hit(id)
+    }
+
+    fn count_hit(&mut self, id: usize) -> Option<&Breakpoint> {
+        let breakpoint = self.list.iter_mut().find(|b| b.id == id)?;
+        breakpoint.hits += 1;
+        Some(breakpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    fn condition(text: &str) -> Result<Condition, String> {
+        Condition::parse(text, None)
+    }
+
+    #[test]
+    fn conditions_parse() {
+        assert_eq!(
+            condition("r0 == 5"),
+            Ok(Condition {
+                operand: Operand::Register(0),
+                comparison: Comparison::Equal,
+                value: 5.0,
+            })
+        );
+        assert_eq!(condition("$200>=-3").unwrap().to_string(), "$200 >= -3");
+        assert_eq!(condition("[7] <= 1").unwrap().operand, Operand::Memory(7));
+        assert_eq!(condition("PC != 0").unwrap().operand, Operand::Pc);
+        assert_eq!(condition("r6 < 1.5").unwrap().value, 1.5);
+        assert_eq!(
+            condition("r0 5"),
+            Err("'r0 5' is not a condition, expected a comparison such as r0 == 5".to_string())
+        );
+        assert_eq!(
+            condition("r8 > 1"),
+            Err("'r8' is not a register, pc, sp, bp or memory address".to_string())
+        );
+        assert_eq!(condition("sp == x"), Err("'x' is not a number".to_string()));
+
+        let symbols = DebugSymbols::parse("belle-symbols 1\nlabel 300 count\n").unwrap();
+        let parsed = Condition::parse("$count == 2", Some(&symbols)).unwrap();
+        assert_eq!(parsed.operand, Operand::Memory(300));
+        assert!(condition("$count == 2").is_err());
+    }
+
+    #[test]
+    fn conditions_hold() {
+        let mut cpu = Machine::new().build();
+        cpu.int_reg[2] = -4;
+        cpu.uint_reg[1] = 9;
+        cpu.float_reg[0] = 0.5;
+        cpu.memory[300] = Some(2);
+        let holds = |text: &str| condition(text).unwrap().holds(&cpu);
+        assert!(holds("r2 < -3"));
+        assert!(holds("r5 == 9"));
+        assert!(holds("r6 > 0.25"));
+        assert!(holds("$300 == 2"));
+        assert!(!holds("$300 != 2"));
+        // empty and out of range addresses never hold
+        assert!(!holds("$301 == 0"));
+        assert!(!holds("$65535 != 1"));
+    }
+
+    #[test]
+    fn hits_are_counted_for_enabled_breakpoints() {
+        let mut cpu = Machine::new().build();
+        cpu.pc = 100;
+        let mut breakpoints = Breakpoints::new();
+        let off = breakpoints.add(BreakpointKind::Breakpoint, 100, None);
+        let conditional = breakpoints.add(
+            BreakpointKind::Breakpoint,
+            100,
+            Some(condition("r0 == 1").unwrap()),
+        );
+        breakpoints.toggle(off);
+
+        assert!(breakpoints.hit_before(&cpu).is_none());
+        cpu.int_reg[0] = 1;
+        for _ in 0..2 {
+            assert_eq!(
+                breakpoints.hit_before(&cpu).map(|b| b.id),
+                Some(conditional)
+            );
+        }
+        cpu.pc = 101;
+        assert!(breakpoints.hit_before(&cpu).is_none());
+
+        let hits: Vec<u32> = breakpoints.iter().map(|b| b.hits).collect();
+        assert_eq!(hits, [0, 2]);
+        assert_eq!(
+            breakpoints.delete(conditional).unwrap().to_string(),
+            "Breakpoint 2 at 100 if r0 == 1"
+        );
+        assert_eq!(breakpoints.iter().count(), 1);
+    }
+
+    #[test]
+    fn watchpoints_fire_after_a_store() {
+        let mut cpu = Machine::new().build();
+        // mov r0, #7; st [50], r0; st [51], r0; hlt
+        cpu.load_binary(&vec![0xE107_u16 as i16, 0x7190, 0x7198, 0]);
+        cpu.running = true;
+        let mut breakpoints = Breakpoints::new();
+        let watch = breakpoints.add(
+            BreakpointKind::Watchpoint,
+            51,
+            Some(condition("$51 == 7").unwrap()),
+        );
+        // a breakpoint at the same address does not watch it
+        breakpoints.add(BreakpointKind::Breakpoint, 50, None);
+        let mut fired = Vec::new();
+        while cpu.running {
+            cpu.step().unwrap();
+            if let Some(breakpoint) = breakpoints.hit_after(&cpu) {
+                fired.push((breakpoint.id, cpu.ip));
+            }
+        }
+        assert_eq!(fired, [(watch, 102)]);
+        assert_eq!(cpu.memory[51], Some(7));
+    }
+}
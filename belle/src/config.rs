@@ -1,5 +1,11 @@
+use belle::{
+    Framebuffer, KeyQueue, KeyboardDevice, Machine, TerminalConsole, TimerDevice,
+    FRAMEBUFFER_ADDRESS, FRAMEBUFFER_SIZE, KEYBOARD_ADDRESS, KEYBOARD_INTERRUPT, TIMER_ADDRESS,
+    TIMER_INTERRUPT,
+};
 use clap::Parser;
 use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex};
 
 pub static CONFIG: Lazy<Cli> = Lazy::new(declare_config);
 
@@ -10,7 +16,7 @@ pub static CONFIG: Lazy<Cli> = Lazy::new(declare_config);
 #[command(about = "BELLE - The Big Endian, Low Level Emulator", long_about = None)]
 pub struct Cli {
     /// Path to input
-    #[clap(required = true)]
+    #[clap(required_unless_present = "dap", default_value = "")]
     pub file: String,
 
     /// Verbose output
@@ -36,31 +42,98 @@ pub struct Cli {
     /// Fuzzing mode
     #[clap(short = 'f', long, default_value_t = false)]
     pub fuzz: bool,
+
+    /// Load a raw image without a header, as written by `basm --raw`
+    #[clap(short = 'r', long, default_value_t = false)]
+    pub raw: bool,
+
+    /// Map the timer at addresses 500 to 503
+    #[clap(long, default_value_t = false)]
+    pub timer: bool,
+
+    /// Draw the framebuffer in the terminal while the program runs
+    #[clap(short = 's', long, default_value_t = false)]
+    pub screen: bool,
+
+    /// Print the text on the framebuffer when the program halts
+    #[clap(long, default_value_t = false)]
+    pub dump_screen: bool,
+
+    /// Keep the terminal in raw mode and queue key presses for the program
+    #[clap(short = 'k', long, default_value_t = false)]
+    pub keyboard: bool,
+
+    /// Wait for GDB on a port, a TCP address or a Unix socket path, and let it run the program
+    #[clap(long, value_name = "ADDRESS", conflicts_with = "debug")]
+    pub gdb: Option<String>,
+
+    /// Speak the Debug Adapter Protocol on stdin and stdout, for debugging from an editor
+    #[clap(long, default_value_t = false, conflicts_with_all = ["debug", "verbose", "pretty", "gdb"])]
+    pub dap: bool,
+
+    /// Run the debugger commands in a file before reading any from stdin (can be repeated)
+    #[clap(short = 'x', long, value_name = "FILE", requires = "debug")]
+    pub command: Vec<String>,
+
+    /// Exit the debugger once its command files have run, with the program's exit status
+    #[clap(long, default_value_t = false, requires = "debug")]
+    pub batch: bool,
 }
 
-fn default_config() -> Cli {
-    Cli {
-        file: "".to_string(),
-        verbose: false,
-        debug: false,
-        quiet: false,
-        time_delay: Some(0),
-        pretty: false,
-        fuzz: true,
+impl Cli {
+    /// The options the CPU is built with, and the devices it has. Each device
+    /// is only mapped when a flag asks for it, so programs that do not use it
+    /// keep those addresses as ordinary memory.
+    #[must_use]
+    pub fn machine(&self, framebuffer: &Arc<Mutex<Framebuffer>>, keys: &Arc<KeyQueue>) -> Machine {
+        let mut machine = Machine::new()
+            .time_delay(self.time_delay.unwrap_or(0))
+            .verbose(self.verbose)
+            .debug(self.debug)
+            .quiet(self.quiet)
+            .pretty(self.pretty)
+            .fuzz(self.fuzz)
+            .record(self.debug || self.verbose);
+        if self.timer {
+            let timer = TimerDevice::new(Some(machine.interrupt_line(TIMER_INTERRUPT)));
+            machine = machine.device("timer", TIMER_ADDRESS, 4, Arc::new(Mutex::new(timer)));
+        }
+        if self.screen || self.dump_screen {
+            machine = machine.device(
+                "framebuffer",
+                FRAMEBUFFER_ADDRESS,
+                FRAMEBUFFER_SIZE,
+                framebuffer.clone(),
+            );
+        }
+        if self.keyboard {
+            let console = TerminalConsole::with_keyboard(Arc::clone(keys));
+            let keyboard = KeyboardDevice::new(
+                Arc::clone(keys),
+                Some(machine.interrupt_line(KEYBOARD_INTERRUPT)),
+            );
+            machine = machine.console(Arc::new(Mutex::new(console))).device(
+                "keyboard",
+                KEYBOARD_ADDRESS,
+                2,
+                Arc::new(Mutex::new(keyboard)),
+            );
+        }
+        machine
+    }
+
+    /// The framebuffer the program draws on, shown in the terminal with `--screen`
+    #[must_use]
+    pub fn framebuffer(&self) -> Arc<Mutex<Framebuffer>> {
+        let framebuffer = if self.screen {
+            Framebuffer::terminal()
+        } else {
+            Framebuffer::new()
+        };
+        Arc::new(Mutex::new(framebuffer))
     }
 }
 
 pub fn declare_config() -> Cli {
-    match Cli::try_parse() {
-        Ok(cli) => Cli {
-            file: cli.file,
-            verbose: cli.verbose,
-            debug: cli.debug,
-            quiet: cli.quiet,
-            time_delay: Some(cli.time_delay.unwrap_or(0)),
-            pretty: cli.pretty,
-            fuzz: cli.fuzz,
-        },
-        Err(_) => default_config(),
-    }
+    Cli::parse()
 }
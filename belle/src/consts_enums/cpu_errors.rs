@@ -35,6 +35,16 @@ impl UnrecoverableError {
             UnrecoverableError::StackUnderflow(loc, msg) => ("Stack underflow", *loc, msg),
         }
     }
+
+    /// The error followed by the source line it happened on, when `symbols`
+    /// know it. `{:#}` works as it does for the error itself.
+    #[must_use]
+    pub fn located<'a>(&'a self, symbols: Option<&'a DebugSymbols>) -> Located<'a> {
+        Located {
+            error: self,
+            symbols,
+        }
+    }
 }
 
 impl RecoverableError {
@@ -47,6 +57,7 @@ impl RecoverableError {
     }
 }
 
+/// `{:#}` adds the message and the address the error happened at
 impl fmt::Display for UnrecoverableError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (err_type, location, msg) = self.details();
@@ -54,34 +65,50 @@ impl fmt::Display for UnrecoverableError {
         write!(f, "{}", err_type.bold().red())?;
 
         if let Some(s) = msg {
-            if CONFIG.debug || CONFIG.verbose {
+            if f.alternate() {
                 write!(f, ": {}", s.magenta())?;
             }
         }
-        if CONFIG.debug || CONFIG.verbose {
+        if f.alternate() {
             writeln!(f, " at memory address {}", location.to_string().green())?;
         }
         Ok(())
     }
 }
 
-impl fmt::Display for RecoverableError {
+/// An error shown with its source line, see [`UnrecoverableError::located`]
+pub struct Located<'a> {
+    error: &'a UnrecoverableError,
+    symbols: Option<&'a DebugSymbols>,
+}
+
+impl fmt::Display for Located<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if !CONFIG.debug && !CONFIG.verbose {
-            return Ok(());
+        let (_, location, _) = self.error.details();
+        if f.alternate() {
+            write!(f, "{:#}", self.error)?;
+        } else {
+            write!(f, "{}", self.error)?;
+        }
+        if let Some(source) = self.symbols.and_then(|s| s.describe(location)) {
+            if !f.alternate() {
+                writeln!(f)?;
+            }
+            write!(f, "  in {}", source.bold())?;
         }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RecoverableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let (err_type, location, msg) = self.details();
         write!(f, "{}: ", "RECOVERABLE ERROR:".yellow())?;
         write!(f, "{}", err_type.yellow())?;
 
         if let Some(s) = msg {
-            if CONFIG.debug || CONFIG.verbose {
-                write!(f, ": {}", s.magenta())?;
-            }
-        }
-        if CONFIG.debug || CONFIG.verbose {
-            writeln!(f, " at memory address {}", location.to_string().green())?;
+            write!(f, ": {}", s.magenta())?;
         }
-        Ok(())
+        writeln!(f, " at memory address {}", location.to_string().green())
     }
 }
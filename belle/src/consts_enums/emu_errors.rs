@@ -1,23 +1,20 @@
-use crate::CONFIG;
 use colored::Colorize;
 use std::fmt;
 pub enum EmuError {
-    FileNotFound(),
-    IsDirectory(),
+    FileNotFound(String),
+    IsDirectory(String),
     MemoryOverflow(),
     Duplicate(String),
     ReadFail(String),
     Impossible(String),
+    InvalidExecutable(String),
 }
 impl EmuError {
     pub fn err(&self) {
-        if CONFIG.quiet {
-            return;
-        }
         eprint!("{} ", "Emulator Error:".red());
         match self {
-            EmuError::FileNotFound() => {
-                eprintln!("File {} not found", CONFIG.file.to_string().green());
+            EmuError::FileNotFound(file) => {
+                eprintln!("File {} not found", file.green());
             }
             EmuError::MemoryOverflow() => {
                 eprintln!("{}", "Memory will overflow".red());
@@ -35,8 +32,11 @@ impl EmuError {
             EmuError::Impossible(s) => {
                 eprintln!("{}: {}", "Configuration combination not possible".red(), s);
             }
-            EmuError::IsDirectory() => {
-                eprintln!("{} is a directory", CONFIG.file.to_string().green());
+            EmuError::IsDirectory(file) => {
+                eprintln!("{} is a directory", file.green());
+            }
+            EmuError::InvalidExecutable(s) => {
+                eprintln!("{}: {}", "Cannot load executable".red(), s);
             }
         }
         if let EmuError::ReadFail(_) = self {
@@ -48,12 +48,12 @@ impl EmuError {
 impl fmt::Display for EmuError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            EmuError::FileNotFound() => {
+            EmuError::FileNotFound(file) => {
                 write!(
                     f,
                     "{} File {} not found",
                     "Emulator Error:".red(),
-                    CONFIG.file.to_string().green(),
+                    file.green(),
                 )
             }
             EmuError::MemoryOverflow() => {
@@ -83,12 +83,21 @@ impl fmt::Display for EmuError {
                     s,
                 )
             }
-            EmuError::IsDirectory() => {
+            EmuError::IsDirectory(file) => {
                 write!(
                     f,
                     "{} {} is a directory",
                     "Emulator Error:".red(),
-                    CONFIG.file.to_string().green(),
+                    file.green(),
+                )
+            }
+            EmuError::InvalidExecutable(s) => {
+                write!(
+                    f,
+                    "{} {}: {}",
+                    "Emulator Error:".red(),
+                    "Cannot load executable".red(),
+                    s,
                 )
             }
         }
@@ -0,0 +1,962 @@
+use crate::Argument::Literal;
+use crate::Instruction::{INT, JMP, JO, JZ, RET};
+use crate::{
+    bin_to_vec, decode, stack_depth, symbol_map_path, Breakpoint, BreakpointKind, Breakpoints,
+    Condition, DebugSymbols, Machine, MemoryConsole, UnrecoverableError, CPU, MEMORY_SIZE,
+};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The only thread there is
+const THREAD_ID: i64 = 1;
+
+/// How many clock cycles run between checks for a `pause` request, and for
+/// output to send
+const PAUSE_CHECK_INTERVAL: u32 = 1024;
+
+/// Variable references, for the scopes and the groups of registers in them
+const REGISTERS: i64 = 1;
+const FLAGS: i64 = 2;
+const SIGNED_REGISTERS: i64 = 3;
+const UINT_REGISTERS: i64 = 4;
+const FLOAT_REGISTERS: i64 = 5;
+
+/// Speaks the Debug Adapter Protocol on stdin and stdout until the client
+/// disconnects, debugging the program a `launch` request names on CPUs built
+/// by `machine`.
+///
+/// The program's `int` output is sent to the client as `output` events, and
+/// `int 9` reads no keys. Memory is shown as bytes, with each word at twice its
+/// address, high byte first, as the GDB stub does.
+pub fn run_dap(machine: Machine) -> io::Result<()> {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = io::stdin().lock();
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    Adapter::new(machine, messages, Box::new(io::stdout())).serve()
+}
+
+/// Reads one message, or `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            length = n.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Why the program stopped running
+enum Stop {
+    Halted,
+    Error(UnrecoverableError),
+    Breakpoint(Breakpoint),
+    Paused,
+    /// The step got where it was going
+    Done,
+}
+
+/// How far a request runs the program
+#[derive(Clone, Copy, PartialEq)]
+enum Resume {
+    Continue,
+    /// One instruction (`stepIn`)
+    Step,
+    /// One instruction, running jumps until they return (`next`)
+    Next,
+    /// Until the subroutine returns (`stepOut`)
+    Out,
+}
+
+struct Adapter {
+    messages: Receiver<Value>,
+    /// Where responses and events are written
+    output: Box<dyn Write>,
+    /// Requests that came in while the program was running
+    pending: VecDeque<Value>,
+    seq: i64,
+    machine: Machine,
+    console: Arc<Mutex<MemoryConsole>>,
+    cpu: Option<CPU>,
+    breakpoints: Breakpoints,
+    /// The breakpoints set in each source file, which `setBreakpoints` replaces
+    source_breakpoints: HashMap<String, Vec<usize>>,
+    program: String,
+    /// The `.asm` file given to `launch`, used for frames in a file of that name
+    source: Option<String>,
+    stop_on_entry: bool,
+    /// 0 if the client counts lines from 0
+    first_line: u32,
+    exited: bool,
+}
+
+impl Adapter {
+    fn new(machine: Machine, messages: Receiver<Value>, output: Box<dyn Write>) -> Adapter {
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        Adapter {
+            messages,
+            output,
+            pending: VecDeque::new(),
+            seq: 0,
+            machine: machine.console(console.clone()),
+            console,
+            cpu: None,
+            breakpoints: Breakpoints::new(),
+            source_breakpoints: HashMap::new(),
+            program: String::new(),
+            source: None,
+            stop_on_entry: false,
+            first_line: 1,
+            exited: false,
+        }
+    }
+
+    /// Answers requests until the client disconnects or goes away
+    fn serve(mut self) -> io::Result<()> {
+        loop {
+            let request = match self.pending.pop_front() {
+                Some(request) => request,
+                None => match self.messages.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if request["type"] != "request" {
+                continue;
+            }
+            if !self.handle(&request)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Answers `request`, returning whether to carry on
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let arguments = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or("");
+        if self.cpu.is_none() && !matches!(command, "initialize" | "launch" | "disconnect") {
+            self.fail(request, "No program has been launched")?;
+            return Ok(true);
+        }
+        match command {
+            "initialize" => {
+                if arguments["linesStartAt1"] == false {
+                    self.first_line = 0;
+                }
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsReadMemoryRequest": true,
+                        "supportsWriteMemoryRequest": true,
+                        "supportsConditionalBreakpoints": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                )?;
+            }
+            "launch" => {
+                if let Err(e) = self.launch(arguments) {
+                    self.fail(request, &e)?;
+                    return Ok(true);
+                }
+                self.respond(request, Value::Null)?;
+                self.event("initialized", Value::Null)?;
+            }
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                self.respond(request, body)?;
+            }
+            "setExceptionBreakpoints" => {
+                self.respond(request, json!({ "breakpoints": [] }))?;
+            }
+            "configurationDone" => {
+                self.respond(request, Value::Null)?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None, None)?;
+                } else {
+                    self.resume(Resume::Continue)?;
+                }
+            }
+            "threads" => {
+                self.respond(
+                    request,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "BELLE" }] }),
+                )?;
+            }
+            "stackTrace" => {
+                let body = self.stack_trace(arguments);
+                self.respond(request, body)?;
+            }
+            "scopes" => {
+                self.respond(
+                    request,
+                    json!({ "scopes": [
+                        { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                        { "name": "Flags", "variablesReference": FLAGS, "expensive": false },
+                    ]}),
+                )?;
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_i64().unwrap_or(0);
+                let variables = self.variables(reference);
+                self.respond(request, json!({ "variables": variables }))?;
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+                let mode = match command {
+                    "continue" => Resume::Continue,
+                    "next" => Resume::Next,
+                    "stepIn" => Resume::Step,
+                    _ => Resume::Out,
+                };
+                self.resume(mode)?;
+            }
+            // the program is not running, or `resume` would have taken this
+            "pause" => {
+                self.respond(request, Value::Null)?;
+                self.stopped("pause", None, None)?;
+            }
+            "readMemory" => match self.read_memory(arguments) {
+                Some(body) => self.respond(request, body)?,
+                None => self.fail(request, "Invalid memory reference")?,
+            },
+            "writeMemory" => match self.write_memory(arguments) {
+                Some(body) => self.respond(request, body)?,
+                None => self.fail(request, "Invalid memory reference or data")?,
+            },
+            "disconnect" | "terminate" => {
+                self.respond(request, Value::Null)?;
+                if command == "terminate" {
+                    self.event("terminated", Value::Null)?;
+                }
+                return Ok(command == "terminate");
+            }
+            _ => self.fail(request, &format!("'{command}' is not supported"))?,
+        }
+        Ok(true)
+    }
+
+    /// Loads the program and its symbols, from `program` and the optional
+    /// `symbols`, `source`, `raw` and `stopOnEntry` arguments
+    fn launch(&mut self, arguments: &Value) -> Result<(), String> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or("'program' is required")?
+            .to_string();
+        let raw = arguments["raw"].as_bool().unwrap_or(false);
+        let bin = bin_to_vec(&program, raw).map_err(|e| format!("{program}: {e}"))?;
+        let symbols = match arguments["symbols"].as_str() {
+            Some(path) => Some(DebugSymbols::load(path)?),
+            None => {
+                let path = symbol_map_path(&program);
+                if Path::new(&path).is_file() {
+                    Some(DebugSymbols::load(&path)?)
+                } else {
+                    None
+                }
+            }
+        };
+        let mut cpu = self.machine.build();
+        cpu.symbols = symbols.map(Arc::new);
+        cpu.load_binary(&bin);
+        if cpu.do_not_run {
+            return Err(format!("{program} could not be loaded"));
+        }
+        self.cpu = Some(cpu);
+        self.program = program;
+        self.source = arguments["source"].as_str().map(str::to_string);
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(())
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+        for id in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            self.breakpoints.delete(id);
+        }
+        let symbols = self.cpu.as_ref().and_then(|cpu| cpu.symbols.clone());
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        let requested = arguments["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for breakpoint in &requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as u32;
+            let source_line = line + 1 - self.first_line;
+            let condition = match breakpoint["condition"].as_str() {
+                Some(text) if !text.trim().is_empty() => {
+                    Condition::parse(text, symbols.as_deref()).map(Some)
+                }
+                _ => Ok(None),
+            };
+            let found = symbols
+                .as_ref()
+                .and_then(|symbols| symbols.line_address(&path, source_line));
+            match (found, condition) {
+                (Some((address, at)), Ok(condition)) => {
+                    let id = self
+                        .breakpoints
+                        .add(BreakpointKind::Breakpoint, address, condition);
+                    ids.push(id);
+                    results.push(json!({
+                        "id": id,
+                        "verified": true,
+                        "line": at + self.first_line - 1,
+                    }));
+                }
+                (None, _) => results.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code on or after this line",
+                })),
+                (_, Err(e)) => results.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": e,
+                })),
+            }
+        }
+        self.source_breakpoints.insert(path, ids);
+        json!({ "breakpoints": results })
+    }
+
+    /// Runs the program as far as `mode` says, and tells the client why it stopped
+    fn resume(&mut self, mode: Resume) -> io::Result<()> {
+        if self.exited {
+            return self.exit();
+        }
+        let mut cpu = self.cpu.take().unwrap();
+        let stop = self.run(&mut cpu, mode);
+        self.cpu = Some(cpu);
+        self.flush_output()?;
+        match stop {
+            Stop::Halted => self.exit(),
+            Stop::Error(e) => {
+                let symbols = self.cpu.as_ref().and_then(|cpu| cpu.symbols.clone());
+                let text = e.located(symbols.as_deref()).to_string();
+                self.stopped("exception", Some(&text), None)
+            }
+            Stop::Breakpoint(breakpoint) => self.stopped("breakpoint", None, Some(breakpoint.id)),
+            Stop::Paused => self.stopped("pause", None, None),
+            Stop::Done => self.stopped("step", None, None),
+        }
+    }
+
+    fn run(&mut self, cpu: &mut CPU, mode: Resume) -> Stop {
+        let word = cpu.memory.get(cpu.pc as usize).copied().flatten();
+        let depth = stack_depth(cpu);
+        let return_to = cpu.pc.wrapping_add(1);
+        let over_jump = mode == Resume::Next
+            && word.is_some_and(|word| matches!(decode(word), JMP(_) | JZ(_) | JO(_)));
+        let done = |cpu: &CPU| match mode {
+            Resume::Continue => false,
+            Resume::Step => true,
+            Resume::Next if over_jump => cpu.pc == return_to && stack_depth(cpu) == depth,
+            Resume::Next => true,
+            Resume::Out if depth == 0 => true,
+            Resume::Out => {
+                matches!(cpu.parse_instruction(), RET | INT(Literal(70)))
+                    && stack_depth(cpu) < depth
+            }
+        };
+        cpu.has_ran = true;
+        cpu.running = true;
+        let mut first = true;
+        let mut cycles: u32 = 0;
+        loop {
+            if !first {
+                if let Some(breakpoint) = self.breakpoints.hit_before(cpu) {
+                    return Stop::Breakpoint(breakpoint.clone());
+                }
+            }
+            first = false;
+            if let Err(e) = cpu.step() {
+                return Stop::Error(e);
+            }
+            if !cpu.running {
+                cpu.halted();
+                return Stop::Halted;
+            }
+            if done(cpu) {
+                return Stop::Done;
+            }
+            cycles = cycles.wrapping_add(1);
+            if cycles.is_multiple_of(PAUSE_CHECK_INTERVAL) {
+                // output is sent as it comes, rather than when the program stops
+                let _ = self.flush_output();
+                if self.pause_requested() {
+                    return Stop::Paused;
+                }
+            }
+        }
+    }
+
+    /// Whether the client asked to pause, or to stop debugging, while the
+    /// program was running. Other requests wait until it stops.
+    fn pause_requested(&mut self) -> bool {
+        let mut pause = false;
+        while let Ok(request) = self.messages.try_recv() {
+            match request["command"].as_str() {
+                Some("pause") => {
+                    let _ = self.respond(&request, Value::Null);
+                    pause = true;
+                }
+                Some("disconnect" | "terminate") => {
+                    self.pending.push_back(request);
+                    pause = true;
+                }
+                _ => self.pending.push_back(request),
+            }
+        }
+        pause
+    }
+
+    fn exit(&mut self) -> io::Result<()> {
+        let code = i32::from(self.cpu.as_ref().is_some_and(|cpu| cpu.err));
+        if !self.exited {
+            self.exited = true;
+            self.event("exited", json!({ "exitCode": code }))?;
+            self.event("terminated", Value::Null)?;
+        }
+        Ok(())
+    }
+
+    /// Sends what the program wrote as an `output` event
+    fn flush_output(&mut self) -> io::Result<()> {
+        let output = std::mem::take(&mut self.console.lock().unwrap().output);
+        if output.is_empty() {
+            return Ok(());
+        }
+        self.event("output", json!({ "category": "stdout", "output": output }))
+    }
+
+    fn stopped(
+        &mut self,
+        reason: &str,
+        description: Option<&str>,
+        breakpoint: Option<usize>,
+    ) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        if let Some(id) = breakpoint {
+            body["hitBreakpointIds"] = json!([id]);
+        }
+        self.event("stopped", body)
+    }
+
+    /// The program counter, then each return address on the stack, newest first
+    fn stack_trace(&self, arguments: &Value) -> Value {
+        let cpu = self.cpu.as_ref().unwrap();
+        let mut addresses = vec![cpu.pc];
+        if cpu.memory.get(cpu.bp as usize).is_some_and(Option::is_some) {
+            let stack: Vec<u16> = if cpu.sp <= cpu.bp {
+                (cpu.sp..=cpu.bp).collect()
+            } else {
+                (cpu.bp..=cpu.sp).rev().collect()
+            };
+            // a value on the stack is taken as a return address if it points
+            // at a jump, which is what pushed it
+            for value in stack
+                .into_iter()
+                .filter_map(|a| cpu.memory.get(a as usize).copied().flatten())
+            {
+                let jumps = cpu
+                    .memory
+                    .get(value as u16 as usize)
+                    .copied()
+                    .flatten()
+                    .is_some_and(|word| matches!(decode(word), JMP(_) | JZ(_) | JO(_)));
+                if jumps {
+                    addresses.push(value as u16);
+                }
+            }
+        }
+        let total = addresses.len();
+        let start = arguments["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match arguments["levels"].as_u64() {
+            Some(0) | None => total,
+            Some(n) => n as usize,
+        };
+        let symbols = cpu.symbols.as_deref();
+        let frames: Vec<Value> = addresses
+            .into_iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, address)| {
+                let name = symbols
+                    .and_then(|symbols| symbols.label_at(address))
+                    .unwrap_or_else(|| address.to_string());
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": memory_reference(address),
+                });
+                if let Some((file, line)) = symbols
+                    .as_ref()
+                    .and_then(|symbols| symbols.location(address))
+                {
+                    let path = self.source_path(file);
+                    let name = Path::new(&path)
+                        .file_name()
+                        .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+                    frame["source"] = json!({ "name": name, "path": path });
+                    frame["line"] = json!(line + self.first_line - 1);
+                    frame["column"] = json!(self.first_line);
+                }
+                frame
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": total })
+    }
+
+    /// Where the client finds a source file named in the symbols: the
+    /// `source` given to `launch` if it has the same name, or the file as
+    /// named, looked for next to the program too
+    fn source_path(&self, file: &str) -> String {
+        if let Some(source) = &self.source {
+            if Path::new(source).file_name() == Path::new(file).file_name() {
+                return source.clone();
+            }
+        }
+        let next_to_program = Path::new(&self.program)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(file);
+        [Path::new(file), next_to_program.as_path()]
+            .iter()
+            .find_map(|path| path.canonicalize().ok())
+            .map_or_else(
+                || file.to_string(),
+                |path| path.to_string_lossy().into_owned(),
+            )
+    }
+
+    /// The registers and flags, grouped as `display_state` prints them
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let cpu = self.cpu.as_ref().unwrap();
+        let value = |name: &str, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let pointer = |name: &str, address: u16| {
+            json!({
+                "name": name,
+                "value": address.to_string(),
+                "variablesReference": 0,
+                "memoryReference": memory_reference(address),
+            })
+        };
+        let group = |name: &str, value: String, reference: i64| json!({ "name": name, "value": value, "variablesReference": reference });
+        match reference {
+            REGISTERS => vec![
+                group(
+                    "Signed Integer Registers",
+                    format!("{:?}", cpu.int_reg),
+                    SIGNED_REGISTERS,
+                ),
+                group(
+                    "Uint registers",
+                    format!("{:?}", cpu.uint_reg),
+                    UINT_REGISTERS,
+                ),
+                group(
+                    "Float Registers",
+                    format!("{:?}", cpu.float_reg),
+                    FLOAT_REGISTERS,
+                ),
+                pointer("Program Counter", cpu.pc),
+                value("Instruction Register", format!("{:016b}", cpu.ir)),
+                value(
+                    "Disassembled Instruction",
+                    cpu.parse_instruction().to_string(),
+                ),
+                pointer("Stack pointer", cpu.sp),
+                pointer("Base pointer", cpu.bp),
+                pointer("Instruction pointer", cpu.ip),
+            ],
+            FLAGS => vec![
+                value("Running", cpu.running.to_string()),
+                value("Zero flag", cpu.zflag.to_string()),
+                value("Overflow flag", cpu.oflag.to_string()),
+                value("Remainder flag", cpu.rflag.to_string()),
+                value("Sign flag", cpu.sflag.to_string()),
+            ],
+            SIGNED_REGISTERS => (0..4)
+                .map(|n| value(&format!("r{n}"), cpu.int_reg[n].to_string()))
+                .collect(),
+            UINT_REGISTERS => (0..2)
+                .map(|n| value(&format!("r{}", n + 4), cpu.uint_reg[n].to_string()))
+                .collect(),
+            FLOAT_REGISTERS => (0..2)
+                .map(|n| value(&format!("r{}", n + 6), cpu.float_reg[n].to_string()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Option<Value> {
+        let cpu = self.cpu.as_ref().unwrap();
+        let start = memory_address(arguments)?;
+        let count = arguments["count"].as_u64()?;
+        let mut bytes = Vec::new();
+        for address in start..start + count {
+            let word = (address / 2) as usize;
+            if word >= MEMORY_SIZE {
+                break;
+            }
+            let value = cpu.memory[word].unwrap_or(0) as u16;
+            bytes.push(if address % 2 == 0 {
+                (value >> 8) as u8
+            } else {
+                value as u8
+            });
+        }
+        Some(json!({
+            "address": format!("0x{start:x}"),
+            "data": base64_encode(&bytes),
+            "unreadableBytes": count - bytes.len() as u64,
+        }))
+    }
+
+    fn write_memory(&mut self, arguments: &Value) -> Option<Value> {
+        let start = memory_address(arguments)?;
+        let bytes = base64_decode(arguments["data"].as_str()?)?;
+        if (start + bytes.len() as u64).div_ceil(2) > MEMORY_SIZE as u64 {
+            return None;
+        }
+        let cpu = self.cpu.as_mut().unwrap();
+        for (address, &byte) in (start..).zip(&bytes) {
+            let cell = &mut cpu.memory[(address / 2) as usize];
+            let value = cell.unwrap_or(0) as u16;
+            let value = if address % 2 == 0 {
+                value & 0x00FF | u16::from(byte) << 8
+            } else {
+                value & 0xFF00 | u16::from(byte)
+            };
+            *cell = Some(value as i16);
+        }
+        cpu.trace.resync();
+        Some(json!({ "bytesWritten": bytes.len() }))
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()
+    }
+}
+
+/// How the client refers to the byte at `address`, in the same terms as the
+/// memory views
+fn memory_reference(address: u16) -> String {
+    format!("0x{:x}", u32::from(address) * 2)
+}
+
+/// The byte address `memoryReference` and `offset` point at
+fn memory_address(arguments: &Value) -> Option<u64> {
+    let reference = arguments["memoryReference"].as_str()?;
+    let base = match reference.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => reference.parse().ok()?,
+    };
+    base.checked_add_signed(arguments["offset"].as_i64().unwrap_or(0))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let digit = BASE64.iter().position(|&d| d == c)? as u32;
+        n = (n << 6 | digit) & 0xFFFF;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basm::{Assembler, AssemblerOptions};
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    /// Everything the adapter writes, kept where the test can read it back
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Sends `requests` to an adapter, one after another, and returns every
+    /// message it sends back
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let (sender, messages) = mpsc::channel();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            sender.send(request).unwrap();
+        }
+        drop(sender);
+        let output = Shared::default();
+        Adapter::new(Machine::new(), messages, Box::new(output.clone()))
+            .serve()
+            .unwrap();
+        let bytes = output.0.lock().unwrap().clone();
+        let mut input = Cursor::new(bytes);
+        let mut replies = Vec::new();
+        while let Some(message) = read_message(&mut input).unwrap() {
+            replies.push(message);
+        }
+        replies
+    }
+
+    /// The replies as `command` for responses, with `!` when they failed, and
+    /// `event:reason` for events
+    fn summary(replies: &[Value]) -> Vec<String> {
+        replies
+            .iter()
+            .map(|reply| match reply["type"].as_str() {
+                Some("response") if reply["success"] == true => {
+                    reply["command"].as_str().unwrap().to_string()
+                }
+                Some("response") => format!("{}!", reply["command"].as_str().unwrap()),
+                _ => match reply["body"]["reason"].as_str() {
+                    Some(reason) => format!("{}:{reason}", reply["event"].as_str().unwrap()),
+                    None => reply["event"].as_str().unwrap().to_string(),
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn messages_are_framed_by_length() {
+        let text = "Content-Length: 13\r\n\r\n{\"seq\":1}\n   Content-Length: 2\r\nX: y\r\n\r\n{}";
+        let mut input = Cursor::new(text);
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        let mut input = Cursor::new("Content-Length: 3\r\n\r\n{{{");
+        assert!(read_message(&mut input).is_err());
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for bytes in [&b""[..], b"a", b"ab", b"abc", &[0, 0xff, 0x10, 0x80]] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base64_encode(b"belle"), "YmVsbGU=");
+        assert_eq!(base64_decode("!"), None);
+    }
+
+    #[test]
+    fn requests_wait_for_a_program() {
+        let replies = session(&[
+            json!({ "command": "threads" }),
+            json!({ "command": "launch", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": "/no/such/file" } }),
+            json!({ "command": "disconnect" }),
+        ]);
+        assert_eq!(
+            summary(&replies),
+            ["threads!", "launch!", "launch!", "disconnect"]
+        );
+        assert_eq!(replies[0]["message"], "No program has been launched");
+        assert_eq!(replies[1]["message"], "'program' is required");
+    }
+
+    #[test]
+    fn stepping_off_the_end_of_memory_is_an_error() {
+        let (_, messages) = mpsc::channel();
+        let mut adapter = Adapter::new(Machine::new(), messages, Box::new(io::sink()));
+        for mode in [Resume::Step, Resume::Next, Resume::Out] {
+            let mut cpu = Machine::new().build();
+            cpu.pc = u16::MAX;
+            assert!(matches!(adapter.run(&mut cpu, mode), Stop::Error(_)));
+        }
+    }
+
+    #[test]
+    fn debugging_an_example() {
+        let source = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../examples/fib.asm")
+            .canonicalize()
+            .unwrap();
+        let assembly =
+            Assembler::new(AssemblerOptions::default()).assemble(source.to_str().unwrap());
+        assert!(!assembly.has_errors());
+        let dir = std::env::temp_dir().join(format!("belle-dap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("fib");
+        let symbols = dir.join("fib.sym");
+        fs::write(&program, assembly.to_bytes()).unwrap();
+        fs::write(&symbols, assembly.render_symbol_map()).unwrap();
+        let source = source.to_str().unwrap();
+
+        let replies = session(&[
+            json!({ "command": "initialize", "arguments": { "linesStartAt1": true } }),
+            json!({ "command": "launch", "arguments": {
+                "program": program,
+                "symbols": symbols,
+                "source": source,
+            }}),
+            // `int 5`, and a line with nothing on it that moves to `hlt`
+            json!({ "command": "setBreakpoints", "arguments": {
+                "source": { "path": source },
+                "breakpoints": [{ "line": 22 }, { "line": 24 }, { "line": 99 }],
+            }}),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": {} }),
+            json!({ "command": "continue" }),
+            json!({ "command": "stepIn" }),
+            json!({ "command": "variables", "arguments": { "variablesReference": UINT_REGISTERS } }),
+            json!({ "command": "setBreakpoints", "arguments": {
+                "source": { "path": source },
+                "breakpoints": [{ "line": 24 }],
+            }}),
+            json!({ "command": "continue" }),
+            json!({ "command": "continue" }),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            summary(&replies),
+            [
+                "initialize",
+                "launch",
+                "initialized",
+                "setBreakpoints",
+                "configurationDone",
+                "stopped:breakpoint",
+                "stackTrace",
+                "continue",
+                "output",
+                "stopped:breakpoint",
+                "stepIn",
+                "output",
+                "stopped:step",
+                "variables",
+                "setBreakpoints",
+                "continue",
+                "output",
+                "stopped:breakpoint",
+                "continue",
+                "exited",
+                "terminated",
+            ]
+        );
+        let seqs: Vec<_> = replies.iter().map(|r| r["seq"].as_i64().unwrap()).collect();
+        assert_eq!(seqs, (1..=replies.len() as i64).collect::<Vec<_>>());
+
+        let set = &replies[3]["body"]["breakpoints"];
+        assert_eq!(set[0]["verified"], true);
+        assert_eq!(set[0]["line"], 22);
+        assert_eq!(set[1]["line"], 25);
+        assert_eq!(set[2]["verified"], false);
+
+        let frame = &replies[6]["body"]["stackFrames"][0];
+        assert_eq!(frame["line"], 22);
+        assert_eq!(frame["source"]["name"], "fib.asm");
+        assert_eq!(frame["source"]["path"], source);
+
+        assert_eq!(replies[8]["body"]["output"], "1\n");
+        assert_eq!(replies[11]["body"]["output"], "2\n");
+        assert_eq!(replies[13]["body"]["variables"][1]["value"], "2");
+        let output = replies[16]["body"]["output"].as_str().unwrap();
+        assert!(output.starts_with("3\n5\n8\n") && output.ends_with("\n46368\n"));
+        assert_eq!(replies[19]["body"]["exitCode"], 0);
+    }
+}
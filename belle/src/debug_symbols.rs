@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// The newest symbol map format this belle understands
+pub const SYMBOL_MAP_VERSION: u32 = 1;
+
+/// Labels and source lines of a program, read from the `.bsym` file `basm -g`
+/// writes next to the binary.
+#[derive(Debug, Default)]
+pub struct DebugSymbols {
+    pub start: u16,
+    files: Vec<String>,
+    /// The text of each file, if it could be read
+    sources: Vec<Option<Vec<String>>>,
+    /// Address to (file index, line)
+    lines: HashMap<u16, (usize, u32)>,
+    /// Labels sorted by address
+    labels: Vec<(u16, String)>,
+}
+
+/// The file a symbol map for `binary` is read from
+#[must_use]
+pub fn symbol_map_path(binary: &str) -> String {
+    format!("{binary}.bsym")
+}
+
+impl DebugSymbols {
+    /// Reads a symbol map. Records this version does not know are skipped, so
+    /// new kinds of record can be added without breaking older readers.
+    pub fn parse(text: &str) -> Result<DebugSymbols, String> {
+        let mut lines = text.lines().enumerate();
+        match lines
+            .next()
+            .map(|(_, line)| line.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(header) if header.len() == 2 && header[0] == "belle-symbols" => {
+                let version: u32 = header[1]
+                    .parse()
+                    .map_err(|_| format!("invalid version {}", header[1]))?;
+                if version > SYMBOL_MAP_VERSION {
+                    return Err(format!(
+                        "symbol map version {version} is newer than the supported version {SYMBOL_MAP_VERSION}"
+                    ));
+                }
+            }
+            _ => return Err("not a symbol map".to_string()),
+        }
+
+        let mut symbols = DebugSymbols::default();
+        for (index, line) in lines {
+            let bad = || format!("line {}: malformed record", index + 1);
+            let mut fields = line.splitn(4, ' ');
+            let kind = fields.next().unwrap_or("");
+            let mut number = || fields.next().and_then(|f| f.parse::<u32>().ok());
+            match kind {
+                "start" => symbols.start = number().ok_or_else(bad)? as u16,
+                "file" => {
+                    let file_index = number().ok_or_else(bad)? as usize;
+                    let path = line.splitn(3, ' ').nth(2).ok_or_else(bad)?;
+                    if file_index != symbols.files.len() {
+                        return Err(bad());
+                    }
+                    symbols.files.push(path.to_string());
+                }
+                "line" => {
+                    let (Some(address), Some(file), Some(source_line)) =
+                        (number(), number(), number())
+                    else {
+                        return Err(bad());
+                    };
+                    if file as usize >= symbols.files.len() {
+                        return Err(bad());
+                    }
+                    symbols
+                        .lines
+                        .insert(address as u16, (file as usize, source_line));
+                }
+                "label" => {
+                    let address = number().ok_or_else(bad)?;
+                    let name = line.splitn(3, ' ').nth(2).ok_or_else(bad)?;
+                    symbols.labels.push((address as u16, name.to_string()));
+                }
+                _ => {}
+            }
+        }
+        symbols.labels.sort();
+        Ok(symbols)
+    }
+
+    /// Loads the symbol map at `path` and the source files it refers to.
+    /// Source files given by relative paths are also looked for next to the map.
+    pub fn load(path: &str) -> Result<DebugSymbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+        let mut symbols = DebugSymbols::parse(&text).map_err(|e| format!("{path}: {e}"))?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        symbols.sources = symbols
+            .files
+            .iter()
+            .map(|file| {
+                fs::read_to_string(file)
+                    .or_else(|_| fs::read_to_string(dir.join(file)))
+                    .ok()
+                    .map(|text| text.lines().map(str::to_string).collect())
+            })
+            .collect();
+        Ok(symbols)
+    }
+
+    /// The file and line the word at `address` was assembled from
+    #[must_use]
+    pub fn location(&self, address: u16) -> Option<(&str, u32)> {
+        let (file, line) = self.lines.get(&address)?;
+        Some((self.files[*file].as_str(), *line))
+    }
+
+    /// The source text the word at `address` was assembled from
+    #[must_use]
+    pub fn source_line(&self, address: u16) -> Option<&str> {
+        let (file, line) = self.lines.get(&address)?;
+        let text = self.sources.get(*file)?.as_ref()?;
+        text.get(line.checked_sub(1)? as usize).map(String::as_str)
+    }
+
+    /// The nearest label at or before `address`, as `label` or `label+offset`.
+    /// A label only reaches as far as the words assembled after it, so
+    /// addresses past the end of the program have no label.
+    #[must_use]
+    pub fn label_at(&self, address: u16) -> Option<String> {
+        let (at, name) = self.labels.iter().rev().find(|(at, _)| *at <= address)?;
+        if *at == address {
+            Some(name.to_string())
+        } else if (*at..=address).all(|a| self.lines.contains_key(&a)) {
+            Some(format!("{name}+{}", address - at))
+        } else {
+            None
+        }
+    }
+
+    /// Every label, sorted by address
+    #[must_use]
+    pub fn labels(&self) -> &[(u16, String)] {
+        &self.labels
+    }
+
+    /// The address of a label
+    #[must_use]
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name == label)
+            .map(|(address, _)| *address)
+    }
+
+    /// The first address assembled from `line` of the file at `path`, or from
+    /// the next line after it with code on it, along with that line
+    #[must_use]
+    pub fn line_address(&self, path: &str, line: u32) -> Option<(u16, u32)> {
+        let file = self.file_index(path)?;
+        self.lines
+            .iter()
+            .filter(|(_, &(f, l))| f == file && l >= line)
+            .map(|(&address, &(_, l))| (l, address))
+            .min()
+            .map(|(l, address)| (address, l))
+    }
+
+    /// The file `path` names, by its full path or, failing that, its file name
+    fn file_index(&self, path: &str) -> Option<usize> {
+        let wanted = fs::canonicalize(path).ok();
+        self.files
+            .iter()
+            .position(|file| wanted.is_some() && fs::canonicalize(file).ok() == wanted)
+            .or_else(|| {
+                let name = Path::new(path).file_name();
+                self.files
+                    .iter()
+                    .position(|file| Path::new(file).file_name() == name)
+            })
+    }
+
+    /// A one line summary of `address`, such as `loop+2 at fib.asm:12: add %r0, %r1`
+    #[must_use]
+    pub fn describe(&self, address: u16) -> Option<String> {
+        let label = self.label_at(address);
+        let location = self.location(address).map(|(file, line)| {
+            let file = Path::new(file)
+                .file_name()
+                .map_or(file.into(), |name| name.to_string_lossy());
+            match self.source_line(address) {
+                Some(text) => format!("{file}:{line}: {}", text.trim()),
+                None => format!("{file}:{line}"),
+            }
+        });
+        match (label, location) {
+            (Some(label), Some(location)) => Some(format!("{label} at {location}")),
+            (label, location) => label.or(location),
+        }
+    }
+}
+
+/// Loads the symbol map next to `binary`, if there is one, for the CPU to
+/// report addresses with. A map that cannot be read is reported and ignored.
+#[must_use]
+pub fn load_debug_symbols(binary: &str) -> Option<DebugSymbols> {
+    let path = symbol_map_path(binary);
+    if !Path::new(&path).is_file() {
+        return None;
+    }
+    DebugSymbols::load(&path)
+        .map_err(|e| eprintln!("Ignoring debug symbols: {e}"))
+        .ok()
+}
+
+/// Reads an address given as a number or as the name of a label in `symbols`
+#[must_use]
+pub fn parse_address(arg: &str, symbols: Option<&DebugSymbols>) -> Option<u16> {
+    let arg = arg.trim();
+    arg.parse::<u16>()
+        .ok()
+        .or_else(|| symbols?.address_of(arg.strip_prefix('@').unwrap_or(arg)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+belle-symbols 1
+start 100
+file 0 main.asm
+line 100 0 2
+line 101 0 3
+line 102 0 5
+label 100 main
+label 102 done
+line 110 0 8
+line 111 0 9
+label 110 data
+";
+
+    #[test]
+    fn labels_cover_the_words_after_them() {
+        let symbols = DebugSymbols::parse(MAP).unwrap();
+        assert_eq!(symbols.label_at(100).as_deref(), Some("main"));
+        assert_eq!(symbols.label_at(101).as_deref(), Some("main+1"));
+        assert_eq!(symbols.label_at(102).as_deref(), Some("done"));
+        assert_eq!(symbols.label_at(111).as_deref(), Some("data+1"));
+        assert_eq!(symbols.label_at(99), None);
+        // past the end of a labelled region
+        assert_eq!(symbols.label_at(103), None);
+        assert_eq!(symbols.label_at(196), None);
+        assert_eq!(symbols.describe(196), None);
+        assert_eq!(
+            symbols.describe(101).as_deref(),
+            Some("main+1 at main.asm:3")
+        );
+    }
+}
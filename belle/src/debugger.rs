@@ -1,197 +1,362 @@
-use crate::display_mem;
-use crate::CPU;
+use crate::Argument::Literal;
+use crate::Instruction::{INT, JMP, JO, JZ, RET};
+use crate::{
+    decode, evaluate, evaluate_address, evaluate_location, symbol_map_path, Breakpoint,
+    BreakpointKind, Breakpoints, Condition, DebugSymbols, Executable, Machine, UnrecoverableError,
+    CPU,
+};
 use colored::Colorize;
-use std::fs::File;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::vec::Vec;
+
+/// Set while the debugger runs the program, so Ctrl+C stops the program
+/// instead of the debugger
+static RESUMED: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 pub fn cls() {
     print!("\x1B[2J\x1B[1;1H");
 }
-pub fn run_bdb(executable_path: &str) -> io::Result<()> {
-    let prompt = "(bdb)> ".green();
-    let mut dbgcpu = CPU::new();
-    let mut clock = 0;
-    println!("Welcome to the BELLE-debugger!");
-    println!("First time? Type 'h' or 'help'\n");
-    loop {
-        let _ = ctrlc::set_handler(move || {
+/// How `belle -d` runs the debugger
+#[derive(Debug, Clone, Default)]
+pub struct BdbOptions {
+    /// Load a raw image without a header
+    pub raw: bool,
+    /// Command files to run before anything is typed in
+    pub scripts: Vec<String>,
+    /// Exit once the command files have run, instead of reading commands from stdin
+    pub batch: bool,
+}
+
+/// How deeply aliases and user-defined commands may run each other
+const MAX_COMMAND_DEPTH: usize = 16;
+
+/// Runs the debugger on the program at `executable_path`, with a CPU built by
+/// `machine`. Returns the status belle exits with: 1 if the program crashed or
+/// halted with errors, and 0 otherwise.
+pub fn run_bdb(executable_path: &str, machine: &Machine, options: &BdbOptions) -> io::Result<i32> {
+    let mut bdb = Bdb {
+        executable_path,
+        raw: options.raw,
+        batch: options.batch,
+        bin: bin_to_vec(executable_path, options.raw)?,
+        cpu: machine.build(),
+        breakpoints: Breakpoints::new(),
+        clock: 0,
+        input: VecDeque::new(),
+        aliases: BTreeMap::new(),
+        macros: BTreeMap::new(),
+        depth: 0,
+        status: 0,
+    };
+    for script in &options.scripts {
+        bdb.input.extend(read_script(script)?);
+    }
+    if !options.batch {
+        println!("Welcome to the BELLE-debugger!");
+        println!("First time? Type 'h' or 'help'\n");
+    }
+    let _ = ctrlc::set_handler(move || {
+        if RESUMED.load(Ordering::SeqCst) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        } else {
             println!("\nExiting...");
             std::process::exit(0);
-        });
-        let bin = bin_to_vec(executable_path)?;
+        }
+    });
+    let prompt = "(bdb)> ".green().to_string();
+    while let Some(line) = bdb.next_line(&prompt)? {
+        if let Flow::Quit = bdb.execute(&line)? {
+            return Ok(bdb.status);
+        }
+    }
+    if !options.batch {
+        println!("{}", "Exiting...\n".yellow());
+    }
+    Ok(bdb.status)
+}
+
+/// What the debugger does after a command
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// A debugging session: the program, the CPU running it, and everything the
+/// user has set up along the way
+struct Bdb<'a> {
+    executable_path: &'a str,
+    raw: bool,
+    batch: bool,
+    /// The program, read when the debugger starts and again on 'load'
+    bin: Vec<i16>,
+    cpu: CPU,
+    breakpoints: Breakpoints,
+    clock: u32,
+    /// Lines from command files, which run before anything typed in
+    input: VecDeque<String>,
+    aliases: BTreeMap<String, String>,
+    /// Commands made with 'define'
+    macros: BTreeMap<String, Vec<String>>,
+    /// How many aliases and user-defined commands are running inside each other
+    depth: usize,
+    /// 1 once the program crashes or halts with errors
+    status: i32,
+}
+
+impl Bdb<'_> {
+    /// The next line of commands, from a command file if any are left and
+    /// otherwise from stdin. `None` once there is nothing left to read.
+    fn next_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        if let Some(line) = self.input.pop_front() {
+            return Ok(Some(line));
+        }
+        if self.batch {
+            return Ok(None);
+        }
         print!("{prompt}");
         io::stdout().flush().unwrap();
         let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-
-        let command = input.trim().to_string();
-        if command.is_empty() {
-            continue;
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Ok(None);
         }
-        let mut parts = command.splitn(2, ' ');
+        Ok(Some(input.trim_end().to_string()))
+    }
 
-        let (cmd, arg) = (parts.next().unwrap(), parts.next().unwrap_or(""));
-        match cmd.to_lowercase().as_str() {
+    /// Runs one line of commands
+    fn execute(&mut self, line: &str) -> io::Result<Flow> {
+        let command = line.trim();
+        if command.is_empty() || command.starts_with('#') {
+            return Ok(Flow::Continue);
+        }
+        let (cmd, arg) = match command.split_once(' ') {
+            Some((cmd, arg)) => (cmd.to_lowercase(), arg),
+            None => (command.to_lowercase(), ""),
+        };
+        match cmd.as_str() {
             "q" | "quit" | ":q" => {
                 println!("{}", "Exiting...\n".yellow());
-                return Ok(());
+                return Ok(Flow::Quit);
             }
-            "h" | "help" => {
-                if arg.is_empty() {
-                    println!("{}", "Available commands:".blue());
-                    println!("q | quit | :q - Exit BDB");
-                    println!("h | help      - Print help on BDB or a specific command");
-                    println!("l | load      - Load program");
-                    println!("r | run       - Run program");
-                    println!("rs            - Reset emulator");
-                    println!("cls           - Clear screen\n");
-                    println!("Can be used whether or not the CPU has ran:");
-                    println!("spc           - Set program counter to a given value");
-                    println!("p | pmem      - Print value in memory");
-                    println!("pk            - Set a new value for a location in memory");
-                    println!("a             - Print all memory");
-                    println!("c | setclk    - Set clock");
-                    println!("wb            - Print CPU's starting memory address\n");
-
-                    println!("Used to step through the program:");
-                    println!("set           - Set the program counter to the starting value");
-                    println!("e | exc       - Execute instruction");
-                    println!("w             - View the state of the CPU\n");
-
-                    println!("Can only be used after the CPU has ran");
-                    println!("i | info      - Print CPU state at debugger's clock");
-                    println!("im            - Print a value in memory at the clock after the CPU has run\n");
+            "h" | "help" => help(arg),
+            "l" | "load" => match bin_to_vec(self.executable_path, self.raw) {
+                Ok(bin) => {
+                    self.bin = bin;
+                    self.cpu.load_binary(&self.bin);
+                }
+                Err(e) => eprintln!("{}\n", e.to_string().red()),
+            },
+            "r" | "run" | "c" | "continue" => {
+                if self.cpu.memory.iter().all(|&x| x.is_none()) {
+                    eprintln!(
+                        "{}",
+                        "CPU memory is empty.\nTry to load the program first.\n".red()
+                    );
+                    return Ok(Flow::Continue);
+                }
+                // a program stopped at a breakpoint continues past it
+                let from_breakpoint = matches!(cmd.as_str(), "c" | "continue");
+                let stop = self.resume(from_breakpoint, |_| false);
+                report_stop(&self.cpu, &stop);
+            }
+            "n" | "next" => {
+                let Some(word) = self.cpu.memory.get(self.cpu.pc as usize).copied().flatten()
+                else {
+                    eprintln!("Nothing at PC {}", self.cpu.pc);
+                    return Ok(Flow::Continue);
+                };
+                let stop = if matches!(decode(word), JMP(_) | JZ(_) | JO(_)) {
+                    let return_to = self.cpu.pc.wrapping_add(1);
+                    let depth = stack_depth(&self.cpu);
+                    self.resume(true, |cpu| cpu.pc == return_to && stack_depth(cpu) == depth)
                 } else {
-                    match arg.trim().to_lowercase().as_str() {
-                        "q" | "quit" | ":q" => {
-                            println!("'quit' takes no arguments.");
-                            println!("'quit' exits the BELLE-debugger with code 0.");
-                            println!(
-                                "The CPU will stop execution or will exit if 'quit' is called.\n"
-                            );
-                        }
-                        "h" | "help" => {
-                            println!("'help' takes zero or one argument.");
-                            println!("'help' prints out information on how to use commands.\n");
-                        }
-                        "l" | "load" => {
-                            println!("'load' takes no arguments.");
-                            println!("'load' loads the CPU's memory with the program, and does nothing afterwards.");
-                            println!("'load' can be used to verify that the program is loaded correctly.");
-                            println!(
-                                "'p' | 'pmem' can be used after 'load' to view the CPU's memory\n"
-                            );
-                        }
-                        "r" | "run" => {
-                            println!("'run' takes no arguments");
-                            println!(
-                                "'run' executes the CPU with the data stored in its memory.\n"
-                            );
-                        }
-                        "spc" => {
-                            println!("'set program counter' takes one argument.");
-                            println!("'spc' sets the CPU's program counter to a given number\n");
-                        }
-                        "c" | "clkset" => {
-                            println!("'clock set' sets the CPU clock to a given value");
-                            println!("If no value is set, the CPU clock is reset to 1\n");
-                        }
-                        "p" | "pmem" => {
-                            println!("'print memory' takes one argument.");
-                            println!("'pmem' prints the value at the specified memory address.");
-                            println!("If nothing is there, it will say so.\n");
-                        }
-                        "e" | "exc" => {
-                            println!("'execute' takes no arguments");
-                            println!("'e' executes the instruction at the current memory address (program counter)\n");
-                        }
-                        "i" | "info" => {
-                            println!("'info' takes no arguments.");
-                            println!("'info' prints the current state of the CPU on the current clock cycle.\n");
-                        }
-                        "cls" | "clear" => {
-                            println!("'clear' takes no arguments and resets the cursor to the top left\nof the terminal\n");
-                        }
-                        "wb" => {
-                            println!("'where begins' takes no arguments");
-                            println!("'wb' prints the starting memory address of the CPU\n");
-                        }
-                        "a" => {
-                            println!("'all instructions' takes no arguments");
-                            println!("'a' prints everything in memory as an instruction if it is a value\n");
-                        }
-                        "set" => {
-                            println!("'set' takes no arguments");
-                            println!("'set' sets the program counter to the starting\nexecution address in memory\n");
-                        }
-                        "w" => {
-                            println!("'w' takes no arguments");
-                            println!("'w' prints the state of the CPU as-is\n");
-                        }
-                        "pk" => {
-                            println!("'pk' takes one argument");
-                            println!("'pk' will print the value in memory and ask for a new value");
-                            println!("if an invalid value is entered or nothing is entered, it will not do anything\n");
-                        }
-                        "im" => {
-                            println!("'info memory' takes one argument");
-                            println!("'im' will print the value in memory at the clock cycle after the CPU has ran");
-                            println!(
-                                "if an invalid value or nothing is entered, nothing will happen\n"
-                            );
-                        }
-                        "rs" => {
-                            println!("'reset' takes no arguments");
-                        }
-                        _ => {
-                            println!("Unknown command: '{arg}'");
-                            println!("Type 'h' or 'help' for a list of available commands.\n");
+                    self.resume(true, |_| true)
+                };
+                report_stop(&self.cpu, &stop);
+            }
+            "finish" => {
+                let depth = stack_depth(&self.cpu);
+                if depth == 0 {
+                    eprintln!(
+                        "{}",
+                        "The stack is empty, so there is nothing to return from.\n".red()
+                    );
+                    return Ok(Flow::Continue);
+                }
+                let stop = self.resume(true, |cpu| {
+                    matches!(cpu.parse_instruction(), RET | INT(Literal(70)))
+                        && stack_depth(cpu) < depth
+                });
+                report_stop(&self.cpu, &stop);
+            }
+            "rstep" | "reverse-step" => {
+                let Some(previous) = self
+                    .cpu
+                    .clock
+                    .checked_sub(1)
+                    .filter(|&previous| self.cpu.rewind(previous))
+                else {
+                    eprintln!("{}", "No recorded history before this clock cycle.\n".red());
+                    return Ok(Flow::Continue);
+                };
+                println!("Clock {previous}");
+                report_stop(&self.cpu, &Stop::Done);
+            }
+            "rc" | "reverse-continue" => {
+                let found = self.cpu.find_before(self.cpu.clock, |cpu| {
+                    self.breakpoints.stops_before(cpu).is_some()
+                        || self.breakpoints.stops_after(cpu).is_some()
+                });
+                let Some(target) = found.or(self.cpu.trace.first_clock()) else {
+                    eprintln!("{}", "Nothing has been recorded yet.\n".red());
+                    return Ok(Flow::Continue);
+                };
+                self.cpu.rewind(target);
+                println!("Clock {target}");
+                let stop = match self
+                    .breakpoints
+                    .stops_before(&self.cpu)
+                    .or_else(|| self.breakpoints.stops_after(&self.cpu))
+                {
+                    Some(breakpoint) if found.is_some() => Stop::Breakpoint(breakpoint.clone()),
+                    _ => {
+                        println!("{}", "Reached the start of the recorded history.".yellow());
+                        Stop::Done
+                    }
+                };
+                report_stop(&self.cpu, &stop);
+            }
+            "goto" => {
+                let Some(target) = self.value(arg).and_then(|n| u32::try_from(n).ok()) else {
+                    eprintln!("{} requires a clock cycle\n", "goto".red());
+                    return Ok(Flow::Continue);
+                };
+                if !self.cpu.rewind(target) {
+                    match (self.cpu.trace.first_clock(), self.cpu.trace.last_clock()) {
+                        (Some(first), Some(last)) => {
+                            eprint!("Clock {target} was not recorded, history runs from {first} to {last}");
+                            if self.cpu.trace.dropped() {
+                                eprint!(" (older cycles were dropped to save memory)");
+                            }
+                            eprintln!("\n");
                         }
+                        _ => eprintln!("{}", "Nothing has been recorded yet.\n".red()),
                     }
+                    return Ok(Flow::Continue);
                 }
+                println!("Clock {target}");
+                report_stop(&self.cpu, &Stop::Done);
             }
-            "l" | "load" => dbgcpu.load_binary(&bin),
-            "r" | "run" => {
-                if dbgcpu.memory.iter().all(|&x| x.is_none()) {
-                    eprintln!(
-                        "{}",
-                        "CPU memory is empty.\nTry to load the program first.\n".red()
+            "b" | "break" | "watch" => {
+                let kind = if cmd == "watch" {
+                    BreakpointKind::Watchpoint
+                } else {
+                    BreakpointKind::Breakpoint
+                };
+                let (address, condition) = match arg.split_once(" if ") {
+                    Some((address, condition)) => (address, Some(condition)),
+                    None => (arg, None),
+                };
+                let Some(address) = self.address(address) else {
+                    eprintln!("{} requires an address\n", cmd.red());
+                    return Ok(Flow::Continue);
+                };
+                let symbols = self.cpu.symbols.as_deref();
+                let condition = match condition.map(|c| Condition::parse(c, symbols)).transpose() {
+                    Ok(condition) => condition,
+                    Err(e) => {
+                        eprintln!("{}\n", e.red());
+                        return Ok(Flow::Continue);
+                    }
+                };
+                let id = self.breakpoints.add(kind, address, condition);
+                let breakpoint = self.breakpoints.iter().find(|b| b.id == id).unwrap();
+                println!("{}\n", breakpoint.describe(symbols));
+            }
+            "bl" => {
+                if self.breakpoints.is_empty() {
+                    println!("No breakpoints or watchpoints.\n");
+                    return Ok(Flow::Continue);
+                }
+                for breakpoint in self.breakpoints.iter() {
+                    let state = if breakpoint.enabled { "" } else { " (off)" };
+                    println!(
+                        "{}{state}, hit {}",
+                        breakpoint.describe(self.cpu.symbols.as_deref()),
+                        times(breakpoint.hits)
                     );
-                } else if let Err(e) = dbgcpu.run() {
-                    eprintln!("{e}");
+                }
+                println!();
+            }
+            "toggle" => match arg
+                .trim()
+                .parse()
+                .ok()
+                .and_then(|id| self.breakpoints.toggle(id))
+            {
+                Some(breakpoint) => {
+                    let state = if breakpoint.enabled { "on" } else { "off" };
+                    let symbols = self.cpu.symbols.as_deref();
+                    println!("{} is {state}\n", breakpoint.describe(symbols));
+                }
+                None => eprintln!("{} requires the number of a breakpoint\n", "toggle".red()),
+            },
+            "del" | "delete" => {
+                if arg.trim().is_empty() {
+                    self.breakpoints.clear();
+                    println!("Deleted all breakpoints and watchpoints\n");
+                } else if let Some(breakpoint) = arg
+                    .trim()
+                    .parse()
+                    .ok()
+                    .and_then(|id| self.breakpoints.delete(id))
+                {
+                    let symbols = self.cpu.symbols.as_deref();
+                    println!("Deleted {}\n", breakpoint.describe(symbols));
+                } else {
+                    eprintln!("{} requires the number of a breakpoint\n", "delete".red());
                 }
             }
             "spc" => 'spc: {
-                if dbgcpu.memory.iter().all(|&x| x.is_none()) {
+                if self.cpu.memory.iter().all(|&x| x.is_none()) {
                     eprintln!(
                         "{}",
                         "CPU memory is empty.\nTry to load the program first.\n".red()
                     );
                     break 'spc;
                 }
-                if let Ok(n) = arg.trim().parse::<u16>() {
-                    dbgcpu.pc = n;
+                if let Some(n) = self.address(arg) {
+                    self.cpu.pc = n;
                     println!("Program counter set to {n}\n");
                 } else {
-                    eprintln!("{} requires a numeric argument\n", "p | pmem".red());
+                    eprintln!("{} requires a numeric argument\n", "spc".red());
                 }
             }
-            "c" | "clkset" => {
-                if !dbgcpu.has_ran {
+            "clk" | "clkset" => {
+                if !self.cpu.has_ran {
                     eprintln!("{}", "CPU has not run.\n".red());
-                    continue;
+                    return Ok(Flow::Continue);
+                }
+                if arg.trim().is_empty() {
+                    self.clock = 1;
+                } else if let Some(n) = self.value(arg).and_then(|n| u32::try_from(n).ok()) {
+                    self.clock = n;
+                } else {
+                    eprintln!("{} requires a clock cycle\n", "clk".red());
                 }
-                let n = arg.parse::<u32>().unwrap_or(1);
-                clock = n;
             }
             "p" | "pmem" => {
-                if let Ok(n) = arg.parse::<usize>() {
-                    if let Some(memvalue) = dbgcpu.memory[n] {
+                if let Some(n) = self.location(arg).map(usize::from) {
+                    if let Some(memvalue) = self.cpu.read_memory(n) {
                         println!("Value in memory is:\n{memvalue:016b}\n{memvalue}");
-                        let oldvalue = dbgcpu.ir;
-                        dbgcpu.ir = memvalue;
-                        println!("dumped instruction: {}", dbgcpu.parse_instruction());
-                        dbgcpu.ir = oldvalue;
+                        let oldvalue = self.cpu.ir;
+                        self.cpu.ir = memvalue;
+                        println!("dumped instruction: {}", self.cpu.parse_instruction());
+                        self.cpu.ir = oldvalue;
                     } else {
                         println!("{}", "Nothing in memory here.\n".yellow());
                     }
@@ -199,120 +364,153 @@ pub fn run_bdb(executable_path: &str) -> io::Result<()> {
                     eprintln!("{} requires a numeric argument\n", "p | pmem".red());
                 }
             }
-            "i" | "info" => CPU::display_state(&clock),
+            "i" | "info" => self.cpu.display_state(self.clock),
             "wb" => {
-                if dbgcpu.memory.iter().all(|&x| x.is_none()) {
+                if self.cpu.memory.iter().all(|&x| x.is_none()) {
                     eprintln!(
                         "{}",
                         "CPU memory is empty.\nTry to load the program first.\n".red()
                     );
                 } else {
-                    println!("Execution begins at memory address {}", dbgcpu.starts_at);
+                    print!("Execution begins at memory address {}", self.cpu.starts_at);
+                    match self.cpu.describe_address(self.cpu.starts_at) {
+                        Some(source) => println!(" ({source})"),
+                        None => println!(),
+                    }
                 }
             }
-            "e" | "exc" => 'exc: {
-                dbgcpu.ir = if let Some(value) = dbgcpu.memory[dbgcpu.pc as usize] {
-                    value
-                } else {
-                    eprintln!("Nothing at PC {}", dbgcpu.pc);
-                    break 'exc;
-                };
-                let parsed_ins = dbgcpu.parse_instruction();
-                if let Err(e) = dbgcpu.execute_instruction(&parsed_ins) {
-                    eprintln!("An error occurred: {e}");
-                }
-                dbgcpu.record_state();
-                println!("  Signed Integer Registers : {:?}", dbgcpu.int_reg);
-                println!("  Uint registers           : {:?}", dbgcpu.uint_reg);
-                println!("  Float Registers          : {:?}", dbgcpu.float_reg);
-                println!("  Program Counter          : {}", dbgcpu.pc);
-                println!("  Instruction Register     : {:016b}", dbgcpu.ir);
-                println!("  Running                  : {}", dbgcpu.running);
-                println!("  Zero flag                : {}", dbgcpu.zflag);
-                println!("  Overflow flag            : {}", dbgcpu.oflag);
-                println!("  Remainder flag           : {}", dbgcpu.rflag);
-                println!("  Stack pointer            : {}", dbgcpu.sp);
-                println!("  Base pointer             : {}", dbgcpu.bp);
-                println!("  Instruction pointer      : {}", dbgcpu.ip);
+            "e" | "exc" => {
+                if self
+                    .cpu
+                    .memory
+                    .get(self.cpu.pc as usize)
+                    .copied()
+                    .flatten()
+                    .is_none()
+                {
+                    eprintln!("Nothing at PC {}", self.cpu.pc);
+                    return Ok(Flow::Continue);
+                }
+                match self.resume(true, |_| true) {
+                    Stop::Error(e) => eprintln!(
+                        "An error occurred: {:#}",
+                        e.located(self.cpu.symbols.as_deref())
+                    ),
+                    stop @ Stop::Breakpoint(_) => report_stop(&self.cpu, &stop),
+                    _ => (),
+                }
+                println!("  Signed Integer Registers : {:?}", self.cpu.int_reg);
+                println!("  Uint registers           : {:?}", self.cpu.uint_reg);
+                println!("  Float Registers          : {:?}", self.cpu.float_reg);
+                println!("  Program Counter          : {}", self.cpu.pc);
+                println!("  Instruction Register     : {:016b}", self.cpu.ir);
+                println!("  Running                  : {}", self.cpu.running);
+                println!("  Zero flag                : {}", self.cpu.zflag);
+                println!("  Overflow flag            : {}", self.cpu.oflag);
+                println!("  Remainder flag           : {}", self.cpu.rflag);
+                println!("  Stack pointer            : {}", self.cpu.sp);
+                println!("  Base pointer             : {}", self.cpu.bp);
+                println!("  Instruction pointer      : {}", self.cpu.ip);
                 println!(
                     "  Disassembled Instruction : {}",
-                    dbgcpu.parse_instruction()
+                    self.cpu.parse_instruction()
                 );
-                let tmp = dbgcpu.ir;
-                if let Some(n) = dbgcpu.memory[dbgcpu.pc as usize] {
-                    dbgcpu.ir = n;
+                if let Some(source) = self.cpu.describe_address(self.cpu.ip) {
+                    println!("  Source                   : {source}");
+                }
+                let tmp = self.cpu.ir;
+                if let Some(n) = self.cpu.memory.get(self.cpu.pc as usize).copied().flatten() {
+                    self.cpu.ir = n;
                     println!(
-                        "  Next instruction         : {}\n",
-                        dbgcpu.parse_instruction()
+                        "  Next instruction         : {}",
+                        self.cpu.parse_instruction()
                     );
+                    if let Some(source) = self.cpu.describe_address(self.cpu.pc) {
+                        println!("  Next source              : {source}");
+                    }
+                    println!();
                 }
-                dbgcpu.ir = tmp;
+                self.cpu.ir = tmp;
             }
             "a" => {
-                for (index, element) in dbgcpu.memory.iter().enumerate() {
+                for (index, element) in self.cpu.memory.iter().enumerate() {
                     if element.is_some() {
                         let mut tmpcpu = CPU::new();
                         tmpcpu.ir = element.unwrap();
                         println!("Value at {} is {}", index, tmpcpu.parse_instruction());
                     }
                 }
-                for (index, element) in dbgcpu.memory.iter().enumerate() {
+                for (index, element) in self.cpu.memory.iter().enumerate() {
                     if element.is_some() {
                         println!("Value at {} is {:016b}", index, element.unwrap());
                     }
                 }
             }
             "set" => 'set: {
-                if dbgcpu.memory.iter().all(|&x| x.is_none()) {
+                if self.cpu.memory.iter().all(|&x| x.is_none()) {
                     eprintln!(
                         "{}",
                         "CPU memory is empty.\nTry to load the program first.\n".red()
                     );
                     break 'set;
                 }
-                dbgcpu.pc = dbgcpu.starts_at;
+                self.cpu.pc = self.cpu.starts_at;
             }
             "w" => {
-                println!("  Signed Integer Registers : {:?}", dbgcpu.int_reg);
-                println!("  Uint registers           : {:?}", dbgcpu.uint_reg);
-                println!("  Float Registers          : {:?}", dbgcpu.float_reg);
-                println!("  Program Counter          : {}", dbgcpu.pc);
-                println!("  Instruction Register     : {:016b}", dbgcpu.ir);
-                println!("  Running                  : {}", dbgcpu.running);
-                println!("  Zero flag                : {}", dbgcpu.zflag);
-                println!("  Overflow flag            : {}", dbgcpu.oflag);
-                println!("  Remainder flag           : {}", dbgcpu.rflag);
-                println!("  Stack pointer            : {}", dbgcpu.sp);
-                println!("  Base pointer             : {}", dbgcpu.bp);
+                println!("  Signed Integer Registers : {:?}", self.cpu.int_reg);
+                println!("  Uint registers           : {:?}", self.cpu.uint_reg);
+                println!("  Float Registers          : {:?}", self.cpu.float_reg);
+                println!("  Program Counter          : {}", self.cpu.pc);
+                println!("  Instruction Register     : {:016b}", self.cpu.ir);
+                println!("  Running                  : {}", self.cpu.running);
+                println!("  Zero flag                : {}", self.cpu.zflag);
+                println!("  Overflow flag            : {}", self.cpu.oflag);
+                println!("  Remainder flag           : {}", self.cpu.rflag);
+                println!("  Stack pointer            : {}", self.cpu.sp);
+                println!("  Base pointer             : {}", self.cpu.bp);
                 println!(
                     "  Disassembled Instruction : {}",
-                    dbgcpu.parse_instruction()
+                    self.cpu.parse_instruction()
                 );
-                let tmp = dbgcpu.ir;
-                if let Some(n) = dbgcpu.memory[dbgcpu.pc as usize] {
-                    dbgcpu.ir = n;
+                if let Some(source) = self.cpu.describe_address(self.cpu.ip) {
+                    println!("  Source                   : {source}");
+                }
+                let tmp = self.cpu.ir;
+                if let Some(n) = self.cpu.memory.get(self.cpu.pc as usize).copied().flatten() {
+                    self.cpu.ir = n;
                     println!(
-                        "  Next instruction         : {}\n",
-                        dbgcpu.parse_instruction()
+                        "  Next instruction         : {}",
+                        self.cpu.parse_instruction()
                     );
+                    if let Some(source) = self.cpu.describe_address(self.cpu.pc) {
+                        println!("  Next source              : {source}");
+                    }
+                    println!();
                 }
-                dbgcpu.ir = tmp;
+                self.cpu.ir = tmp;
             }
             "cls" | "clear" => {
                 cls();
             }
             "pk" => 'pk: {
-                if let Ok(n) = arg.parse::<usize>() {
-                    if let Some(memvalue) = dbgcpu.memory[n] {
-                        println!("Value in memory is:\n{memvalue:016b}\n{memvalue}");
-                        let oldvalue = dbgcpu.ir;
-                        dbgcpu.ir = memvalue;
-                        println!("{}", dbgcpu.parse_instruction());
-                        dbgcpu.ir = oldvalue;
-                        let mut buffer = String::new();
-                        io::stdout().flush().unwrap();
-                        io::stdin().read_line(&mut buffer)?;
-                        if buffer.is_empty() {
+                let (location, value) = match arg.split_once('=') {
+                    Some((location, value)) => (location, Some(value.to_string())),
+                    None => (arg, None),
+                };
+                if let Some(n) = self.location(location).map(usize::from) {
+                    if let Some(memvalue) = self.cpu.read_memory(n) {
+                        let buffer = match value {
+                            Some(value) => value,
+                            None => {
+                                println!("Value in memory is:\n{memvalue:016b}\n{memvalue}");
+                                let oldvalue = self.cpu.ir;
+                                self.cpu.ir = memvalue;
+                                println!("{}", self.cpu.parse_instruction());
+                                self.cpu.ir = oldvalue;
+                                self.next_line("")?.unwrap_or_default()
+                            }
+                        };
+                        if buffer.trim().is_empty() {
                             println!("Empty input\n");
                             break 'pk;
                         }
@@ -320,7 +518,8 @@ pub fn run_bdb(executable_path: &str) -> io::Result<()> {
                             match i32::from_str_radix(&buffer.trim()[2..], 2) {
                                 Ok(val) => {
                                     println!("Value in memory address {n} set to {val:016b}");
-                                    dbgcpu.memory[n] = Some(val as i16);
+                                    self.cpu.write_memory(n, val as i16);
+                                    self.cpu.trace.resync();
                                     break 'pk;
                                 }
                                 Err(e) => println!("Input could not be parsed to binary\n{e}"),
@@ -329,7 +528,8 @@ pub fn run_bdb(executable_path: &str) -> io::Result<()> {
 
                         if let Ok(v) = buffer.trim().parse::<i16>() {
                             println!("Value in memory address {n} set to {v}");
-                            dbgcpu.memory[n] = Some(v);
+                            self.cpu.write_memory(n, v);
+                            self.cpu.trace.resync();
                         } else {
                             println!("Could not parse a valid integer from input\n");
                         }
@@ -341,27 +541,565 @@ pub fn run_bdb(executable_path: &str) -> io::Result<()> {
                 }
             }
             "im" => 'im: {
-                if let Ok(n) = arg.parse::<usize>() {
-                    let tmp = dbgcpu.ir;
-                    let mval = display_mem(&n, &clock);
+                if let Some(n) = self.location(arg).map(usize::from) {
+                    let tmp = self.cpu.ir;
+                    let mval = self.cpu.display_mem(n, self.clock);
                     if mval.is_none() {
                         eprintln!("Nothing in memory here\n");
                         break 'im;
                     }
                     let uwrap_val = mval.unwrap() as i16;
-                    dbgcpu.ir = uwrap_val; // can't panic
+                    self.cpu.ir = uwrap_val; // can't panic
                     println!(
-                        "Value in address {n} is\n{uwrap_val}\n{uwrap_val:016b}\nDisassembles to: {}\n",
-                        dbgcpu.parse_instruction()
-                    );
-                    dbgcpu.ir = tmp;
+                    "Value in address {n} is\n{uwrap_val}\n{uwrap_val:016b}\nDisassembles to: {}\n",
+                    self.cpu.parse_instruction()
+                );
+                    self.cpu.ir = tmp;
                 } else {
                     eprintln!("{} requires a numeric argument\n", "im".red());
                 }
             }
-            _ => unknown_command(&command),
+            "sym" => {
+                if arg.trim().is_empty() {
+                    list_symbols(self.executable_path, self.cpu.symbols.as_deref());
+                } else {
+                    match DebugSymbols::load(arg.trim()) {
+                        Ok(symbols) => {
+                            println!("Loaded debug symbols from {}\n", arg.trim());
+                            self.cpu.symbols = Some(Arc::new(symbols));
+                        }
+                        Err(e) => eprintln!("{}\n", e.red()),
+                    }
+                }
+            }
+            "eval" => {
+                if let Some(value) = self.value(arg) {
+                    println!("{value}\n");
+                }
+            }
+            "echo" => println!("{arg}"),
+            "source" => match read_script(arg.trim()) {
+                Ok(lines) => {
+                    for line in lines.into_iter().rev() {
+                        self.input.push_front(line);
+                    }
+                }
+                Err(e) => eprintln!("{}\n", e.to_string().red()),
+            },
+            "alias" => match arg.trim().split_once(' ') {
+                Some((name, expansion)) => {
+                    let name = name.to_lowercase();
+                    self.macros.remove(&name);
+                    self.aliases.insert(name, expansion.trim().to_string());
+                }
+                None if arg.trim().is_empty() => {
+                    for (name, expansion) in &self.aliases {
+                        println!("{name} = {expansion}");
+                    }
+                    println!();
+                }
+                None => eprintln!("{} requires a name and a command\n", "alias".red()),
+            },
+            "define" => {
+                let name = arg.trim().to_lowercase();
+                if name.is_empty() {
+                    for (name, lines) in &self.macros {
+                        println!("{name}:");
+                        for line in lines {
+                            println!("  {line}");
+                        }
+                    }
+                    println!();
+                    return Ok(Flow::Continue);
+                }
+                if name.contains(' ') {
+                    eprintln!("{} requires a single word as a name\n", "define".red());
+                    return Ok(Flow::Continue);
+                }
+                if self.input.is_empty() && !self.batch {
+                    println!("Type the commands for '{name}', one per line, and 'end' when done.");
+                }
+                let mut lines = Vec::new();
+                while let Some(line) = self.next_line("> ")? {
+                    if line.trim().eq_ignore_ascii_case("end") {
+                        break;
+                    }
+                    lines.push(line.trim().to_string());
+                }
+                self.aliases.remove(&name);
+                self.macros.insert(name, lines);
+            }
+            _ => return self.user_command(&cmd, arg, command),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// Runs an alias or a command made with 'define', with `$arg0`, `$arg1`...
+    /// replaced by its arguments and `$argc` by how many there are
+    fn user_command(&mut self, cmd: &str, arg: &str, command: &str) -> io::Result<Flow> {
+        let lines = if let Some(expansion) = self.aliases.get(cmd) {
+            vec![format!("{expansion} {arg}")]
+        } else if let Some(lines) = self.macros.get(cmd) {
+            let args: Vec<&str> = arg.split_whitespace().collect();
+            lines.iter().map(|line| substitute(line, &args)).collect()
+        } else {
+            unknown_command(command);
+            return Ok(Flow::Continue);
+        };
+        if self.depth == MAX_COMMAND_DEPTH {
+            eprintln!(
+                "{}\n",
+                format!("'{cmd}' runs commands more than {MAX_COMMAND_DEPTH} deep").red()
+            );
+            return Ok(Flow::Continue);
+        }
+        self.depth += 1;
+        let mut flow = Ok(Flow::Continue);
+        for line in lines {
+            flow = self.execute(&line);
+            if !matches!(flow, Ok(Flow::Continue)) {
+                break;
+            }
         }
+        self.depth -= 1;
+        flow
     }
+
+    /// Resumes the program, noting whether it crashed or halted with errors
+    fn resume(&mut self, from_breakpoint: bool, done: impl FnMut(&CPU) -> bool) -> Stop {
+        let stop = resume(&mut self.cpu, &mut self.breakpoints, from_breakpoint, done);
+        match stop {
+            Stop::Error(_) => self.status = 1,
+            Stop::Halted => self.status = i32::from(self.cpu.err),
+            _ => (),
+        }
+        stop
+    }
+
+    /// The value of an expression argument, saying what is wrong with it if
+    /// there is one that cannot be worked out
+    fn value(&self, arg: &str) -> Option<i64> {
+        self.checked(arg, evaluate(arg, &self.cpu))
+    }
+
+    /// An address given as an expression
+    fn address(&self, arg: &str) -> Option<u16> {
+        self.checked(arg, evaluate_address(arg, &self.cpu))
+    }
+
+    /// A place in memory, given as its address or as the memory there, so
+    /// `p sp+1` and `p $[sp+1]` print the same value
+    fn location(&self, arg: &str) -> Option<u16> {
+        self.checked(arg, evaluate_location(arg, &self.cpu))
+    }
+
+    fn checked<T>(&self, arg: &str, result: Result<T, String>) -> Option<T> {
+        if arg.trim().is_empty() {
+            return None;
+        }
+        result.map_err(|e| eprintln!("{}", e.red())).ok()
+    }
+}
+
+fn help(arg: &str) {
+    if arg.is_empty() {
+        println!("{}", "Available commands:".blue());
+        println!("q | quit | :q - Exit BDB");
+        println!("h | help      - Print help on BDB or a specific command");
+        println!("l | load      - Load program");
+        println!("r | run       - Run program, stopping at breakpoints");
+        println!("rs            - Reset emulator");
+        println!("sym           - Load or list debug symbols");
+        println!("cls           - Clear screen\n");
+        println!(
+            "Addresses can be expressions, such as 'sp+1', '@loop' or '$[bp]' (see 'help eval')."
+        );
+        println!("Can be used whether or not the CPU has ran:");
+        println!("spc           - Set program counter to a given value");
+        println!("p | pmem      - Print value in memory");
+        println!("pk            - Set a new value for a location in memory");
+        println!("a             - Print all memory");
+        println!("clk | clkset  - Set clock");
+        println!("wb            - Print CPU's starting memory address\n");
+
+        println!("Used to step through the program:");
+        println!("set           - Set the program counter to the starting value");
+        println!("e | exc       - Execute instruction");
+        println!("n | next      - Execute instruction, running jumps until they return");
+        println!("c | continue  - Run until a breakpoint or watchpoint stops the program");
+        println!("finish        - Run until the current subroutine returns");
+        println!("w             - View the state of the CPU\n");
+
+        println!("Going back in time:");
+        println!("rstep | reverse-step    - Undo the last instruction");
+        println!("rc | reverse-continue   - Go back to the last breakpoint or watchpoint");
+        println!("goto                    - Go to a recorded clock cycle\n");
+
+        println!("Breakpoints:");
+        println!("b | break     - Stop before the instruction at an address runs");
+        println!("watch         - Stop after an instruction stores to an address");
+        println!("bl            - List breakpoints and watchpoints");
+        println!("toggle        - Turn a breakpoint on or off");
+        println!("del | delete  - Delete a breakpoint, or all of them\n");
+
+        println!("Can only be used after the CPU has ran");
+        println!("i | info      - Print CPU state at debugger's clock");
+        println!("im            - Print a value in memory at the clock after the CPU has run\n");
+
+        println!("Scripting:");
+        println!("source        - Run the commands in a file");
+        println!("alias         - Give a command another name");
+        println!("define        - Make a new command out of other commands");
+        println!("echo          - Print a line of text");
+        println!("eval          - Print the value of an expression\n");
+    } else {
+        match arg.trim().to_lowercase().as_str() {
+            "q" | "quit" | ":q" => {
+                println!("'quit' takes no arguments.");
+                println!("'quit' exits the BELLE-debugger, with code 1 if the program crashed");
+                println!("or halted with errors, and 0 otherwise.");
+                println!("The CPU will stop execution or will exit if 'quit' is called.\n");
+            }
+            "h" | "help" => {
+                println!("'help' takes zero or one argument.");
+                println!("'help' prints out information on how to use commands.\n");
+            }
+            "l" | "load" => {
+                println!("'load' takes no arguments.");
+                println!(
+                    "'load' loads the CPU's memory with the program, and does nothing afterwards."
+                );
+                println!("'load' can be used to verify that the program is loaded correctly.");
+                println!("'p' | 'pmem' can be used after 'load' to view the CPU's memory\n");
+            }
+            "r" | "run" => {
+                println!("'run' takes no arguments");
+                println!("'run' executes the CPU with the data stored in its memory.");
+                println!("It stops at breakpoints and watchpoints, like 'continue'.\n");
+            }
+            "spc" => {
+                println!("'set program counter' takes one argument.");
+                println!("'spc' sets the CPU's program counter to a given number\n");
+            }
+            "clk" | "clkset" => {
+                println!("'clock set' sets the CPU clock to a given value");
+                println!("If no value is set, the CPU clock is reset to 1\n");
+            }
+            "p" | "pmem" => {
+                println!("'print memory' takes one argument.");
+                println!("'pmem' prints the value at the specified memory address.");
+                println!("If nothing is there, it will say so. An address a device claims is");
+                println!("read from the device, the same as the program would read it.\n");
+            }
+            "e" | "exc" => {
+                println!("'execute' takes no arguments");
+                println!("'e' executes the instruction at the current memory address (program counter)\n");
+            }
+            "n" | "next" => {
+                println!("'next' takes no arguments");
+                println!(
+                    "'next' executes one instruction, like 'e'. If it is a jump that is taken,"
+                );
+                println!("the program runs until the jump returns to the following instruction.");
+                println!("A jump whose return address is popped off the stack never returns.\n");
+            }
+            "c" | "continue" => {
+                println!("'continue' takes no arguments");
+                println!("'continue' runs the program until it halts, or a breakpoint or");
+                println!("watchpoint stops it. Ctrl+C stops it too.\n");
+            }
+            "finish" => {
+                println!("'finish' takes no arguments");
+                println!("'finish' runs the program until the subroutine it is in returns,");
+                println!("with 'ret' or 'int 70'.\n");
+            }
+            "rstep" | "reverse-step" => {
+                println!("'reverse-step' takes no arguments");
+                println!("'reverse-step' puts the CPU back the way it was one clock cycle ago.\n");
+            }
+            "rc" | "reverse-continue" => {
+                println!("'reverse-continue' takes no arguments");
+                println!("'reverse-continue' goes back to the last clock cycle a breakpoint or");
+                println!("watchpoint would have stopped the program on, or to the start of the");
+                println!("recorded history if there is none.\n");
+            }
+            "goto" => {
+                println!("'goto' takes a clock cycle");
+                println!("'goto 40' puts the CPU back the way it was on clock cycle 40, which");
+                println!("can be before or after the current one as long as it was recorded.");
+                println!("Memory and registers go back, but devices such as the timer do not.\n");
+            }
+            "b" | "break" => {
+                println!("'break' takes an address and an optional condition");
+                println!("'break 120' stops the program before the instruction at 120 runs.");
+                println!("'break loop if r0 == 5' only stops when r0 is 5 as well.");
+                println!("Conditions compare r0-r7, pc, sp, bp or a memory address ($N)");
+                println!("with a number, using == != < <= > or >=.\n");
+            }
+            "watch" => {
+                println!("'watch' takes an address and an optional condition");
+                println!("'watch 200' stops the program after any instruction stores to 200,");
+                println!("even if the value stays the same. Pushes and pops count as stores.");
+                println!("'watch 200 if $200 > 10' only stops when the condition holds as well.\n");
+            }
+            "bl" => {
+                println!("'bl' takes no arguments");
+                println!("'bl' lists breakpoints and watchpoints, with their numbers and how");
+                println!("many times they have stopped the program.\n");
+            }
+            "toggle" => {
+                println!("'toggle' takes the number of a breakpoint or watchpoint");
+                println!("'toggle' turns it off if it is on, and on if it is off.\n");
+            }
+            "del" | "delete" => {
+                println!("'delete' takes zero or one argument");
+                println!("'delete 2' deletes breakpoint or watchpoint 2, and 'delete'");
+                println!("on its own deletes all of them.\n");
+            }
+            "i" | "info" => {
+                println!("'info' takes no arguments.");
+                println!(
+                    "'info' prints the current state of the CPU on the current clock cycle.\n"
+                );
+            }
+            "cls" | "clear" => {
+                println!("'clear' takes no arguments and resets the cursor to the top left\nof the terminal\n");
+            }
+            "wb" => {
+                println!("'where begins' takes no arguments");
+                println!("'wb' prints the starting memory address of the CPU\n");
+            }
+            "a" => {
+                println!("'all instructions' takes no arguments");
+                println!("'a' prints everything in memory as an instruction if it is a value\n");
+            }
+            "set" => {
+                println!("'set' takes no arguments");
+                println!(
+                    "'set' sets the program counter to the starting\nexecution address in memory\n"
+                );
+            }
+            "w" => {
+                println!("'w' takes no arguments");
+                println!("'w' prints the state of the CPU as-is\n");
+            }
+            "pk" => {
+                println!("'pk' takes one argument, and optionally '=' and a value");
+                println!("'pk' will print the value in memory and ask for a new value");
+                println!(
+                    "if an invalid value is entered or nothing is entered, it will not do anything"
+                );
+                println!("'pk sp+1 = 5' sets the value without asking, which is handy in scripts");
+                println!("An address a device claims is read from and written to the device\n");
+            }
+            "im" => {
+                println!("'info memory' takes one argument");
+                println!(
+                    "'im' will print the value in memory at the clock cycle after the CPU has ran"
+                );
+                println!("if an invalid value or nothing is entered, nothing will happen\n");
+            }
+            "rs" => {
+                println!("'reset' takes no arguments");
+            }
+            "sym" => {
+                println!("'symbols' takes zero or one argument");
+                println!("With no argument, 'sym' lists the labels of the loaded debug symbols.");
+                println!("With a file, 'sym' loads debug symbols from it.");
+                println!(
+                    "Symbols written by 'basm -g' next to the program are loaded on startup.\n"
+                );
+            }
+            "source" => {
+                println!("'source' takes a file");
+                println!("'source' runs the commands in the file, one per line, before reading");
+                println!("any more. Lines starting with '#' are comments.");
+                println!("'belle -d prog -x FILE' does the same when the debugger starts, and");
+                println!("'--batch' exits once the file has run, with the program's exit code.\n");
+            }
+            "alias" => {
+                println!("'alias' takes a name and a command, or nothing to list aliases");
+                println!("'alias s spc @start' makes 's' run 'spc @start'. Anything typed after");
+                println!("'s' is added to the end of the command.\n");
+            }
+            "define" => {
+                println!("'define' takes a name, or nothing to list defined commands");
+                println!("The commands on the lines after it, up to 'end', become a new command.");
+                println!("In them, $arg0, $arg1... are replaced with the arguments it is given,");
+                println!(
+                    "and $argc with how many there are. Built-in commands cannot be replaced.\n"
+                );
+            }
+            "echo" => {
+                println!("'echo' prints the rest of the line, which is handy in scripts\n");
+            }
+            "eval" => {
+                println!("'eval' takes an expression and prints its value");
+                println!("Expressions are made of numbers (10, 0xa, 0b1010), registers (r0-r7,");
+                println!("pc, sp, bp, ip), labels (@loop or loop) and memory ($40 or $[sp+1]),");
+                println!("with + - * / % and brackets. Any command that takes an address takes");
+                println!(
+                    "an expression, and 'p $[sp+1]' prints the value at sp+1 like 'p sp+1'.\n"
+                );
+            }
+            _ => {
+                println!("Unknown command: '{arg}'");
+                println!("Type 'h' or 'help' for a list of available commands.\n");
+            }
+        }
+    }
+}
+
+/// Why the debugger stopped running the program
+enum Stop {
+    Halted,
+    Error(UnrecoverableError),
+    Breakpoint(Breakpoint),
+    Interrupted,
+    /// The command got where it was going
+    Done,
+}
+
+/// Runs the program until it halts, a breakpoint stops it, or `done` says
+/// the command that resumed it is finished. The instruction at the program
+/// counter always runs, even if a breakpoint is set on it, when
+/// `from_breakpoint` is set.
+fn resume(
+    cpu: &mut CPU,
+    breakpoints: &mut Breakpoints,
+    from_breakpoint: bool,
+    mut done: impl FnMut(&CPU) -> bool,
+) -> Stop {
+    if cpu.do_not_run {
+        return Stop::Halted;
+    }
+    cpu.has_ran = true;
+    cpu.running = true;
+    // so the program can be rewound to where it was resumed from
+    if cpu.options.record && !cpu.trace.contains(cpu.clock) {
+        cpu.record_state();
+    }
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    RESUMED.store(true, Ordering::SeqCst);
+    let mut first = from_breakpoint;
+    let stop = loop {
+        if !first {
+            if let Some(breakpoint) = breakpoints.hit_before(cpu) {
+                break Stop::Breakpoint(breakpoint.clone());
+            }
+        }
+        first = false;
+        if let Err(e) = cpu.step() {
+            break Stop::Error(e);
+        }
+        if let Some(breakpoint) = breakpoints.hit_after(cpu) {
+            break Stop::Breakpoint(breakpoint.clone());
+        }
+        if !cpu.running {
+            cpu.halted();
+            break Stop::Halted;
+        }
+        if done(cpu) {
+            break Stop::Done;
+        }
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break Stop::Interrupted;
+        }
+    };
+    RESUMED.store(false, Ordering::SeqCst);
+    stop
+}
+
+/// Says where the program stopped, and shows the next instruction
+fn report_stop(cpu: &CPU, stop: &Stop) {
+    match stop {
+        Stop::Halted => println!("{}", "Program halted.".yellow()),
+        Stop::Error(e) => {
+            eprintln!("{:#}\n", e.located(cpu.symbols.as_deref()));
+            return;
+        }
+        Stop::Breakpoint(breakpoint) => println!(
+            "{}, hit {}",
+            breakpoint.describe(cpu.symbols.as_deref()).yellow(),
+            times(breakpoint.hits)
+        ),
+        Stop::Interrupted => println!("{}", "Interrupted.".yellow()),
+        Stop::Done => (),
+    }
+    if let Some(word) = cpu.memory.get(cpu.pc as usize).copied().flatten() {
+        print!("  {:>5}: {}", cpu.pc, decode(word));
+        match cpu.describe_address(cpu.pc) {
+            Some(source) => println!("  ({source})"),
+            None => println!(),
+        }
+    }
+    println!();
+}
+
+fn times(n: u32) -> String {
+    if n == 1 {
+        "1 time".to_string()
+    } else {
+        format!("{n} times")
+    }
+}
+
+/// The number of values on the stack
+pub(crate) fn stack_depth(cpu: &CPU) -> usize {
+    if cpu.memory.get(cpu.bp as usize).copied().flatten().is_none() {
+        0
+    } else {
+        usize::from(cpu.sp.abs_diff(cpu.bp)) + 1
+    }
+}
+
+fn list_symbols(executable_path: &str, symbols: Option<&DebugSymbols>) {
+    let Some(symbols) = symbols else {
+        println!(
+            "No debug symbols loaded. Assemble with 'basm -g' to write {}\n",
+            symbol_map_path(executable_path)
+        );
+        return;
+    };
+    for (address, name) in symbols.labels() {
+        match symbols.location(*address) {
+            Some((file, line)) => println!("{address:>5}  {name}  {file}:{line}"),
+            None => println!("{address:>5}  {name}"),
+        }
+    }
+    println!();
+}
+
+/// The lines of a command file
+fn read_script(path: &str) -> io::Result<Vec<String>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {path}: {e}")))?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// Puts the arguments a user-defined command was given in place of `$arg0`,
+/// `$arg1`..., and how many there are in place of `$argc`. Missing arguments
+/// are left empty.
+fn substitute(line: &str, args: &[&str]) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("$arg") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 4..];
+        if let Some(after) = rest.strip_prefix('c') {
+            result.push_str(&args.len().to_string());
+            rest = after;
+            continue;
+        }
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        match rest[..digits].parse::<usize>() {
+            Ok(index) => result.push_str(args.get(index).copied().unwrap_or("")),
+            Err(_) => result.push_str("$arg"),
+        }
+        rest = &rest[digits..];
+    }
+    result.push_str(rest);
+    result
 }
 
 fn unknown_command(command: &str) {
@@ -371,7 +1109,9 @@ fn unknown_command(command: &str) {
     );
 }
 
-pub fn bin_to_vec(file_path: &str) -> io::Result<Vec<i16>> {
+/// Reads a program into words, checking that it is a valid executable.
+/// Raw images without a header are only accepted when `raw` is set.
+pub fn bin_to_vec(file_path: &str, raw: bool) -> io::Result<Vec<i16>> {
     let mut file = File::open(file_path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
@@ -387,5 +1127,17 @@ pub fn bin_to_vec(file_path: &str) -> io::Result<Vec<i16>> {
         }
     }
 
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    if Executable::is_executable(&result) {
+        if buffer.len() % 2 != 0 {
+            return Err(invalid("executable is truncated".to_string()));
+        }
+        Executable::parse(&result).map_err(invalid)?;
+    } else if !raw {
+        return Err(invalid(format!(
+            "{file_path} is not a BELLE executable, use --raw to load a raw image"
+        )));
+    }
+
     Ok(result)
 }
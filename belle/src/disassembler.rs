@@ -0,0 +1,399 @@
+use crate::Argument::*;
+use crate::Instruction::*;
+use crate::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Where a raw image is loaded when it has no `.start`
+pub const DEFAULT_START: u16 = 100;
+
+/// Data words written on one `.word` line
+const WORDS_PER_LINE: usize = 8;
+
+/// One word of a program and the instruction it holds
+#[derive(Clone)]
+pub struct DisassembledWord {
+    pub address: u16,
+    pub word: i16,
+    /// `None` when the word is not an instruction `basm` would write, such as data
+    pub instruction: Option<Instruction>,
+}
+
+/// Decodes one word the way the CPU does when it executes it
+#[must_use]
+pub fn decode(word: i16) -> Instruction {
+    let opcode = (word >> 12) & 0b1111u16 as i16;
+    let mut ins_type = if ((word >> 8) & 1) == 1 {
+        1
+    } else if ((word >> 7) & 1) == 1 {
+        2
+    } else if ((word >> 6) & 1) == 1 {
+        3
+    } else {
+        0
+    };
+    let it_is_bouncy = opcode == JZ_OP || opcode == JO_OP || opcode == JMP_OP;
+    let indirect_bounce = (word & 0b100000000000) >> 11 == 1;
+    let tmp = word & 0b1111111;
+
+    let source = match ins_type {
+        1 => {
+            if it_is_bouncy {
+                if indirect_bounce {
+                    ins_type = 4;
+                    word & 0b1111
+                } else {
+                    word & 0b111111111111
+                }
+            } else if (word & 0b10000000) >> 7 == 1 {
+                -tmp
+            } else {
+                tmp
+            }
+        }
+        _ => {
+            if it_is_bouncy {
+                if indirect_bounce {
+                    ins_type = 4;
+                    word & 0b1111
+                } else {
+                    word & 0b111111111111
+                }
+            } else {
+                word & 0b1111111
+            }
+        }
+    };
+    let destination = (word & 0b111000000000) >> 9;
+    let mut part = match ins_type {
+        0 => Register(source),
+        1 => Literal(source),
+        2 => MemPtr(source),
+        _ => RegPtr(source),
+    };
+
+    if let RegPtr(value) = part {
+        part = RegPtr(value & 0b111);
+    }
+
+    if let MemPtr(value) = part {
+        part = MemPtr(value & 0b1111111);
+    }
+
+    match opcode {
+        HLT_OP => HLT,
+        ADD_OP => ADD(Register(destination), part),
+        JO_OP => {
+            if ins_type == 4 {
+                JO(RegPtr(source))
+            } else {
+                JO(MemAddr(source))
+            }
+        }
+        POP_OP => POP(Register(source)),
+        DIV_OP => DIV(Register(destination), part),
+        RET_OP => RET,
+        LD_OP => {
+            let part = word & 0b111111111;
+            LD(Register(destination), MemAddr(part))
+        }
+        ST_OP => {
+            if (word & 0b100000000000) >> 11 == 1 {
+                let part = (word & 0b1110000000) >> 7;
+                ST(RegPtr(part), Register(word & 0b111))
+            } else {
+                let part = (word & 0b111111111000) >> 3;
+                ST(MemAddr(part), Register(word & 0b111))
+            }
+        }
+        JMP_OP => {
+            if ins_type == 4 {
+                JMP(RegPtr(source))
+            } else {
+                JMP(MemAddr(source))
+            }
+        }
+        JZ_OP => {
+            if ins_type == 4 {
+                JZ(RegPtr(source))
+            } else {
+                JZ(MemAddr(source))
+            }
+        }
+        CMP_OP => CMP(Register(destination), part),
+        MUL_OP => MUL(Register(destination), part),
+        PUSH_OP if ins_type == 1 => PUSH(Literal(source)),
+        PUSH_OP => PUSH(Register(source)),
+        INT_OP => INT(Literal(source)),
+        MOV_OP => MOV(Register(destination), part),
+        NOP_OP => NOP,
+        _ => unreachable!("opcodes are 4 bits"),
+    }
+}
+
+/// The word `basm` writes for an instruction, or `None` if it cannot write it
+#[must_use]
+pub fn encode(ins: &Instruction) -> Option<i16> {
+    let register = |arg: &Argument| match arg {
+        Register(n) if (0..=7).contains(n) => Some(*n),
+        _ => None,
+    };
+    let literal = |value: i16| {
+        let magnitude = value.unsigned_abs() as i16;
+        match value {
+            0..=127 => Some(0b100000000 | value),
+            -127..=-1 => Some(0b110000000 | magnitude),
+            _ => None,
+        }
+    };
+    let source = |arg: &Argument| match arg {
+        Register(_) => register(arg),
+        Literal(value) => literal(*value),
+        MemPtr(address) if (0..=127).contains(address) => Some(0b10000000 | address),
+        RegPtr(n) if (0..=7).contains(n) => Some(0b1000000 | n),
+        _ => None,
+    };
+    let two = |opcode: i16, dest: &Argument, src: &Argument| {
+        Some(opcode << 12 | register(dest)? << 9 | source(src)?)
+    };
+    let jump = |opcode: i16, arg: &Argument| match arg {
+        MemAddr(address) if (0..=2047).contains(address) => Some(opcode << 12 | address),
+        RegPtr(n) if (0..=7).contains(n) => Some(opcode << 12 | 1 << 11 | n),
+        _ => None,
+    };
+
+    match ins {
+        HLT => Some(HLT_OP),
+        RET => Some(RET_OP << 12),
+        NOP => Some(NOP_OP << 12),
+        ADD(dest, src) => two(ADD_OP, dest, src),
+        DIV(dest, src) => two(DIV_OP, dest, src),
+        CMP(dest, src) => two(CMP_OP, dest, src),
+        MUL(dest, src) => two(MUL_OP, dest, src),
+        MOV(dest, src) => two(MOV_OP, dest, src),
+        JO(arg) => jump(JO_OP, arg),
+        JMP(arg) => jump(JMP_OP, arg),
+        JZ(arg) => jump(JZ_OP, arg),
+        POP(arg) => Some(POP_OP << 12 | register(arg)?),
+        PUSH(Literal(value)) => Some(PUSH_OP << 12 | literal(*value)?),
+        PUSH(arg) => Some(PUSH_OP << 12 | register(arg)?),
+        INT(Literal(value)) if *value >= -1 => Some(INT_OP << 12 | literal(*value)?),
+        LD(dest, MemAddr(address)) if (0..=511).contains(address) => {
+            Some(LD_OP << 12 | register(dest)? << 9 | address)
+        }
+        ST(MemAddr(address), src) if (0..=511).contains(address) => {
+            Some(ST_OP << 12 | address << 3 | register(src)?)
+        }
+        ST(RegPtr(n), src) if (0..=7).contains(n) => {
+            Some(ST_OP << 12 | 1 << 11 | n << 7 | register(src)?)
+        }
+        _ => None,
+    }
+}
+
+/// Decodes `words` loaded from `address` onwards. A word is only taken as an
+/// instruction when `basm` would write that same word for it.
+#[must_use]
+pub fn disassemble(words: &[i16], address: u16) -> Vec<DisassembledWord> {
+    words
+        .iter()
+        .enumerate()
+        .map(|(offset, &word)| {
+            let instruction = decode(word);
+            DisassembledWord {
+                address: address.wrapping_add(offset as u16),
+                word,
+                instruction: (encode(&instruction) == Some(word)).then_some(instruction),
+            }
+        })
+        .collect()
+}
+
+/// A line of disassembled source
+enum Item {
+    Directive(&'static str, u16),
+    Code(DisassembledWord),
+    Data(u16, i16),
+}
+
+impl From<DisassembledWord> for Item {
+    fn from(word: DisassembledWord) -> Item {
+        match word.instruction {
+            Some(_) => Item::Code(word),
+            None => Item::Data(word.address, word.word),
+        }
+    }
+}
+
+/// `basm` source for an executable. Assembling it gives back the same
+/// executable, as long as every text word is an instruction `basm` writes.
+#[must_use]
+pub fn executable_source(executable: &Executable) -> String {
+    let mut items = vec![Item::Directive("start", executable.entry)];
+    items.extend(executable.ssp.map(|ssp| Item::Directive("ssp", ssp)));
+    items.extend(executable.sbp.map(|sbp| Item::Directive("sbp", sbp)));
+    let mut next = executable.entry;
+    for segment in &executable.segments {
+        if segment.address > next {
+            items.push(Item::Directive("org", segment.address));
+        }
+        next = segment.address.wrapping_add(segment.words.len() as u16);
+        match segment.kind {
+            SegmentKind::Text => items.extend(
+                disassemble(&segment.words, segment.address)
+                    .into_iter()
+                    .map(Item::from),
+            ),
+            SegmentKind::Data => items.extend(
+                segment
+                    .words
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, word)| Item::Data(segment.address + offset as u16, *word)),
+            ),
+        }
+    }
+    render(&items)
+}
+
+/// `basm --raw` source for a raw image, with its `.start`, `.ssp` and
+/// `.sbp` words turned back into directives where they appear
+#[must_use]
+pub fn raw_source(words: &[i16]) -> String {
+    let start = words
+        .iter()
+        .find(|word| *word >> 9 == 1)
+        .map_or(DEFAULT_START, |word| (word & 0b111111111) as u16);
+    let mut items = Vec::new();
+    let mut address = start;
+    for &word in words {
+        let value = (word & 0b111111111) as u16;
+        match word >> 9 {
+            1 => items.push(Item::Directive("start", value)),
+            2 => items.push(Item::Directive("ssp", value)),
+            3 => items.push(Item::Directive("sbp", value)),
+            _ => {
+                items.push(Item::from(disassemble(&[word], address).remove(0)));
+                address = address.wrapping_add(1);
+            }
+        }
+    }
+    render(&items)
+}
+
+/// Writes the items out, naming every address a jump lands on
+fn render(items: &[Item]) -> String {
+    let placed: Vec<u16> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Code(word) => Some(word.address),
+            Item::Data(address, _) => Some(*address),
+            Item::Directive(..) => None,
+        })
+        .collect();
+    let labels: BTreeMap<u16, String> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Code(DisassembledWord {
+                instruction: Some(JMP(MemAddr(target)) | JZ(MemAddr(target)) | JO(MemAddr(target))),
+                ..
+            }) => Some(*target as u16),
+            _ => None,
+        })
+        .filter(|target| placed.contains(target))
+        .map(|target| (target, format!("label_{target}")))
+        .collect();
+
+    let mut out = String::new();
+    let mut data: Vec<(u16, i16)> = Vec::new();
+    let flush = |out: &mut String, data: &mut Vec<(u16, i16)>| {
+        for line in data.chunks(WORDS_PER_LINE) {
+            let values: Vec<String> = line.iter().map(|(_, word)| word.to_string()).collect();
+            let text = format!("    .word {}", values.join(", "));
+            let _ = writeln!(out, "{text:<31} ; {}", line[0].0);
+        }
+        data.clear();
+    };
+    for item in items {
+        let address = match item {
+            Item::Code(word) => Some(word.address),
+            Item::Data(address, _) => Some(*address),
+            Item::Directive(..) => None,
+        };
+        if let Some(label) = address.and_then(|address| labels.get(&address)) {
+            flush(&mut out, &mut data);
+            let _ = writeln!(out, "{label}:");
+        }
+        match item {
+            Item::Data(address, word) => data.push((*address, *word)),
+            Item::Directive(name, value) => {
+                flush(&mut out, &mut data);
+                let _ = writeln!(out, ".{name} ${value}");
+            }
+            Item::Code(word) => {
+                flush(&mut out, &mut data);
+                let text = format!(
+                    "    {}",
+                    source_text(word.instruction.as_ref().unwrap(), &labels)
+                );
+                let _ = writeln!(
+                    out,
+                    "{text:<31} ; {}: {:04X}",
+                    word.address, word.word as u16
+                );
+            }
+        }
+    }
+    flush(&mut out, &mut data);
+    out
+}
+
+/// An instruction in the syntax `basm` reads, with jump targets named by `labels`
+#[must_use]
+pub fn source_text(ins: &Instruction, labels: &BTreeMap<u16, String>) -> String {
+    let operand = |arg: &Argument| match arg {
+        Register(n) => format!("r{n}"),
+        Literal(value) => literal(*value),
+        MemAddr(address) => match labels.get(&(*address as u16)) {
+            Some(label) => format!("@{label}"),
+            None => format!("${address}"),
+        },
+        MemPtr(address) => format!("&${address}"),
+        RegPtr(n) => format!("&r{n}"),
+        SR(value) | Flag(value) => value.to_string(),
+        Nothing => String::new(),
+    };
+    let (name, args): (&str, Vec<&Argument>) = match ins {
+        HLT => ("hlt", vec![]),
+        RET => ("ret", vec![]),
+        NOP => ("nop", vec![]),
+        ADD(a, b) => ("add", vec![a, b]),
+        DIV(a, b) => ("div", vec![a, b]),
+        CMP(a, b) => ("cmp", vec![a, b]),
+        MUL(a, b) => ("mul", vec![a, b]),
+        MOV(a, b) => ("mov", vec![a, b]),
+        LD(a, b) => ("ld", vec![a, b]),
+        ST(a, b) => ("st", vec![a, b]),
+        JO(a) => ("jo", vec![a]),
+        JMP(a) => ("jmp", vec![a]),
+        JZ(a) => ("jz", vec![a]),
+        POP(a) => ("pop", vec![a]),
+        PUSH(a) => ("push", vec![a]),
+        INT(a) => ("int", vec![a]),
+    };
+    let args: Vec<String> = args.into_iter().map(operand).collect();
+    if args.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name} {}", args.join(", "))
+    }
+}
+
+/// A number as `basm` reads it, which needs `#` in front of negative values
+fn literal(value: i16) -> String {
+    if value < 0 {
+        format!("#{value}")
+    } else {
+        value.to_string()
+    }
+}
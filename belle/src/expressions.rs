@@ -0,0 +1,222 @@
+use crate::CPU;
+
+/// Works out an expression typed into the debugger, such as `sp+1`, `@loop`
+/// or `$[bp-2] * 2`.
+///
+/// Numbers can be decimal, `0x` hexadecimal or `0b` binary. `r0` to `r7`,
+/// `pc`, `sp`, `bp` and `ip` are the registers, with floats rounded towards
+/// zero, and labels can be written as `@loop` or just `loop`. `$N` and
+/// `$[expr]` are the value in memory at an address. `+`, `-`, `*`, `/`, `%`
+/// and brackets work as usual.
+pub fn evaluate(text: &str, cpu: &CPU) -> Result<i64, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+        cpu,
+    };
+    let value = parser.expression()?;
+    parser.skip_spaces();
+    if parser.position < text.len() {
+        return Err(format!(
+            "unexpected '{}' in '{}'",
+            &text[parser.position..],
+            text.trim()
+        ));
+    }
+    Ok(value)
+}
+
+/// Works out an expression that should be an address
+pub fn evaluate_address(text: &str, cpu: &CPU) -> Result<u16, String> {
+    let value = evaluate(text, cpu)?;
+    u16::try_from(value).map_err(|_| format!("{value} is not an address"))
+}
+
+/// Works out the address of a location in memory, given either as an address
+/// or as the memory there, so `sp+1`, `$[sp+1]` and `$41` all name a place
+pub fn evaluate_location(text: &str, cpu: &CPU) -> Result<u16, String> {
+    let trimmed = text.trim();
+    let inner = trimmed
+        .strip_prefix("$[")
+        .and_then(|t| t.strip_suffix(']'))
+        .filter(|inner| !inner.contains(']'))
+        .or_else(|| {
+            trimmed
+                .strip_prefix('$')
+                .filter(|t| t.bytes().all(|b| b.is_ascii_alphanumeric()))
+        });
+    evaluate_address(inner.unwrap_or(trimmed), cpu)
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+    cpu: &'a CPU,
+}
+
+impl Parser<'_> {
+    fn expression(&mut self) -> Result<i64, String> {
+        let mut value = self.term()?;
+        loop {
+            match self.peek() {
+                Some(b'+') => {
+                    self.position += 1;
+                    value = value.wrapping_add(self.term()?);
+                }
+                Some(b'-') => {
+                    self.position += 1;
+                    value = value.wrapping_sub(self.term()?);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(operator @ (b'*' | b'/' | b'%')) => operator,
+                _ => return Ok(value),
+            };
+            self.position += 1;
+            let right = self.unary()?;
+            value = match operator {
+                b'*' => value.wrapping_mul(right),
+                _ if right == 0 => return Err("division by zero".to_string()),
+                b'/' => value / right,
+                _ => value % right,
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<i64, String> {
+        match self.peek() {
+            Some(b'(') => {
+                self.position += 1;
+                let value = self.expression()?;
+                self.expect(b')')?;
+                Ok(value)
+            }
+            Some(b'$') => {
+                self.position += 1;
+                let address = if self.peek() == Some(b'[') {
+                    self.position += 1;
+                    let address = self.expression()?;
+                    self.expect(b']')?;
+                    address
+                } else {
+                    self.atom()?
+                };
+                self.memory(address)
+            }
+            Some(b'@') => {
+                self.position += 1;
+                let name = self.word();
+                self.label(&name)
+            }
+            Some(b'0'..=b'9') => {
+                let word = self.word();
+                let (digits, radix) = if let Some(hex) = word.strip_prefix("0x") {
+                    (hex, 16)
+                } else if let Some(binary) = word.strip_prefix("0b") {
+                    (binary, 2)
+                } else {
+                    (word.as_str(), 10)
+                };
+                i64::from_str_radix(digits, radix).map_err(|_| format!("'{word}' is not a number"))
+            }
+            Some(_) => {
+                let word = self.word();
+                if word.is_empty() {
+                    let rest = String::from_utf8_lossy(&self.text[self.position..]);
+                    return Err(format!("expected a value at '{rest}'"));
+                }
+                self.register(&word).map_or_else(|| self.label(&word), Ok)
+            }
+            None => Err("expected a value".to_string()),
+        }
+    }
+
+    fn register(&self, name: &str) -> Option<i64> {
+        let cpu = self.cpu;
+        let value = match name.to_lowercase().as_str() {
+            "r0" => cpu.int_reg[0].into(),
+            "r1" => cpu.int_reg[1].into(),
+            "r2" => cpu.int_reg[2].into(),
+            "r3" => cpu.int_reg[3].into(),
+            "r4" => cpu.uint_reg[0].into(),
+            "r5" => cpu.uint_reg[1].into(),
+            "r6" => cpu.float_reg[0] as i64,
+            "r7" => cpu.float_reg[1] as i64,
+            "pc" => cpu.pc.into(),
+            "sp" => cpu.sp.into(),
+            "bp" => cpu.bp.into(),
+            "ip" => cpu.ip.into(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Plain memory, never a device, as breakpoint conditions are worked out
+    /// on every clock cycle and must not take keys or the like
+    fn memory(&self, address: i64) -> Result<i64, String> {
+        usize::try_from(address)
+            .ok()
+            .and_then(|address| self.cpu.memory.get(address).copied().flatten())
+            .map(i64::from)
+            .ok_or_else(|| format!("nothing in memory at {address}"))
+    }
+
+    /// A number, register or label
+    fn word(&mut self) -> String {
+        self.skip_spaces();
+        let start = self.position;
+        while self
+            .text
+            .get(self.position)
+            .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'.')
+        {
+            self.position += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.position]).into_owned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", byte as char))
+        }
+    }
+
+    /// The next character that is not a space
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.text.get(self.position).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.text.get(self.position) == Some(&b' ') {
+            self.position += 1;
+        }
+    }
+
+    fn label(&self, name: &str) -> Result<i64, String> {
+        self.cpu
+            .symbols
+            .as_ref()
+            .and_then(|symbols| symbols.address_of(name))
+            .map(i64::from)
+            .ok_or_else(|| format!("'{name}' is not a register, label or number"))
+    }
+}
@@ -0,0 +1,720 @@
+use crate::{BreakpointKind, Breakpoints, UnrecoverableError, CPU, MEMORY_SIZE};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// BELLE's registers as GDB numbers them, in the order `g` sends them.
+///
+/// GDB addresses bytes and BELLE addresses 16 bit words, so GDB sees each
+/// word as two bytes, high byte first, at twice its BELLE address. `pc`, `sp`
+/// and `bp` are given as these byte addresses, which is why they are wider
+/// than BELLE's own registers.
+///
+/// Everything is big-endian, but GDB has no BELLE architecture to name here,
+/// and a target description cannot give the byte order on its own. GDB has
+/// to be told with `set endian big` before it connects.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.belle.core">
+    <flags id="belle_flags" size="2">
+      <field name="Z" start="0" end="0"/>
+      <field name="O" start="1" end="1"/>
+      <field name="R" start="2" end="2"/>
+      <field name="S" start="3" end="3"/>
+    </flags>
+    <reg name="r0" bitsize="16" type="int16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="int16"/>
+    <reg name="r2" bitsize="16" type="int16"/>
+    <reg name="r3" bitsize="16" type="int16"/>
+    <reg name="r4" bitsize="16" type="uint16"/>
+    <reg name="r5" bitsize="16" type="uint16"/>
+    <reg name="r6" bitsize="32" type="ieee_single"/>
+    <reg name="r7" bitsize="32" type="ieee_single"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="sp" bitsize="32" type="data_ptr"/>
+    <reg name="bp" bitsize="32" type="data_ptr"/>
+    <reg name="flags" bitsize="16" type="belle_flags"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: usize = 12;
+
+/// How many clock cycles run between checks for GDB interrupting the program
+const INTERRUPT_CHECK_INTERVAL: u32 = 1024;
+
+/// Waits for GDB to connect to `address` and lets it debug `cpu`, which should
+/// already have the program loaded. `address` is a Unix socket if it has a `/`
+/// in it, and otherwise a TCP address such as `localhost:1234`, or just a
+/// port on 127.0.0.1.
+pub fn run_gdb_stub(address: &str, cpu: &mut CPU) -> io::Result<()> {
+    if address.contains('/') {
+        return serve_unix(address, cpu);
+    }
+    let address = if address.parse::<u16>().is_ok() {
+        format!("127.0.0.1:{address}")
+    } else {
+        address.to_string()
+    };
+    let listener = TcpListener::bind(address)?;
+    println!("Waiting for GDB on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    println!("GDB connected from {peer}");
+    Session::new(stream, cpu).serve()
+}
+
+#[cfg(unix)]
+fn serve_unix(path: &str, cpu: &mut CPU) -> io::Result<()> {
+    let listener = UnixListener::bind(path)?;
+    println!("Waiting for GDB on {path}");
+    let served = listener
+        .accept()
+        .and_then(|(stream, _)| Session::new(stream, cpu).serve());
+    let _ = std::fs::remove_file(path);
+    served
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_path: &str, _cpu: &mut CPU) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+/// A stream GDB is connected over
+trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+struct Session<'a, C: Connection> {
+    connection: C,
+    cpu: &'a mut CPU,
+    breakpoints: Breakpoints,
+    /// Packets are acknowledged until GDB asks for no-ack mode
+    acks: bool,
+    /// The reply to `?`, which is why the program last stopped
+    stop: String,
+    /// GDB detached or killed the program
+    done: bool,
+}
+
+impl<'a, C: Connection> Session<'a, C> {
+    fn new(connection: C, cpu: &'a mut CPU) -> Session<'a, C> {
+        let stop = if cpu.do_not_run { "W01" } else { "S05" };
+        Session {
+            connection,
+            cpu,
+            breakpoints: Breakpoints::new(),
+            acks: true,
+            stop: stop.to_string(),
+            done: false,
+        }
+    }
+
+    /// Answers packets until GDB hangs up, detaches or kills the program
+    fn serve(mut self) -> io::Result<()> {
+        while !self.done {
+            let Some(packet) = self.receive()? else {
+                break;
+            };
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            if let Some(reply) = self.handle(&packet)? {
+                self.send(reply.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The reply to `packet`, or `None` if it gets none
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let reply = match packet {
+            "?" => self.stop.clone(),
+            "g" => (0..REGISTER_COUNT)
+                .filter_map(|n| read_register(self.cpu, n))
+                .map(|bytes| hex(&bytes))
+                .collect(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            "QStartNoAckMode" => {
+                self.acks = false;
+                "OK".to_string()
+            }
+            "k" => {
+                self.done = true;
+                return Ok(None);
+            }
+            _ if packet.starts_with('D') => {
+                self.done = true;
+                // a detached program carries on by itself
+                if !self.cpu.do_not_run && self.stop.starts_with('S') {
+                    self.send(b"OK")?;
+                    if let Err(e) = self.cpu.run() {
+                        eprintln!("{}", e.located(self.cpu.symbols.as_deref()));
+                    }
+                    return Ok(None);
+                }
+                "OK".to_string()
+            }
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;vContSupported+".to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:") => {
+                features(&packet["qXfer:features:read:".len()..])
+            }
+            _ if packet.starts_with('G') => self.write_registers(&packet[1..]),
+            _ if packet.starts_with('p') => usize::from_str_radix(&packet[1..], 16)
+                .ok()
+                .and_then(|n| read_register(self.cpu, n))
+                .map_or_else(|| "E01".to_string(), |bytes| hex(&bytes)),
+            _ if packet.starts_with('P') => self.write_one_register(&packet[1..]),
+            _ if packet.starts_with('m') => self.read_memory(&packet[1..]),
+            _ if packet.starts_with('M') => self.write_memory(&packet[1..]),
+            _ if packet.starts_with('Z') || packet.starts_with('z') => {
+                self.breakpoint(packet.starts_with('Z'), &packet[1..])
+            }
+            _ if packet.starts_with('c') || packet.starts_with('s') => {
+                if let Some(address) = parse_hex(&packet[1..]).and_then(word_address) {
+                    self.cpu.pc = address;
+                }
+                self.resume(packet.starts_with('s'))?
+            }
+            _ if packet.starts_with("vCont;") => match packet["vCont;".len()..].chars().next() {
+                Some('s' | 'S') => self.resume(true)?,
+                Some('c' | 'C') => self.resume(false)?,
+                _ => "E01".to_string(),
+            },
+            _ if packet.starts_with('H') || packet.starts_with('T') => "OK".to_string(),
+            // anything else is not supported
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    /// Runs the program until it halts, a breakpoint stops it, or GDB
+    /// interrupts it, or for one clock cycle if `step` is set. The instruction
+    /// at the program counter always runs, even if a breakpoint is set on it.
+    fn resume(&mut self, step: bool) -> io::Result<String> {
+        if self.cpu.do_not_run || self.stop.starts_with('W') {
+            return Ok(self.stop.clone());
+        }
+        self.cpu.has_ran = true;
+        self.cpu.running = true;
+        let mut first = true;
+        let mut cycles: u32 = 0;
+        let stop = loop {
+            if !first && self.breakpoints.hit_before(self.cpu).is_some() {
+                break "S05".to_string();
+            }
+            first = false;
+            if let Err(e) = self.cpu.step() {
+                break format!("S{:02x}", signal(&e));
+            }
+            if let Some(watchpoint) = self.breakpoints.hit_after(self.cpu) {
+                break format!("T05watch:{:x};", byte_address(watchpoint.address));
+            }
+            if !self.cpu.running {
+                self.cpu.halted();
+                break if self.cpu.err { "W01" } else { "W00" }.to_string();
+            }
+            if step {
+                break "S05".to_string();
+            }
+            cycles = cycles.wrapping_add(1);
+            if cycles.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && self.interrupted()? {
+                break "S02".to_string();
+            }
+        };
+        self.stop.clone_from(&stop);
+        Ok(stop)
+    }
+
+    /// Whether GDB sent Ctrl+C, or hung up, while the program was running
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.connection.set_nonblocking(true)?;
+        let mut byte = [0];
+        let read = self.connection.read(&mut byte);
+        self.connection.set_nonblocking(false)?;
+        match read {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let Some(bytes) = unhex(data) else {
+            return "E01".to_string();
+        };
+        let mut rest = &bytes[..];
+        for n in 0..REGISTER_COUNT {
+            let size = read_register(self.cpu, n).map_or(0, |bytes| bytes.len());
+            if rest.len() < size {
+                break;
+            }
+            write_register(self.cpu, n, &rest[..size]);
+            rest = &rest[size..];
+        }
+        "OK".to_string()
+    }
+
+    fn write_one_register(&mut self, data: &str) -> String {
+        let written = data.split_once('=').and_then(|(n, value)| {
+            let n = usize::from_str_radix(n, 16).ok()?;
+            write_register(self.cpu, n, &unhex(value)?)
+        });
+        match written {
+            Some(()) => "OK".to_string(),
+            None => "E01".to_string(),
+        }
+    }
+
+    /// `addr,length`, read from plain memory, so devices are not disturbed
+    /// by GDB looking at them. Empty addresses read as 0.
+    fn read_memory(&self, data: &str) -> String {
+        let Some((start, length)) = parse_range(data) else {
+            return "E01".to_string();
+        };
+        let mut reply = String::new();
+        for address in start..start.saturating_add(length) {
+            let Some(word) = word_address(address).filter(|&w| usize::from(w) < MEMORY_SIZE) else {
+                break;
+            };
+            let value = self.cpu.memory[usize::from(word)].unwrap_or(0) as u16;
+            let byte = if address % 2 == 0 {
+                value >> 8
+            } else {
+                value & 0xFF
+            };
+            let _ = write!(reply, "{byte:02x}");
+        }
+        if reply.is_empty() && length > 0 {
+            return "E01".to_string();
+        }
+        reply
+    }
+
+    /// `addr,length:XX...`, written to plain memory
+    fn write_memory(&mut self, data: &str) -> String {
+        let Some((range, bytes)) = data.split_once(':') else {
+            return "E01".to_string();
+        };
+        let (Some((start, length)), Some(bytes)) = (parse_range(range), unhex(bytes)) else {
+            return "E01".to_string();
+        };
+        if bytes.len() != length as usize {
+            return "E01".to_string();
+        }
+        for (address, byte) in (start..).zip(bytes) {
+            let Some(word) = word_address(address).filter(|&w| usize::from(w) < MEMORY_SIZE) else {
+                return "E01".to_string();
+            };
+            let cell = &mut self.cpu.memory[usize::from(word)];
+            let value = cell.unwrap_or(0) as u16;
+            let value = if address % 2 == 0 {
+                value & 0x00FF | u16::from(byte) << 8
+            } else {
+                value & 0xFF00 | u16::from(byte)
+            };
+            *cell = Some(value as i16);
+        }
+        self.cpu.trace.resync();
+        "OK".to_string()
+    }
+
+    /// `type,addr,kind` for `Z` and `z`. Software and hardware breakpoints are
+    /// the same thing here, and only write watchpoints are supported.
+    fn breakpoint(&mut self, insert: bool, data: &str) -> String {
+        let mut fields = data.split(',');
+        let kind = match fields.next() {
+            Some("0" | "1") => BreakpointKind::Breakpoint,
+            Some("2") => BreakpointKind::Watchpoint,
+            _ => return String::new(),
+        };
+        let Some(address) = fields.next().and_then(parse_hex).and_then(word_address) else {
+            return "E01".to_string();
+        };
+        let existing = self
+            .breakpoints
+            .iter()
+            .find(|b| b.kind == kind && b.address == address)
+            .map(|b| b.id);
+        match (insert, existing) {
+            (true, None) => {
+                self.breakpoints.add(kind, address, None);
+            }
+            (false, Some(id)) => {
+                self.breakpoints.delete(id);
+            }
+            _ => (),
+        }
+        "OK".to_string()
+    }
+
+    /// The next packet's data, or `None` once GDB hangs up. Anything outside a
+    /// packet, such as acknowledgements, is skipped.
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut sum = [0; 2];
+            for digit in &mut sum {
+                let Some(byte) = self.read_byte()? else {
+                    return Ok(None);
+                };
+                *digit = byte;
+            }
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+                == Some(checksum(&data));
+            if self.acks {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut packet = vec![b'$'];
+        for &byte in data {
+            if matches!(byte, b'#' | b'$' | b'}' | b'*') {
+                packet.extend([b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let sum = checksum(&packet[1..]);
+        packet.extend(format!("#{sum:02x}").bytes());
+        self.connection.write_all(&packet)?;
+        self.connection.flush()
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.connection.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+/// `target.xml:offset,length`
+fn features(data: &str) -> String {
+    let Some(("target.xml", range)) = data.split_once(':') else {
+        return "E00".to_string();
+    };
+    let Some((offset, length)) = parse_range(range) else {
+        return "E01".to_string();
+    };
+    let xml = TARGET_XML.as_bytes();
+    let start = (offset as usize).min(xml.len());
+    let end = start.saturating_add(length as usize).min(xml.len());
+    let more = if end < xml.len() { 'm' } else { 'l' };
+    format!("{more}{}", String::from_utf8_lossy(&xml[start..end]))
+}
+
+fn read_register(cpu: &CPU, n: usize) -> Option<Vec<u8>> {
+    let bytes = match n {
+        0..=3 => cpu.int_reg[n].to_be_bytes().to_vec(),
+        4..=5 => cpu.uint_reg[n - 4].to_be_bytes().to_vec(),
+        6..=7 => cpu.float_reg[n - 6].to_be_bytes().to_vec(),
+        8 => byte_address(cpu.pc).to_be_bytes().to_vec(),
+        9 => byte_address(cpu.sp).to_be_bytes().to_vec(),
+        10 => byte_address(cpu.bp).to_be_bytes().to_vec(),
+        11 => {
+            let flags = u16::from(cpu.zflag)
+                | u16::from(cpu.oflag) << 1
+                | u16::from(cpu.rflag) << 2
+                | u16::from(cpu.sflag) << 3;
+            flags.to_be_bytes().to_vec()
+        }
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+fn write_register(cpu: &mut CPU, n: usize, bytes: &[u8]) -> Option<()> {
+    match n {
+        0..=3 => cpu.int_reg[n] = i16::from_be_bytes(bytes.try_into().ok()?),
+        4..=5 => cpu.uint_reg[n - 4] = u16::from_be_bytes(bytes.try_into().ok()?),
+        6..=7 => cpu.float_reg[n - 6] = f32::from_be_bytes(bytes.try_into().ok()?),
+        8..=10 => {
+            let address = word_address(u32::from_be_bytes(bytes.try_into().ok()?))?;
+            match n {
+                8 => cpu.pc = address,
+                9 => cpu.sp = address,
+                _ => cpu.bp = address,
+            }
+        }
+        11 => {
+            let flags = u16::from_be_bytes(bytes.try_into().ok()?);
+            cpu.zflag = flags & 1 != 0;
+            cpu.oflag = flags & 2 != 0;
+            cpu.rflag = flags & 4 != 0;
+            cpu.sflag = flags & 8 != 0;
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// The signal GDB is told stopped the program
+fn signal(error: &UnrecoverableError) -> u8 {
+    match error {
+        UnrecoverableError::IllegalInstruction(..) | UnrecoverableError::InvalidRegister(..) => 4,
+        UnrecoverableError::DivideByZero(..) => 8,
+        _ => 11,
+    }
+}
+
+fn byte_address(word: u16) -> u32 {
+    u32::from(word) * 2
+}
+
+fn word_address(byte: u32) -> Option<u16> {
+    u16::try_from(byte / 2).ok()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// `addr,length`
+fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let (address, length) = text.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, MemoryConsole};
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Packets from GDB, already queued up, and everything the stub sends back
+    #[derive(Default)]
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for &mut Pipe {
+        fn set_nonblocking(&self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${data}#{:02x}", checksum(data.as_bytes()))
+    }
+
+    /// `mov r0, 1`, `add r0, 1`, `int 0` and `hlt` from address 100
+    fn cpu(console: &Arc<Mutex<MemoryConsole>>) -> CPU {
+        let mut cpu = Machine::new().console(console.clone()).build();
+        cpu.load_binary(&vec![
+            0x0264,
+            0xE101_u16 as i16,
+            0x1101,
+            0xD100_u16 as i16,
+            0,
+        ]);
+        cpu
+    }
+
+    #[test]
+    fn checksums_and_hex() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+        assert_eq!(hex(&[0x00, 0xab, 0x7f]), "00ab7f");
+        assert_eq!(unhex("00aB7f"), Some(vec![0x00, 0xab, 0x7f]));
+        assert_eq!(unhex("abc"), None);
+        assert_eq!(unhex("zz"), None);
+        assert_eq!(unhex(""), Some(vec![]));
+    }
+
+    #[test]
+    fn special_bytes_are_escaped() {
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        let mut cpu = cpu(&console);
+        let mut pipe = Pipe::default();
+        Session::new(&mut pipe, &mut cpu).send(b"a#b$c}d*").unwrap();
+        let escaped = b"a}\x03b}\x04c}]d}\x0a";
+        let mut expected = vec![b'$'];
+        expected.extend(escaped);
+        expected.extend(format!("#{:02x}", checksum(escaped)).bytes());
+        assert_eq!(pipe.output, expected);
+    }
+
+    #[test]
+    fn packets_are_acknowledged() {
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        let mut cpu = cpu(&console);
+        let mut pipe = Pipe {
+            input: Cursor::new(b"+$g#00$?#3f".to_vec()),
+            ..Pipe::default()
+        };
+        let mut session = Session::new(&mut pipe, &mut cpu);
+        assert_eq!(session.receive().unwrap(), Some(b"?".to_vec()));
+        assert_eq!(session.receive().unwrap(), None);
+        drop(session);
+        assert_eq!(pipe.output, b"-+");
+    }
+
+    #[test]
+    fn memory_is_two_bytes_a_word() {
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        let mut cpu = cpu(&console);
+        let mut pipe = Pipe::default();
+        let mut session = Session::new(&mut pipe, &mut cpu);
+        // 100 is byte 0xc8, and 99 is empty
+        assert_eq!(session.read_memory("c6,6"), "0000e1011101");
+        assert_eq!(session.read_memory("c9,2"), "0111");
+        assert_eq!(session.write_memory("c9,2:abcd"), "OK");
+        assert_eq!(session.cpu.memory[100], Some(0xE1AB_u16 as i16));
+        assert_eq!(session.cpu.memory[101], Some(0xCD01_u16 as i16));
+        assert_eq!(session.write_memory("0,1:12"), "OK");
+        assert_eq!(session.cpu.memory[0], Some(0x1200));
+        assert_eq!(session.write_memory("0,2:12"), "E01");
+        assert_eq!(session.read_memory("1fffe,4"), "E01");
+        assert_eq!(session.read_memory("nonsense"), "E01");
+    }
+
+    #[test]
+    fn breakpoints_are_inserted_and_removed() {
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        let mut cpu = cpu(&console);
+        let mut pipe = Pipe::default();
+        let mut session = Session::new(&mut pipe, &mut cpu);
+        assert_eq!(session.breakpoint(true, "0,cc,2"), "OK");
+        assert_eq!(session.breakpoint(true, "1,cc,2"), "OK");
+        assert_eq!(session.breakpoint(true, "2,2,2"), "OK");
+        let set: Vec<_> = session
+            .breakpoints
+            .iter()
+            .map(|b| (b.kind, b.address))
+            .collect();
+        assert_eq!(
+            set,
+            [
+                (BreakpointKind::Breakpoint, 102),
+                (BreakpointKind::Watchpoint, 1)
+            ]
+        );
+        assert_eq!(session.breakpoint(false, "0,cc,2"), "OK");
+        assert_eq!(session.breakpoint(false, "0,cc,2"), "OK");
+        assert_eq!(session.breakpoints.iter().count(), 1);
+        // read and access watchpoints are not supported
+        assert_eq!(session.breakpoint(true, "3,2,2"), "");
+        assert_eq!(session.breakpoint(true, "0,zz,2"), "E01");
+    }
+
+    #[test]
+    fn scripted_session() {
+        let script = [
+            (
+                "qSupported:multiprocess+",
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;vContSupported+",
+            ),
+            ("?", "S05"),
+            ("p8", "000000c8"),
+            ("Z0,cc,2", "OK"),
+            ("c", "S05"),
+            ("p8", "000000cc"),
+            ("p0", "0002"),
+            ("P1=0007", "OK"),
+            ("z0,cc,2", "OK"),
+            ("s", "S05"),
+            ("c", "W00"),
+            ("?", "W00"),
+        ];
+        let mut input: String = script.iter().map(|(sent, _)| packet(sent)).collect();
+        input.push_str(&packet("k"));
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        let mut cpu = cpu(&console);
+        let mut pipe = Pipe {
+            input: Cursor::new(input.into_bytes()),
+            ..Pipe::default()
+        };
+        Session::new(&mut pipe, &mut cpu).serve().unwrap();
+
+        let mut expected: String = script
+            .iter()
+            .map(|(_, reply)| format!("+{}", packet(reply)))
+            .collect();
+        expected.push('+');
+        assert_eq!(String::from_utf8(pipe.output).unwrap(), expected);
+        assert_eq!(cpu.int_reg[1], 7);
+        assert_eq!(console.lock().unwrap().output, "2\n");
+    }
+}
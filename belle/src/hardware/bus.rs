@@ -0,0 +1,247 @@
+use crate::*;
+use std::sync::{Arc, Mutex};
+
+/// Hardware mapped into a range of addresses. The CPU calls it whenever a
+/// program loads from or stores to that range, with the offset of the address
+/// from the start of the range.
+pub trait Device {
+    /// The value at `offset`, or `None` to fault as if the address were empty
+    fn read(&mut self, offset: u16) -> Option<i16>;
+    fn write(&mut self, offset: u16, value: i16);
+    /// Called once every clock cycle, after the instruction has run
+    fn tick(&mut self, _clock: u32) {}
+}
+
+/// A device the CPU can share with whoever built it
+pub type SharedDevice = Arc<Mutex<dyn Device + Send>>;
+
+/// A range of addresses claimed by a device
+#[derive(Clone)]
+pub struct Region {
+    pub name: String,
+    pub start: u16,
+    pub len: u16,
+    device: SharedDevice,
+}
+
+impl Region {
+    #[must_use]
+    pub fn contains(&self, address: usize) -> bool {
+        (usize::from(self.start)..usize::from(self.start) + usize::from(self.len))
+            .contains(&address)
+    }
+}
+
+/// Routes the addresses claimed by devices to them. Everything else is plain
+/// memory, which the CPU reads and writes as before.
+#[derive(Clone, Default)]
+pub struct Bus {
+    regions: Vec<Region>,
+}
+
+impl Bus {
+    /// Gives `len` addresses starting at `start` to `device`
+    pub fn map(
+        &mut self,
+        name: &str,
+        start: u16,
+        len: u16,
+        device: SharedDevice,
+    ) -> Result<(), String> {
+        if len == 0 || usize::from(start) + usize::from(len) > MEMORY_SIZE {
+            return Err(format!(
+                "{name} at {start} with {len} addresses does not fit in memory"
+            ));
+        }
+        let end = usize::from(start) + usize::from(len);
+        if let Some(other) = self.regions.iter().find(|r| {
+            usize::from(r.start) < end
+                && usize::from(start) < usize::from(r.start) + usize::from(r.len)
+        }) {
+            return Err(format!(
+                "{name} at {start} overlaps {} at {}",
+                other.name, other.start
+            ));
+        }
+        self.regions.push(Region {
+            name: name.to_string(),
+            start,
+            len,
+            device,
+        });
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn region(&self, address: usize) -> Option<&Region> {
+        self.regions.iter().find(|region| region.contains(address))
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter()
+    }
+
+    /// What the device at `address` reads, or `None` if no device claims it
+    pub fn read(&self, address: usize) -> Option<Option<i16>> {
+        let region = self.region(address)?;
+        let offset = (address - usize::from(region.start)) as u16;
+        Some(region.device.lock().unwrap().read(offset))
+    }
+
+    /// Lets every device know a clock cycle has passed
+    pub fn tick(&self, clock: u32) {
+        for region in &self.regions {
+            region.device.lock().unwrap().tick(clock);
+        }
+    }
+
+    /// Hands a store to the device at `address`, returning whether there is one
+    pub fn write(&self, address: usize, value: i16) -> bool {
+        let Some(region) = self.region(address) else {
+            return false;
+        };
+        let offset = (address - usize::from(region.start)) as u16;
+        region.device.lock().unwrap().write(offset, value);
+        true
+    }
+}
+
+impl CPU {
+    /// Loads from `address`, through the bus if a device claims it
+    #[must_use]
+    pub fn read_memory(&self, address: usize) -> Option<i16> {
+        match self.bus.read(address) {
+            Some(value) => value,
+            None => self.memory.get(address).copied().flatten(),
+        }
+    }
+
+    /// Stores to `address`, through the bus if a device claims it
+    pub fn write_memory(&mut self, address: usize, value: i16) {
+        self.stores.push(address);
+        if !self.bus.write(address, value) {
+            if let Some(cell) = self.memory.get_mut(address) {
+                *cell = Some(value);
+            }
+        }
+    }
+}
+
+/// A port that reads as a new pseudo-random number every time. Stores set the
+/// seed, so a program can make its numbers repeat.
+#[derive(Debug, Clone)]
+pub struct RandomDevice {
+    state: u32,
+}
+
+impl RandomDevice {
+    #[must_use]
+    pub fn new(seed: u32) -> RandomDevice {
+        RandomDevice { state: seed.max(1) }
+    }
+}
+
+impl Device for RandomDevice {
+    fn read(&mut self, _offset: u16) -> Option<i16> {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        Some((self.state >> 16) as i16)
+    }
+
+    fn write(&mut self, _offset: u16, value: i16) {
+        self.state = u32::from(value as u16).max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, MemoryConsole};
+
+    /// A device that keeps what is stored at each of its offsets
+    struct Registers([Option<i16>; 4]);
+
+    impl Device for Registers {
+        fn read(&mut self, offset: u16) -> Option<i16> {
+            self.0[usize::from(offset)]
+        }
+
+        fn write(&mut self, offset: u16, value: i16) {
+            self.0[usize::from(offset)] = Some(value);
+        }
+    }
+
+    fn registers() -> Arc<Mutex<Registers>> {
+        Arc::new(Mutex::new(Registers([None; 4])))
+    }
+
+    #[test]
+    fn regions_must_fit_and_not_overlap() {
+        let mut bus = Bus::default();
+        bus.map("a", 200, 4, registers()).unwrap();
+        // right next to another region is fine
+        bus.map("b", 204, 4, registers()).unwrap();
+        bus.map("c", 196, 4, registers()).unwrap();
+        assert_eq!(
+            bus.map("d", 203, 2, registers()),
+            Err("d at 203 overlaps a at 200".to_string())
+        );
+        assert_eq!(
+            bus.map("e", 190, 100, registers()),
+            Err("e at 190 overlaps a at 200".to_string())
+        );
+        assert_eq!(
+            bus.map("f", 300, 0, registers()),
+            Err("f at 300 with 0 addresses does not fit in memory".to_string())
+        );
+        assert_eq!(
+            bus.map("g", 65530, 10, registers()),
+            Err("g at 65530 with 10 addresses does not fit in memory".to_string())
+        );
+        let names: Vec<&str> = bus.regions().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn loads_and_stores_go_to_the_right_offset() {
+        let device = registers();
+        let mut cpu = Machine::new()
+            .device("regs", 200, 4, device.clone())
+            .build();
+        cpu.write_memory(202, 7);
+        cpu.write_memory(204, 8);
+        assert_eq!(device.lock().unwrap().0, [None, None, Some(7), None]);
+        assert_eq!(cpu.memory[202], None);
+        assert_eq!(cpu.memory[204], Some(8));
+        assert_eq!(cpu.stores, [202, 204]);
+
+        device.lock().unwrap().0[0] = Some(-1);
+        assert_eq!(cpu.read_memory(200), Some(-1));
+        assert_eq!(cpu.read_memory(201), None);
+        assert_eq!(cpu.read_memory(204), Some(8));
+        assert_eq!(cpu.bus.region(203).map(|r| r.start), Some(200));
+        assert!(cpu.bus.region(204).is_none());
+    }
+
+    #[test]
+    fn strings_are_printed_from_devices() {
+        let device = registers();
+        device.lock().unwrap().0 = [Some(104), Some(105), Some(33), None];
+        let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+        let mut cpu = Machine::new()
+            .console(console.clone())
+            .device("text", 50, 4, device)
+            .build();
+        // mov r0, #50; mov r1, #52; int 8; hlt
+        cpu.load_binary(&vec![
+            0xE132_u16 as i16,
+            0xE334_u16 as i16,
+            0xD108_u16 as i16,
+            0,
+        ]);
+        cpu.run().unwrap();
+        assert_eq!(console.lock().unwrap().output, "\x0chi!\n");
+    }
+}
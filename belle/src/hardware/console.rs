@@ -0,0 +1,197 @@
+use crate::{cls, KeyQueue};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Where a program's `int` calls for I/O go
+pub trait Console {
+    /// Prints the value of a register on its own line (`int 0` to `int 7`)
+    fn write_number(&mut self, number: &dyn fmt::Display);
+    /// Prints a line of text (`int 8`)
+    fn write_line(&mut self, text: &str);
+    /// Clears the screen (`int 8`)
+    fn clear(&mut self);
+    /// Waits for a key and returns its byte, or 0 if there is no more input (`int 9`)
+    fn read_key(&mut self) -> u8;
+    /// Pauses the program (`int 10`)
+    fn delay(&mut self, duration: Duration);
+}
+
+/// A console a CPU can share with whoever built it, so output can be read back
+pub type SharedConsole = Arc<Mutex<dyn Console + Send>>;
+
+/// The console belle runs programs with: stdout, and keys read from stdin in
+/// raw mode. With a keyboard, keys come from its queue instead, and lines end
+/// in `\r\n` as the terminal is already in raw mode.
+#[derive(Debug, Default)]
+pub struct TerminalConsole {
+    keyboard: Option<Arc<KeyQueue>>,
+}
+
+impl TerminalConsole {
+    #[must_use]
+    pub fn new() -> TerminalConsole {
+        TerminalConsole::default()
+    }
+
+    /// A console that reads keys from `keyboard`, for a terminal a
+    /// [`TerminalKeyboard`](crate::TerminalKeyboard) keeps in raw mode
+    #[must_use]
+    pub fn with_keyboard(keyboard: Arc<KeyQueue>) -> TerminalConsole {
+        TerminalConsole {
+            keyboard: Some(keyboard),
+        }
+    }
+}
+
+impl Console for TerminalConsole {
+    fn write_number(&mut self, number: &dyn fmt::Display) {
+        self.write_line(&number.to_string());
+    }
+
+    fn write_line(&mut self, text: &str) {
+        if self.keyboard.is_some() {
+            print!("{text}\r\n");
+            let _ = io::stdout().flush();
+        } else {
+            println!("{text}");
+        }
+    }
+
+    fn clear(&mut self) {
+        cls();
+    }
+
+    fn read_key(&mut self) -> u8 {
+        use crossterm::terminal;
+        if let Some(keyboard) = &self.keyboard {
+            return (keyboard.wait() & 0xFF) as u8;
+        }
+        // stdin may not be a terminal, in which case the byte is read as it is
+        let raw = terminal::enable_raw_mode().is_ok();
+        let mut buffer = [0; 1];
+        let _ = io::stdin().read_exact(&mut buffer);
+        if raw {
+            let _ = terminal::disable_raw_mode();
+        }
+        let _ = io::stdout().flush();
+        buffer[0]
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A console that reads keys from a script and keeps everything written to
+/// it, for running programs without a terminal. Clearing the screen is
+/// recorded as a form feed (`\x0c`), and delays are added up instead of slept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryConsole {
+    pub input: VecDeque<u8>,
+    pub output: String,
+    pub delayed: Duration,
+}
+
+impl MemoryConsole {
+    /// A console that answers `int 9` with each byte of `input` in turn
+    #[must_use]
+    pub fn new(input: &[u8]) -> MemoryConsole {
+        MemoryConsole {
+            input: input.iter().copied().collect(),
+            ..MemoryConsole::default()
+        }
+    }
+}
+
+impl Console for MemoryConsole {
+    fn write_number(&mut self, number: &dyn fmt::Display) {
+        self.output.push_str(&format!("{number}\n"));
+    }
+
+    fn write_line(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn clear(&mut self) {
+        self.output.push('\x0c');
+    }
+
+    fn read_key(&mut self) -> u8 {
+        self.input.pop_front().unwrap_or(0)
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        self.delayed += duration;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    #[test]
+    fn memory_console_records_everything() {
+        let mut console = MemoryConsole::new(b"ab");
+        console.write_number(&-3);
+        console.write_number(&1.5);
+        console.clear();
+        console.write_line("hi");
+        console.delay(Duration::from_secs(1));
+        console.delay(Duration::from_millis(500));
+        assert_eq!(console.output, "-3\n1.5\n\x0chi\n");
+        assert_eq!(console.delayed, Duration::from_millis(1500));
+        assert_eq!(
+            [console.read_key(), console.read_key(), console.read_key()],
+            [b'a', b'b', 0]
+        );
+    }
+
+    /// A console that only counts what is asked of it
+    #[derive(Default)]
+    struct Counting {
+        keys: u8,
+        lines: usize,
+    }
+
+    impl Console for Counting {
+        fn write_number(&mut self, _: &dyn fmt::Display) {
+            self.lines += 1;
+        }
+
+        fn write_line(&mut self, _: &str) {
+            self.lines += 1;
+        }
+
+        fn clear(&mut self) {}
+
+        fn read_key(&mut self) -> u8 {
+            self.keys += 1;
+            self.keys
+        }
+
+        fn delay(&mut self, _: Duration) {}
+    }
+
+    #[test]
+    fn programs_use_the_console_they_are_given() {
+        let console = Arc::new(Mutex::new(Counting::default()));
+        let mut cpu = Machine::new().console(console.clone()).build();
+        // int 9, int 9, int 0, hlt
+        cpu.load_binary(&vec![
+            0xD109_u16 as i16,
+            0xD109_u16 as i16,
+            0xD100_u16 as i16,
+            0,
+        ]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.int_reg[0], 2);
+        let console = console.lock().unwrap();
+        assert_eq!((console.keys, console.lines), (2, 1));
+    }
+}
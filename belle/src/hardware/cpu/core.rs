@@ -1,4 +1,5 @@
 use crate::*;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use std::vec::Vec;
@@ -28,6 +29,20 @@ pub struct CPU {
     pub hit_max_clk: bool,
     pub do_not_run: bool,
     pub err: bool,
+    pub options: MachineOptions,
+    /// Clock cycles run so far
+    pub clock: u32,
+    /// Snapshots taken while running, when `options.record` is set
+    pub trace: Trace,
+    /// Where `int` calls for I/O go
+    pub console: SharedConsole,
+    /// Devices mapped into memory
+    pub bus: Bus,
+    pub interrupts: InterruptController,
+    /// Addresses stored to during the last clock cycle
+    pub stores: Vec<usize>,
+    /// Labels and source lines of the program, if `basm -g` wrote them
+    pub symbols: Option<Arc<DebugSymbols>>,
 }
 
 impl Default for CPU {
@@ -37,6 +52,7 @@ impl Default for CPU {
 }
 
 impl CPU {
+    /// A CPU with the default options, see [`Machine`] to choose them
     #[must_use]
     pub fn new() -> CPU {
         CPU {
@@ -62,10 +78,34 @@ impl CPU {
             hit_max_clk: false,
             do_not_run: false,
             err: false,
+            options: MachineOptions::default(),
+            clock: 0,
+            trace: Trace::new(),
+            console: Arc::new(Mutex::new(TerminalConsole::new())),
+            bus: Bus::default(),
+            interrupts: InterruptController::default(),
+            stores: Vec::new(),
+            symbols: None,
         }
     }
 
+    /// What the program's symbols say about `address`, see [`DebugSymbols::describe`]
+    #[must_use]
+    pub fn describe_address(&self, address: u16) -> Option<String> {
+        self.symbols.as_ref()?.describe(address)
+    }
+
     pub fn load_binary(&mut self, binary: &Vec<i16>) {
+        if Executable::is_executable(binary) {
+            match Executable::parse(binary) {
+                Ok(executable) => self.load_executable(&executable),
+                Err(e) => {
+                    self.report(EmuError::InvalidExecutable(e));
+                    self.do_not_run = true;
+                }
+            }
+            return;
+        }
         let mut counter = 0;
         let mut start_found = false;
 
@@ -73,33 +113,33 @@ impl CPU {
             if (element >> 9) == 1 {
                 // start directive
                 if start_found {
-                    EmuError::Duplicate(".start directives".to_string()).err();
+                    self.report(EmuError::Duplicate(".start directives".to_string()));
                     self.do_not_run = true;
                 }
                 self.starts_at = (element & 0b111111111) as u16;
-                if CONFIG.verbose {
+                if self.options.verbose {
                     println!(".start directive found.");
                 }
                 start_found = true;
-                if CONFIG.verbose {
+                if self.options.verbose {
                     println!("program starts at {}", self.starts_at);
                 }
                 continue;
             } else if (element >> 9) == 2 {
                 self.sp = (element & 0b111111111) as u16;
-                if CONFIG.verbose {
+                if self.options.verbose {
                     println!(".ssp directive found");
                 }
                 continue;
             } else if (element >> 9) == 3 {
                 self.bp = (element & 0b111111111) as u16;
-                if CONFIG.verbose {
+                if self.options.verbose {
                     println!(".sbp directive found");
                 }
                 continue;
             }
             self.memory[counter + self.starts_at as usize] = Some(*element);
-            if CONFIG.verbose {
+            if self.options.verbose {
                 println!("Element {element:016b} loaded into memory");
             }
 
@@ -107,6 +147,36 @@ impl CPU {
         }
         self.shift_memory();
         self.pc = self.starts_at;
+        self.trace.resync();
+    }
+
+    /// Places each segment at its load address and sets up the entry point and stack
+    pub fn load_executable(&mut self, executable: &Executable) {
+        self.starts_at = executable.entry;
+        if let Some(sp) = executable.ssp {
+            self.sp = sp;
+        }
+        if let Some(bp) = executable.sbp {
+            self.bp = bp;
+        }
+        for segment in &executable.segments {
+            if self.options.verbose {
+                println!(
+                    "{:?} segment of {} words loaded at {}",
+                    segment.kind,
+                    segment.words.len(),
+                    segment.address
+                );
+            }
+            for (offset, word) in segment.words.iter().enumerate() {
+                self.memory[usize::from(segment.address) + offset] = Some(*word);
+            }
+        }
+        if self.options.verbose {
+            println!("program starts at {}", self.starts_at);
+        }
+        self.pc = self.starts_at;
+        self.trace.resync();
     }
 
     fn shift_memory(&mut self) {
@@ -116,14 +186,14 @@ impl CPU {
             }
         }
 
-        if CONFIG.verbose {
+        if self.options.verbose {
             println!("Shifting memory...");
         }
 
         let some_count = self.memory.iter().filter(|&&e| e.is_some()).count();
 
         if some_count as u32 + u32::from(self.starts_at) > MEMORY_SIZE.try_into().unwrap() {
-            EmuError::MemoryOverflow().err();
+            self.report(EmuError::MemoryOverflow());
         }
 
         let mut new_memory = Box::new([None; MEMORY_SIZE]);
@@ -139,7 +209,7 @@ impl CPU {
         std::mem::swap(&mut self.memory, &mut new_memory);
         self.pc = self.starts_at;
 
-        if CONFIG.verbose {
+        if self.options.verbose {
             println!("Shift completed.");
         }
     }
@@ -150,96 +220,105 @@ impl CPU {
         if self.do_not_run {
             return Ok(());
         }
-        if CONFIG.verbose {
+        if self.options.verbose {
             println!("  Starts At MemAddr: {}", self.starts_at);
         }
         while self.running {
-            if !CONFIG.debug {
-                let _ = ctrlc::set_handler(move || {
-                    println!("Halting...");
-                    std::process::exit(0);
-                });
+            self.step()?;
+        }
+        self.halted();
+        Ok(())
+    }
+
+    /// Runs one clock cycle: the instruction at the program counter, then the
+    /// devices, then any interrupt that is due
+    pub fn step(&mut self) -> Result<(), UnrecoverableError> {
+        self.stores.clear();
+        self.clock += 1;
+        if self.options.time_delay > 0 {
+            thread::sleep(Duration::from_millis(self.options.time_delay.into()));
+        }
+
+        // Check for segmentation fault
+        match self.memory.get(self.pc as usize).copied().flatten() {
+            Some(instruction) => {
+                self.ip = self.pc;
+                self.ir = instruction;
             }
-            let mut clock = CLOCK.lock().unwrap(); // might panic
-            *clock += 1;
-            thread::sleep(Duration::from_millis(CONFIG.time_delay.unwrap().into()));
-            std::mem::drop(clock); // clock must go bye bye so it unlocks
-
-            // Check for segmentation fault
-            match self.memory[self.pc as usize] {
-                Some(instruction) => {
-                    self.ip = self.pc;
-                    self.ir = instruction;
-                }
-                None => {
-                    if CONFIG.verbose {
-                        println!("PC: {}", self.pc);
-                    }
-                    return Err(UnrecoverableError::SegmentationFault(
-                        self.pc,
-                        Some("Segmentation fault while finding next instruction".to_string()),
-                    ));
+            None => {
+                if self.options.verbose {
+                    println!("PC: {}", self.pc);
                 }
+                return Err(UnrecoverableError::SegmentationFault(
+                    self.pc,
+                    Some("Segmentation fault while finding next instruction".to_string()),
+                ));
             }
-            let parsed_ins = self.parse_instruction();
-            if let Err(e) = self.execute_instruction(&parsed_ins) {
+        }
+        let parsed_ins = self.parse_instruction();
+        if let Err(e) = self.execute_instruction(&parsed_ins) {
+            self.running = false;
+            return Err(e);
+        }
+        self.bus.tick(self.clock);
+        if self.running {
+            if let Err(e) = self.check_interrupts() {
                 self.running = false;
                 return Err(e);
             }
+        }
 
-            if CONFIG.debug || CONFIG.verbose {
-                self.record_state();
-            }
+        if self.options.record {
+            self.record_state();
+        }
 
-            let clock = CLOCK.lock().unwrap();
-            if CONFIG.verbose {
-                cpu::CPU::display_state(&clock);
-            }
+        if self.options.verbose {
+            self.display_state(self.clock);
+        }
 
-            if self.oflag && self.hlt_on_overflow {
-                self.running = false;
-            }
+        if self.oflag && self.hlt_on_overflow {
+            self.running = false;
+        }
 
-            if let Some(v) = self.max_clk {
-                if *clock == v as u32 {
-                    self.running = false;
-                    if CONFIG.verbose {
-                        println!("Clock limit reached");
-                    }
+        if let Some(v) = self.max_clk {
+            if self.clock == v as u32 {
+                self.running = false;
+                if self.options.verbose {
+                    println!("Clock limit reached");
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Prints what the options ask for once the CPU has stopped running. The
+    /// cycle that stopped it was already recorded by [`CPU::step`], so
+    /// stepping back from here undoes the instruction that halted.
+    pub fn halted(&mut self) {
+        if self.options.verbose {
+            println!("Halting...");
+        }
 
-        if !self.running {
-            if CONFIG.verbose {
-                println!("Halting...");
+        if self.options.pretty {
+            for i in 0..=3 {
+                println!(
+                    "Register {}: {}, {:016b}, {:04x}",
+                    i, self.int_reg[i], self.int_reg[i], self.int_reg[i]
+                );
             }
-            let mut clock = CLOCK.lock().unwrap(); // might panic
-            *clock += 1;
-            std::mem::drop(clock);
-            self.record_state();
-
-            let clock = CLOCK.lock().unwrap(); // might panic
-            if CONFIG.verbose {
-                cpu::CPU::display_state(&clock);
+            for i in 0..=1 {
+                println!("Uint Register {}: {}", i, self.uint_reg[i]);
             }
-
-            if CONFIG.pretty {
-                for i in 0..=3 {
-                    println!(
-                        "Register {}: {}, {:016b}, {:04x}",
-                        i, self.int_reg[i], self.int_reg[i], self.int_reg[i]
-                    );
-                }
-                for i in 0..=1 {
-                    println!("Uint Register {}: {}", i, self.uint_reg[i]);
-                }
-                for i in 0..=1 {
-                    println!("Float Register {}: {}", i, self.float_reg[i]);
-                }
+            for i in 0..=1 {
+                println!("Float Register {}: {}", i, self.float_reg[i]);
             }
         }
+    }
 
-        Ok(())
+    /// Prints an emulator error unless the CPU is quiet
+    fn report(&self, error: EmuError) {
+        if !self.options.quiet {
+            error.err();
+        }
     }
 }
@@ -35,10 +35,10 @@ impl CPU {
             self.oflag = true;
             if self.hlt_on_overflow {
                 self.running = false;
-                if CONFIG.verbose {
+                if self.options.verbose {
                     println!("Halting...");
                 }
-                if CONFIG.pretty {
+                if self.options.pretty {
                     for i in 0..=3 {
                         println!(
                             "Register {}: {}, {:016b}, {:04x}",
@@ -61,6 +61,13 @@ impl CPU {
         Ok(())
     }
 
+    /// Prints a recoverable error when the CPU is verbose or being debugged
+    pub fn warn(&self, error: &RecoverableError) {
+        if self.options.debug || self.options.verbose {
+            eprint!("{error}");
+        }
+    }
+
     pub fn handle_segmentation_fault(&mut self, message: &str) -> UnrecoverableError {
         self.running = false;
         self.err = true;
@@ -1,6 +1,7 @@
 use crate::Argument::*;
 use crate::*;
-use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
 
 impl CPU {
     pub fn handle_add(
@@ -44,7 +45,7 @@ impl CPU {
             };
 
             if let Err(e) = self.check_overflow(new_value, *n as u16) {
-                eprint!("{e}");
+                self.warn(&e);
             }
         }
         Ok(())
@@ -64,14 +65,16 @@ impl CPU {
                 self.set_register_value(arg, v.into())?;
                 if self.sp > self.bp {
                     if self.sp != self.bp {
-                        println!("{}", RecoverableError::BackwardStack(self.pc, None));
+                        self.warn(&RecoverableError::BackwardStack(self.pc, None));
                     }
                     self.memory[self.sp as usize] = None;
+                    self.stores.push(self.sp as usize);
                     if self.sp != self.bp {
                         self.sp -= 1;
                     }
                 } else {
                     self.memory[self.sp as usize] = None;
+                    self.stores.push(self.sp as usize);
                     if self.sp != self.bp {
                         self.sp += 1;
                     }
@@ -152,7 +155,7 @@ impl CPU {
             };
 
             if let Err(e) = self.check_overflow(new_value, *n as u16) {
-                eprint!("{e}");
+                self.warn(&e);
             }
         }
         Ok(())
@@ -163,11 +166,13 @@ impl CPU {
             self.pc = v as u16;
             if self.sp > self.bp {
                 self.memory[self.sp as usize] = None;
+                self.stores.push(self.sp as usize);
                 if self.sp != self.bp {
                     self.sp -= 1;
                 }
             } else {
                 self.memory[self.sp as usize] = None;
+                self.stores.push(self.sp as usize);
                 if self.sp != self.bp {
                     self.sp += 1;
                 }
@@ -193,7 +198,7 @@ impl CPU {
                 n if n > 5 => return Err(self.report_invalid_register()),
                 _ => {
                     if let Err(e) = self.check_overflow(source as i64, *n as u16) {
-                        eprint!("{e}");
+                        self.warn(&e);
                         return Ok(());
                     }
                     self.int_reg[*n as usize] = source as i16;
@@ -217,7 +222,7 @@ impl CPU {
                     Some("segmentation fault whilst storing to an address. OOB".to_string()),
                 ));
             }
-            self.memory[index] = Some(source);
+            self.write_memory(index, source);
         } else if let RegPtr(n) = arg1 {
             let addr = match self.get_value(&Register(*n)) {
                 Ok(a) => a as usize,
@@ -227,7 +232,7 @@ impl CPU {
             if addr >= self.memory.len() {
                 return Err(UnrecoverableError::IllegalInstruction(self.pc, None));
             }
-            self.memory[addr] = Some(source);
+            self.write_memory(addr, source);
         }
 
         Ok(())
@@ -314,7 +319,7 @@ impl CPU {
             };
 
             if let Err(e) = self.check_overflow(new_value, *n as u16) {
-                eprint!("{e}");
+                self.warn(&e);
             }
         }
         Ok(())
@@ -330,7 +335,7 @@ impl CPU {
         }
         if self.sp > self.bp || self.backward_stack {
             if self.sp != self.bp {
-                println!("{}", RecoverableError::BackwardStack(self.pc, None));
+                self.warn(&RecoverableError::BackwardStack(self.pc, None));
             }
             if self.sp != self.bp || self.memory[self.bp as usize].is_some() {
                 self.sp += 1;
@@ -345,6 +350,7 @@ impl CPU {
             }
 
             self.memory[self.sp as usize] = Some(val as i16);
+            self.stores.push(self.sp as usize);
             if self.sp >= self.bp {
                 self.backward_stack = true;
             }
@@ -361,6 +367,7 @@ impl CPU {
                 self.sp -= 1;
             }
             self.memory[self.sp as usize] = Some(val as i16);
+            self.stores.push(self.sp as usize);
         }
         Ok(())
     }
@@ -386,28 +393,27 @@ impl CPU {
     }
 
     pub fn handle_int(&mut self, arg: &Argument) -> Result<(), UnrecoverableError> {
-        if CONFIG.fuzz {
+        if self.options.fuzz {
             return Ok(());
         }
         let code = self.get_value(arg)? as u16;
+        let console = Arc::clone(&self.console);
+        let mut console = console.lock().unwrap();
         match code {
-            0_u16..=3_u16 => {
-                println!("{}", self.int_reg[code as usize]);
-            }
-            4 => println!("{}", self.uint_reg[0]),
-            5 => println!("{}", self.uint_reg[1]),
-            6 => println!("{}", self.float_reg[0]),
-            7 => println!("{}", self.float_reg[1]),
+            0_u16..=3_u16 => console.write_number(&self.int_reg[code as usize]),
+            4 => console.write_number(&self.uint_reg[0]),
+            5 => console.write_number(&self.uint_reg[1]),
+            6 => console.write_number(&self.float_reg[0]),
+            7 => console.write_number(&self.float_reg[1]),
             8 => {
-                cls();
+                console.clear();
                 let starting_point = self.int_reg[0];
                 let end_point = self.int_reg[1];
-                let memory = &self.memory;
                 let mut toprint = String::from("");
                 if end_point < 0
-                    || end_point as usize >= memory.len()
+                    || end_point as usize >= MEMORY_SIZE
                     || starting_point < 0
-                    || starting_point as usize >= memory.len()
+                    || starting_point as usize >= MEMORY_SIZE
                 {
                     return Err(self.handle_segmentation_fault(
                         "Segmentation fault. Memory index out of bounds on interrupt call 8.",
@@ -415,13 +421,14 @@ impl CPU {
                 }
 
                 for index in starting_point..=end_point {
-                    if index < 0 || index as usize >= memory.len() {
+                    if index < 0 || index as usize >= MEMORY_SIZE {
                         return Err(self.handle_segmentation_fault(
                             "Segmentation fault. Memory index out of bounds on interrupt call 8.",
                         ));
                     }
 
-                    if let Some(value) = memory[index as usize] {
+                    // strings can be read from devices, such as the framebuffer
+                    if let Some(value) = self.read_memory(index as usize) {
                         match u32::try_from(value) {
                             Ok(v) => {
                                 if let Some(character) = char::from_u32(v) {
@@ -440,21 +447,10 @@ impl CPU {
                         }
                     }
                 }
-                println!("{toprint}");
-            }
-            9 => {
-                use crossterm::terminal;
-                terminal::enable_raw_mode().unwrap();
-                let mut buffer = [0; 1];
-                let _ = io::stdin().read_exact(&mut buffer);
-                self.int_reg[0] = buffer[0] as i16;
-
-                terminal::disable_raw_mode().unwrap();
-                io::stdout().flush().expect("Failed to flush stdout");
-            }
-            10 => {
-                std::thread::sleep(std::time::Duration::from_secs(1));
+                console.write_line(&toprint);
             }
+            9 => self.int_reg[0] = i16::from(console.read_key()),
+            10 => console.delay(Duration::from_secs(1)),
             11 => self.zflag = true,
             12 => self.zflag = false,
             13 => self.zflag = !self.zflag,
@@ -480,16 +476,30 @@ impl CPU {
             60 => self.sp = self.uint_reg[0],
             61 => self.bp = self.uint_reg[0],
 
+            70 => self.return_from_interrupt()?,
+            71 => self.interrupts.enabled = true,
+            72 => self.interrupts.enabled = false,
+            73 => self.interrupts.mask = self.uint_reg[0],
+            74 => self.interrupts.vector_table = self.uint_reg[0],
+            75 => {
+                let line = self.uint_reg[0];
+                if line < u16::from(INTERRUPT_LINES) {
+                    self.interrupts.line(line as u8).raise();
+                } else {
+                    self.warn(&RecoverableError::UnknownFlag(
+                        self.pc,
+                        Some(format!("There is no interrupt line {line}")),
+                    ));
+                }
+            }
+
             // 10 - 20 set flags
             // 20 - 30 unset them
             // 30 - 40 invert them
-            _ => println!(
-                "{}",
-                RecoverableError::UnknownFlag(
-                    self.pc,
-                    Some(String::from("Occurred whilst handling INT")),
-                )
-            ),
+            _ => self.warn(&RecoverableError::UnknownFlag(
+                self.pc,
+                Some(String::from("Occurred whilst handling INT")),
+            )),
         }
         Ok(())
     }
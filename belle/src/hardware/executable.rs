@@ -0,0 +1,259 @@
+use crate::MEMORY_SIZE;
+
+/// The first two words of an executable, `"BIMG"`
+pub const IMAGE_MAGIC: [i16; 2] = [0x4249, 0x4D47];
+/// The newest executable format this belle understands
+pub const IMAGE_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentKind {
+    Text,
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub address: u16,
+    pub words: Vec<i16>,
+}
+
+/// A program written by `basm` or `bld`. See the assembler's documentation
+/// for the layout of each version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Executable {
+    pub entry: u16,
+    pub ssp: Option<u16>,
+    pub sbp: Option<u16>,
+    pub segments: Vec<Segment>,
+}
+
+impl Executable {
+    /// Whether `words` start with the executable magic number. Anything else
+    /// is a raw image, which is only loaded with `--raw`.
+    #[must_use]
+    pub fn is_executable(words: &[i16]) -> bool {
+        words.starts_with(&IMAGE_MAGIC)
+    }
+
+    pub fn parse(words: &[i16]) -> Result<Executable, String> {
+        let mut reader = Reader { words, pos: 2 };
+        let version = reader.next()?;
+        let flags = reader.next()?;
+        let entry = reader.next()?;
+        let ssp = reader.next()?;
+        let sbp = reader.next()?;
+        let mut executable = Executable {
+            entry,
+            ssp: (flags & 1 != 0).then_some(ssp),
+            sbp: (flags & 2 != 0).then_some(sbp),
+            segments: Vec::new(),
+        };
+
+        match version {
+            1 => {
+                let count = reader.next()?;
+                executable.segments.push(Segment {
+                    kind: SegmentKind::Text,
+                    address: entry,
+                    words: reader.take(count)?.to_vec(),
+                });
+            }
+            2 => {
+                let checksum = u32::from(reader.next()?) << 16 | u32::from(reader.next()?);
+                let mut layout = Vec::new();
+                for _ in 0..reader.next()? {
+                    let kind = match reader.next()? {
+                        0 => SegmentKind::Text,
+                        1 => SegmentKind::Data,
+                        kind => return Err(format!("unknown segment kind {kind}")),
+                    };
+                    layout.push((kind, reader.next()?, reader.next()?));
+                }
+                let body = reader.pos;
+                for (kind, address, count) in layout {
+                    executable.segments.push(Segment {
+                        kind,
+                        address,
+                        words: reader.take(count)?.to_vec(),
+                    });
+                }
+                if flags & 4 != 0 {
+                    let bytes: Vec<u8> = words[body..reader.pos]
+                        .iter()
+                        .flat_map(|w| w.to_be_bytes())
+                        .collect();
+                    if crc32(&bytes) != checksum {
+                        return Err("checksum does not match, the file is damaged".to_string());
+                    }
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "executable version {version} is not supported, the newest is {IMAGE_VERSION}"
+                ))
+            }
+        }
+
+        if reader.pos != words.len() {
+            return Err("trailing data after the last segment".to_string());
+        }
+        for segment in &executable.segments {
+            if usize::from(segment.address) + segment.words.len() > MEMORY_SIZE {
+                return Err(format!(
+                    "segment at {} with {} words does not fit in memory",
+                    segment.address,
+                    segment.words.len()
+                ));
+            }
+        }
+        Ok(executable)
+    }
+}
+
+struct Reader<'a> {
+    words: &'a [i16],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn next(&mut self) -> Result<u16, String> {
+        Ok(self.take(1)?[0] as u16)
+    }
+
+    fn take(&mut self, count: u16) -> Result<&'a [i16], String> {
+        let end = self.pos + usize::from(count);
+        let words = self
+            .words
+            .get(self.pos..end)
+            .ok_or_else(|| "executable is truncated".to_string())?;
+        self.pos = end;
+        Ok(words)
+    }
+}
+
+/// The CRC-32 (as used by zip and PNG) of `bytes`
+#[must_use]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An executable written by basm, as the words belle loads
+    fn written(checksum: bool) -> Vec<i16> {
+        let mut executable = basm::Executable {
+            entry: 200,
+            ssp: Some(50),
+            checksum,
+            ..basm::Executable::default()
+        };
+        executable.push_words(200, &[1, 2, 3], basm::SegmentKind::Text);
+        executable.push_words(300, &[-4], basm::SegmentKind::Data);
+        executable
+            .to_bytes()
+            .chunks(2)
+            .map(|pair| i16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn crc32_known_answer() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"123456789"), basm::crc32(b"123456789"));
+    }
+
+    #[test]
+    fn reads_what_basm_writes() {
+        for checksum in [true, false] {
+            let words = written(checksum);
+            assert!(Executable::is_executable(&words));
+            let executable = Executable::parse(&words).unwrap();
+            assert_eq!(executable.entry, 200);
+            assert_eq!(executable.ssp, Some(50));
+            assert_eq!(executable.sbp, None);
+            assert_eq!(
+                executable.segments,
+                [
+                    Segment {
+                        kind: SegmentKind::Text,
+                        address: 200,
+                        words: vec![1, 2, 3],
+                    },
+                    Segment {
+                        kind: SegmentKind::Data,
+                        address: 300,
+                        words: vec![-4],
+                    }
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn version_1_has_one_segment_at_the_entry_point() {
+        let words = [IMAGE_MAGIC[0], IMAGE_MAGIC[1], 1, 2, 150, 0, 9, 2, 7, 8];
+        let executable = Executable::parse(&words).unwrap();
+        assert_eq!(executable.entry, 150);
+        assert_eq!(executable.ssp, None);
+        assert_eq!(executable.sbp, Some(9));
+        assert_eq!(
+            executable.segments,
+            [Segment {
+                kind: SegmentKind::Text,
+                address: 150,
+                words: vec![7, 8],
+            }]
+        );
+    }
+
+    #[test]
+    fn damaged_executables() {
+        let error = |words: &[i16]| Executable::parse(words).unwrap_err();
+
+        let mut damaged = written(true);
+        *damaged.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            error(&damaged),
+            "checksum does not match, the file is damaged"
+        );
+        // without a checksum the same damage goes unnoticed
+        let mut unchecked = written(false);
+        *unchecked.last_mut().unwrap() ^= 1;
+        assert!(Executable::parse(&unchecked).is_ok());
+
+        let mut newer = written(true);
+        newer[2] = 3;
+        assert_eq!(
+            error(&newer),
+            "executable version 3 is not supported, the newest is 2"
+        );
+
+        let words = written(true);
+        assert_eq!(error(&words[..5]), "executable is truncated");
+        assert_eq!(error(&words[..words.len() - 1]), "executable is truncated");
+
+        let mut trailing = written(true);
+        trailing.push(0);
+        assert_eq!(error(&trailing), "trailing data after the last segment");
+
+        let too_big = [IMAGE_MAGIC[0], IMAGE_MAGIC[1], 1, 0, -2, 0, 0, 2, 7, 8];
+        assert_eq!(
+            error(&too_big),
+            "segment at 65534 with 2 words does not fit in memory"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::*;
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{Clear, ClearType},
+    QueueableCommand,
+};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Where belle maps its framebuffer, just past the addresses jumps can reach
+pub const FRAMEBUFFER_ADDRESS: u16 = 4096;
+pub const SCREEN_WIDTH: u16 = 80;
+pub const SCREEN_HEIGHT: u16 = 25;
+/// The number of addresses the framebuffer takes, one per cell
+pub const FRAMEBUFFER_SIZE: u16 = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+/// How often a framebuffer drawn in the terminal is brought up to date
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// The 16 colours an attribute nibble picks from, in the order PC text mode uses
+const PALETTE: [Color; 16] = [
+    Color::Black,
+    Color::DarkBlue,
+    Color::DarkGreen,
+    Color::DarkCyan,
+    Color::DarkRed,
+    Color::DarkMagenta,
+    Color::DarkYellow,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::Blue,
+    Color::Green,
+    Color::Cyan,
+    Color::Red,
+    Color::Magenta,
+    Color::Yellow,
+    Color::White,
+];
+
+/// An 80x25 screen of text, one address per cell, row by row from the top left.
+///
+/// The low byte of a cell is its character, and the high byte its colours: the
+/// foreground in the low nibble and the background in the high nibble, from
+/// the usual 16 colour palette (`0x07` is grey on black, `0x1F` white on
+/// blue). An attribute of 0 leaves the terminal's own colours. Characters
+/// other than printable ASCII show as spaces.
+///
+/// A headless framebuffer only keeps the cells, which [`Framebuffer::text`]
+/// reads back. One made with [`Framebuffer::terminal`] also draws itself on
+/// stdout as the program runs, redrawing only the cells that changed.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    cells: Vec<u16>,
+    changed: Vec<bool>,
+    terminal: bool,
+    drawing: bool,
+    last_frame: Instant,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer {
+            cells: vec![0; usize::from(FRAMEBUFFER_SIZE)],
+            changed: vec![true; usize::from(FRAMEBUFFER_SIZE)],
+            terminal: false,
+            drawing: false,
+            last_frame: Instant::now(),
+        }
+    }
+}
+
+impl Framebuffer {
+    /// A framebuffer that is never drawn
+    #[must_use]
+    pub fn new() -> Framebuffer {
+        Framebuffer::default()
+    }
+
+    /// A framebuffer drawn on stdout while the program runs. Call
+    /// [`Framebuffer::finish`] once it stops, to draw the last frame and give
+    /// the terminal back.
+    #[must_use]
+    pub fn terminal() -> Framebuffer {
+        Framebuffer {
+            terminal: true,
+            ..Framebuffer::default()
+        }
+    }
+
+    /// The cell at column `x` of row `y`
+    ///
+    /// # Panics
+    ///
+    /// If the cell is off the screen
+    #[must_use]
+    pub fn cell(&self, x: u16, y: u16) -> u16 {
+        assert!(
+            x < SCREEN_WIDTH && y < SCREEN_HEIGHT,
+            "({x}, {y}) is off the screen"
+        );
+        self.cells[usize::from(y * SCREEN_WIDTH + x)]
+    }
+
+    /// The characters on the screen, one line per row, without colours,
+    /// trailing spaces or blank rows at the bottom
+    #[must_use]
+    pub fn text(&self) -> String {
+        let mut rows: Vec<String> = self
+            .cells
+            .chunks(usize::from(SCREEN_WIDTH))
+            .map(|row| {
+                let row: String = row.iter().map(|&cell| character(cell)).collect();
+                row.trim_end().to_string()
+            })
+            .collect();
+        while rows.last().is_some_and(String::is_empty) {
+            rows.pop();
+        }
+        rows.join("\n")
+    }
+
+    /// Draws the cells that changed since the last call to `out`
+    pub fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let mut cursor = None;
+        let mut colours = None;
+        for index in 0..self.cells.len() {
+            if !self.changed[index] {
+                continue;
+            }
+            self.changed[index] = false;
+            let position = (index as u16 % SCREEN_WIDTH, index as u16 / SCREEN_WIDTH);
+            if cursor != Some(position) {
+                out.queue(MoveTo(position.0, position.1))?;
+            }
+            let cell = self.cells[index];
+            let attribute = cell >> 8;
+            if colours != Some(attribute) {
+                out.queue(ResetColor)?;
+                if attribute != 0 {
+                    out.queue(SetForegroundColor(PALETTE[usize::from(attribute & 0xF)]))?;
+                    out.queue(SetBackgroundColor(
+                        PALETTE[usize::from(attribute >> 4 & 0xF)],
+                    ))?;
+                }
+                colours = Some(attribute);
+            }
+            out.queue(Print(character(cell)))?;
+            cursor = Some((position.0 + 1, position.1));
+        }
+        if colours.is_some() {
+            out.queue(ResetColor)?;
+        }
+        out.flush()
+    }
+
+    /// Draws what is left to draw of a terminal framebuffer and puts the
+    /// cursor back, below the screen
+    pub fn finish(&mut self) {
+        if !self.terminal {
+            return;
+        }
+        self.draw_frame();
+        self.terminal = false;
+        let mut stdout = io::stdout();
+        let _ = stdout.queue(MoveTo(0, SCREEN_HEIGHT));
+        let _ = stdout.queue(Show);
+        let _ = stdout.flush();
+    }
+
+    fn draw_frame(&mut self) {
+        let mut stdout = io::stdout();
+        if !self.drawing {
+            self.drawing = true;
+            let _ = stdout.queue(Clear(ClearType::All));
+            let _ = stdout.queue(Hide);
+        }
+        let _ = self.draw(&mut stdout);
+        self.last_frame = Instant::now();
+    }
+}
+
+fn character(cell: u16) -> char {
+    match (cell & 0xFF) as u8 {
+        c @ b' '..=b'~' => c as char,
+        _ => ' ',
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: u16) -> Option<i16> {
+        self.cells.get(usize::from(offset)).map(|&cell| cell as i16)
+    }
+
+    fn write(&mut self, offset: u16, value: i16) {
+        let index = usize::from(offset);
+        if index < self.cells.len() && self.cells[index] != value as u16 {
+            self.cells[index] = value as u16;
+            self.changed[index] = true;
+        }
+    }
+
+    fn tick(&mut self, _clock: u32) {
+        if self.terminal && self.last_frame.elapsed() >= FRAME_TIME {
+            self.draw_frame();
+        }
+    }
+}
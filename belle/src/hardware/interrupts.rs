@@ -0,0 +1,258 @@
+use crate::*;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+/// The number of interrupt lines, and of entries in the vector table
+pub const INTERRUPT_LINES: u8 = 16;
+
+/// Lets a device ask for an interrupt from outside the CPU, from any thread
+#[derive(Debug, Clone)]
+pub struct InterruptLine {
+    line: u8,
+    pending: Arc<AtomicU16>,
+}
+
+impl InterruptLine {
+    /// Marks the line pending. The CPU takes it after the current instruction,
+    /// once interrupts are enabled and the line is not masked.
+    pub fn raise(&self) {
+        self.pending.fetch_or(1 << self.line, Ordering::SeqCst);
+    }
+
+    #[must_use]
+    pub fn line(&self) -> u8 {
+        self.line
+    }
+}
+
+/// Hardware interrupts.
+///
+/// Each of the 16 lines has a pending bit, set by [`InterruptLine::raise`],
+/// and a mask bit. When interrupts are enabled, the lowest pending line that
+/// is not masked is taken between instructions: the address of the next
+/// instruction is pushed, interrupts are disabled, and execution continues at
+/// the address in the line's entry of the vector table. `int 70` returns.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptController {
+    pub enabled: bool,
+    /// Lines whose bit is set are never taken
+    pub mask: u16,
+    /// Address of the vector table, which holds one handler address per line
+    pub vector_table: u16,
+    pending: Arc<AtomicU16>,
+}
+
+impl InterruptController {
+    /// A handle a device can raise `line` with
+    ///
+    /// # Panics
+    ///
+    /// If `line` is not below [`INTERRUPT_LINES`]
+    #[must_use]
+    pub fn line(&self, line: u8) -> InterruptLine {
+        assert!(line < INTERRUPT_LINES, "there is no interrupt line {line}");
+        InterruptLine {
+            line,
+            pending: Arc::clone(&self.pending),
+        }
+    }
+
+    /// Lines that are waiting to be taken, masked or not
+    #[must_use]
+    pub fn pending(&self) -> u16 {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// The line to take now, clearing its pending bit
+    fn next(&self) -> Option<u8> {
+        if !self.enabled {
+            return None;
+        }
+        let ready = self.pending() & !self.mask;
+        if ready == 0 {
+            return None;
+        }
+        let line = ready.trailing_zeros() as u8;
+        self.pending.fetch_and(!(1 << line), Ordering::SeqCst);
+        Some(line)
+    }
+}
+
+impl CPU {
+    /// Takes a pending interrupt, if there is one that can be taken
+    pub fn check_interrupts(&mut self) -> Result<(), UnrecoverableError> {
+        let Some(line) = self.interrupts.next() else {
+            return Ok(());
+        };
+        let entry = usize::from(self.interrupts.vector_table) + usize::from(line);
+        let Some(handler) = self.read_memory(entry) else {
+            return Err(self.handle_segmentation_fault(&format!(
+                "Segmentation fault while taking interrupt {line}.\nIts vector table entry is empty."
+            )));
+        };
+        if self.options.verbose {
+            println!("Interrupt {line}, handler at {}", handler as u16);
+        }
+        self.handle_push(&Argument::Literal(self.pc as i16))?;
+        self.interrupts.enabled = false;
+        self.pc = handler as u16;
+        Ok(())
+    }
+
+    /// Returns from an interrupt handler to where the program was interrupted
+    pub fn return_from_interrupt(&mut self) -> Result<(), UnrecoverableError> {
+        self.handle_ret()?;
+        // execute_instruction moves past the instruction it ran
+        self.pc = self.pc.wrapping_sub(1);
+        self.interrupts.enabled = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use basm::{Assembler, AssemblerOptions, SourceFiles};
+
+    /// A CPU with `text` loaded, and the address of every label in it
+    fn load(text: &str) -> (CPU, impl Fn(&str) -> u16) {
+        let assembly = Assembler::with_files(
+            AssemblerOptions::default(),
+            SourceFiles::memory::<&str, &str>([]),
+        )
+        .assemble_source("main.asm", text);
+        assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+        let words: Vec<i16> = assembly
+            .to_bytes()
+            .chunks(2)
+            .map(|pair| i16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        let mut cpu = Machine::new().build();
+        cpu.load_binary(&words);
+        let symbols = assembly.symbols;
+        (cpu, move |name| symbols.get(name).unwrap().address as u16)
+    }
+
+    #[test]
+    fn lowest_unmasked_line_comes_first() {
+        let mut controller = InterruptController::default();
+        for line in [9, 2, 5] {
+            controller.line(line).raise();
+        }
+        assert_eq!(controller.next(), None, "interrupts start disabled");
+        controller.enabled = true;
+        controller.mask = 1 << 2;
+        assert_eq!(controller.next(), Some(5));
+        assert_eq!(controller.next(), Some(9));
+        assert_eq!(controller.next(), None);
+        assert_eq!(controller.pending(), 1 << 2, "masked lines stay pending");
+        controller.mask = 0;
+        assert_eq!(controller.next(), Some(2));
+        assert_eq!(controller.pending(), 0);
+    }
+
+    #[test]
+    fn lines_can_be_raised_from_other_threads() {
+        let controller = InterruptController::default();
+        let line = controller.line(7);
+        std::thread::spawn(move || line.raise()).join().unwrap();
+        assert_eq!(controller.pending(), 1 << 7);
+    }
+
+    #[test]
+    fn returning_from_a_handler_restores_the_program() {
+        let (mut cpu, address) = load(
+            "\
+    mov r4, 120
+    int 74
+    int 71
+    mov r4, 3
+    int 75
+after:
+    add r1, 1
+    hlt
+tick:
+    add r0, 1
+    int 70
+.org 123
+.word @tick
+",
+        );
+        let sp = cpu.sp;
+        cpu.running = true;
+        while cpu.pc != address("tick") {
+            cpu.step().unwrap();
+        }
+        assert!(!cpu.interrupts.enabled);
+        assert_eq!(
+            cpu.memory[usize::from(cpu.sp)],
+            Some(address("after") as i16)
+        );
+
+        cpu.run().unwrap();
+        assert_eq!((cpu.int_reg[0], cpu.int_reg[1]), (1, 1));
+        assert!(cpu.interrupts.enabled);
+        assert_eq!(cpu.sp, sp);
+    }
+
+    #[test]
+    fn handlers_that_enable_interrupts_can_be_interrupted() {
+        let (mut cpu, _) = load(
+            "\
+    mov r4, 120
+    int 74
+    int 71
+    mov r4, 3
+    int 75
+    add r1, 1
+    hlt
+outer:
+    add r0, 1
+    mov r4, 1
+    int 75
+    int 71          ; line 1 is taken here, in the middle of this handler
+    add r0, 2
+    int 70
+inner:
+    mul r0, 10
+    int 70
+.org 121
+.word @inner
+.org 123
+.word @outer
+",
+        );
+        let sp = cpu.sp;
+        cpu.run().unwrap();
+        // (1 * 10) + 2, where waiting for the outer handler would give (1 + 2) * 10
+        assert_eq!(cpu.int_reg[0], 12);
+        assert_eq!(cpu.int_reg[1], 1);
+        assert_eq!(cpu.sp, sp);
+    }
+
+    #[test]
+    fn masked_lines_wait_until_unmasked() {
+        let (mut cpu, _) = load(
+            "\
+    mov r4, 120
+    int 74
+    mov r4, 8
+    int 73          ; mask line 3
+    int 71
+    mov r4, 3
+    int 75
+    add r1, 1       ; runs before the handler
+    mov r4, 0
+    int 73
+    hlt
+tick:
+    mul r1, 10
+    int 70
+.org 123
+.word @tick
+",
+        );
+        cpu.run().unwrap();
+        assert_eq!(cpu.int_reg[1], 10);
+    }
+}
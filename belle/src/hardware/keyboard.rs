@@ -0,0 +1,232 @@
+use crate::*;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::collections::VecDeque;
+use std::io;
+use std::panic;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// Where belle maps its keyboard, in reach of `ld`
+pub const KEYBOARD_ADDRESS: u16 = 504;
+/// The keyboard's interrupt line
+pub const KEYBOARD_INTERRUPT: u8 = 1;
+
+/// Key codes for keys that have no character
+pub const KEY_UP: u16 = 0x80;
+pub const KEY_DOWN: u16 = 0x81;
+pub const KEY_LEFT: u16 = 0x82;
+pub const KEY_RIGHT: u16 = 0x83;
+pub const KEY_HOME: u16 = 0x84;
+pub const KEY_END: u16 = 0x85;
+pub const KEY_PAGE_UP: u16 = 0x86;
+pub const KEY_PAGE_DOWN: u16 = 0x87;
+pub const KEY_INSERT: u16 = 0x88;
+/// F1, F2 up to F12 follow on from here
+pub const KEY_F1: u16 = 0x90;
+
+/// Modifier bits, in the high byte of a key code
+pub const KEY_SHIFT: u16 = 1 << 8;
+pub const KEY_CONTROL: u16 = 1 << 9;
+pub const KEY_ALT: u16 = 1 << 10;
+
+/// Keys waiting for the program, oldest first. Anything can push keys: the
+/// terminal with [`TerminalKeyboard`], or an embedder feeding a script.
+#[derive(Debug, Default)]
+pub struct KeyQueue {
+    keys: Mutex<Keys>,
+    pushed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Keys {
+    waiting: VecDeque<u16>,
+    /// A key came in since the keyboard last raised its interrupt
+    arrived: bool,
+}
+
+impl KeyQueue {
+    #[must_use]
+    pub fn new() -> KeyQueue {
+        KeyQueue::default()
+    }
+
+    pub fn push(&self, key: u16) {
+        let mut keys = self.keys.lock().unwrap();
+        keys.waiting.push_back(key);
+        keys.arrived = true;
+        self.pushed.notify_one();
+    }
+
+    /// The next key, if there is one
+    pub fn pop(&self) -> Option<u16> {
+        self.keys.lock().unwrap().waiting.pop_front()
+    }
+
+    /// The next key, waiting for one if there is none
+    pub fn wait(&self) -> u16 {
+        let mut keys = self.keys.lock().unwrap();
+        loop {
+            if let Some(key) = keys.waiting.pop_front() {
+                return key;
+            }
+            keys = self.pushed.wait(keys).unwrap();
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.keys.lock().unwrap().waiting.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.keys.lock().unwrap().waiting.clear();
+    }
+
+    fn take_arrived(&self) -> bool {
+        std::mem::take(&mut self.keys.lock().unwrap().arrived)
+    }
+}
+
+/// The program's side of a [`KeyQueue`]. It takes two addresses:
+///
+/// | Offset | Register | |
+/// | :----- | :------- | :- |
+/// | 0 | status | the number of keys waiting; any store throws them away |
+/// | 1 | data | the next key, taken off the queue, or 0 if there is none |
+///
+/// Neither ever waits for a key. The low byte of a key code is its ASCII
+/// character, or one of the `KEY_` codes such as [`KEY_UP`], and the high byte
+/// holds the [`KEY_SHIFT`], [`KEY_CONTROL`] and [`KEY_ALT`] bits. When keys
+/// arrive the keyboard raises its interrupt, if it has one.
+#[derive(Debug, Clone)]
+pub struct KeyboardDevice {
+    queue: Arc<KeyQueue>,
+    interrupt: Option<InterruptLine>,
+}
+
+impl KeyboardDevice {
+    #[must_use]
+    pub fn new(queue: Arc<KeyQueue>, interrupt: Option<InterruptLine>) -> KeyboardDevice {
+        KeyboardDevice { queue, interrupt }
+    }
+}
+
+impl Device for KeyboardDevice {
+    fn read(&mut self, offset: u16) -> Option<i16> {
+        match offset {
+            0 => Some(self.queue.len().min(i16::MAX as usize) as i16),
+            1 => Some(self.queue.pop().unwrap_or(0) as i16),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, offset: u16, _value: i16) {
+        if offset == 0 {
+            self.queue.clear();
+        }
+    }
+
+    fn tick(&mut self, _clock: u32) {
+        if let Some(interrupt) = &self.interrupt {
+            if self.queue.take_arrived() {
+                interrupt.raise();
+            }
+        }
+    }
+}
+
+/// The code a program sees for `key`, or `None` for keys it never sees
+#[must_use]
+pub fn key_code(key: &KeyEvent) -> Option<u16> {
+    let code = match key.code {
+        KeyCode::Char(c) if c.is_ascii() => c as u16,
+        KeyCode::Enter => 13,
+        KeyCode::Tab => 9,
+        KeyCode::BackTab => 9 | KEY_SHIFT,
+        KeyCode::Backspace => 8,
+        KeyCode::Esc => 27,
+        KeyCode::Delete => 127,
+        KeyCode::Up => KEY_UP,
+        KeyCode::Down => KEY_DOWN,
+        KeyCode::Left => KEY_LEFT,
+        KeyCode::Right => KEY_RIGHT,
+        KeyCode::Home => KEY_HOME,
+        KeyCode::End => KEY_END,
+        KeyCode::PageUp => KEY_PAGE_UP,
+        KeyCode::PageDown => KEY_PAGE_DOWN,
+        KeyCode::Insert => KEY_INSERT,
+        KeyCode::F(n @ 1..=12) => KEY_F1 + u16::from(n) - 1,
+        _ => return None,
+    };
+    let mut modifiers = 0;
+    if key.modifiers.contains(KeyModifiers::SHIFT) {
+        modifiers |= KEY_SHIFT;
+    }
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        modifiers |= KEY_CONTROL;
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        modifiers |= KEY_ALT;
+    }
+    Some(code | modifiers)
+}
+
+/// Feeds the keys pressed in the terminal into a [`KeyQueue`].
+///
+/// The terminal stays in raw mode from [`TerminalKeyboard::start`] until this
+/// is dropped, or until the process panics. Ctrl+C is not passed on, and calls
+/// the function given to `start` instead, after raw mode has been turned off.
+pub struct TerminalKeyboard {
+    queue: Arc<KeyQueue>,
+}
+
+impl TerminalKeyboard {
+    pub fn start(
+        queue: Arc<KeyQueue>,
+        on_interrupt: impl Fn() + Send + 'static,
+    ) -> io::Result<TerminalKeyboard> {
+        terminal::enable_raw_mode()?;
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = terminal::disable_raw_mode();
+            hook(info);
+        }));
+        let keys = Arc::clone(&queue);
+        thread::spawn(move || {
+            while let Ok(event) = event::read() {
+                let Event::Key(key) = event else {
+                    continue;
+                };
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    let _ = terminal::disable_raw_mode();
+                    on_interrupt();
+                    continue;
+                }
+                if let Some(code) = key_code(&key) {
+                    keys.push(code);
+                }
+            }
+        });
+        Ok(TerminalKeyboard { queue })
+    }
+
+    #[must_use]
+    pub fn queue(&self) -> &Arc<KeyQueue> {
+        &self.queue
+    }
+}
+
+impl Drop for TerminalKeyboard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,166 @@
+use crate::*;
+use std::sync::Arc;
+
+/// Settings for a [`CPU`], the same as the options of the same names given to `belle`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MachineOptions {
+    /// Milliseconds to wait before each clock cycle (`-t`)
+    pub time_delay: u32,
+    /// Print what the CPU does on every cycle (`-v`)
+    pub verbose: bool,
+    /// The CPU is run from the debugger (`-d`)
+    pub debug: bool,
+    /// Do not print errors (`-q`)
+    pub quiet: bool,
+    /// Print the registers when the CPU halts (`-p`)
+    pub pretty: bool,
+    /// Ignore `int`, so arbitrary programs can be run without side effects (`-f`)
+    pub fuzz: bool,
+    /// Keep a snapshot of the CPU after every cycle, see [`CPU::record_state`]
+    pub record: bool,
+}
+
+/// Builds a [`CPU`] with explicit options. Every CPU owns its clock and the
+/// snapshots recorded while it runs, so any number of them can run in one
+/// process.
+///
+/// ```
+/// use belle::Machine;
+///
+/// let mut cpu = Machine::new().time_delay(0).record(true).build();
+/// cpu.load_binary(&vec![0x0000]);
+/// cpu.run().unwrap();
+/// assert_eq!(cpu.clock, 1);
+/// ```
+///
+/// Programs use the terminal for `int` I/O unless given another [`Console`]:
+///
+/// ```
+/// use belle::{Machine, MemoryConsole};
+/// use std::sync::{Arc, Mutex};
+///
+/// let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+/// let mut cpu = Machine::new().console(console.clone()).build();
+/// // int 0, hlt
+/// cpu.load_binary(&vec![0xD100_u16 as i16, 0x0000]);
+/// cpu.run().unwrap();
+/// assert_eq!(console.lock().unwrap().output, "0\n");
+/// ```
+#[derive(Clone, Default)]
+pub struct Machine {
+    options: MachineOptions,
+    console: Option<SharedConsole>,
+    bus: Bus,
+    interrupts: InterruptController,
+    symbols: Option<Arc<DebugSymbols>>,
+}
+
+impl Machine {
+    #[must_use]
+    pub fn new() -> Machine {
+        Machine::default()
+    }
+
+    #[must_use]
+    pub fn with_options(options: MachineOptions) -> Machine {
+        Machine {
+            options,
+            ..Machine::default()
+        }
+    }
+
+    #[must_use]
+    pub fn time_delay(mut self, milliseconds: u32) -> Machine {
+        self.options.time_delay = milliseconds;
+        self
+    }
+
+    #[must_use]
+    pub fn verbose(mut self, verbose: bool) -> Machine {
+        self.options.verbose = verbose;
+        self
+    }
+
+    #[must_use]
+    pub fn debug(mut self, debug: bool) -> Machine {
+        self.options.debug = debug;
+        self
+    }
+
+    #[must_use]
+    pub fn quiet(mut self, quiet: bool) -> Machine {
+        self.options.quiet = quiet;
+        self
+    }
+
+    #[must_use]
+    pub fn pretty(mut self, pretty: bool) -> Machine {
+        self.options.pretty = pretty;
+        self
+    }
+
+    #[must_use]
+    pub fn fuzz(mut self, fuzz: bool) -> Machine {
+        self.options.fuzz = fuzz;
+        self
+    }
+
+    #[must_use]
+    pub fn record(mut self, record: bool) -> Machine {
+        self.options.record = record;
+        self
+    }
+
+    /// Sends the program's `int` I/O to `console` instead of the terminal
+    #[must_use]
+    pub fn console(mut self, console: SharedConsole) -> Machine {
+        self.console = Some(console);
+        self
+    }
+
+    /// Gives the CPUs this builds the program's labels and source lines, so
+    /// errors and the debuggers can say where an address is in the source
+    #[must_use]
+    pub fn symbols(mut self, symbols: Option<Arc<DebugSymbols>>) -> Machine {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Maps `device` into `len` addresses starting at `start`, see [`Bus::map`]
+    ///
+    /// # Panics
+    ///
+    /// If the range does not fit in memory or overlaps a device mapped before
+    #[must_use]
+    pub fn device(mut self, name: &str, start: u16, len: u16, device: SharedDevice) -> Machine {
+        if let Err(e) = self.bus.map(name, start, len, device) {
+            panic!("{e}");
+        }
+        self
+    }
+
+    /// A handle for a device to raise `line` on the CPUs this builds, see
+    /// [`InterruptController::line`]
+    #[must_use]
+    pub fn interrupt_line(&self, line: u8) -> InterruptLine {
+        self.interrupts.line(line)
+    }
+
+    #[must_use]
+    pub fn options(&self) -> &MachineOptions {
+        &self.options
+    }
+
+    #[must_use]
+    pub fn build(&self) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.options = self.options.clone();
+        if let Some(console) = &self.console {
+            cpu.console = Arc::clone(console);
+        }
+        cpu.bus = self.bus.clone();
+        cpu.interrupts = self.interrupts.clone();
+        cpu.symbols = self.symbols.clone();
+        cpu
+    }
+}
@@ -4,3 +4,19 @@ pub mod state_recording;
 pub use state_recording::*;
 //pub mod instructions;
 //pub use instructions::*;
+pub mod executable;
+pub use executable::*;
+pub mod machine;
+pub use machine::*;
+pub mod console;
+pub use console::*;
+pub mod bus;
+pub use bus::*;
+pub mod interrupts;
+pub use interrupts::*;
+pub mod timer;
+pub use timer::*;
+pub mod framebuffer;
+pub use framebuffer::*;
+pub mod keyboard;
+pub use keyboard::*;
@@ -1,13 +1,13 @@
 use crate::*;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::Mutex;
-
-pub static CPU_STATE: Lazy<Mutex<HashMap<u32, Arc<ModCPU>>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-pub static CLOCK: Lazy<Mutex<u32>> = Lazy::new(|| Mutex::new(0));
+use std::collections::BTreeMap;
+
 const MAX_MEMORY_LIMIT: usize = 128 * 1024 * 1024; // 128 MB
+/// Every this many snapshots, memory is recorded in full rather than as the
+/// changes since the snapshot before, so rebuilding any one cycle stays quick
+const KEYFRAME_INTERVAL: usize = 1024;
+
+/// A full copy of the state of a CPU on one clock cycle
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct ModCPU {
     pub int_reg: [i16; 4], // r0 thru r5
     pub uint_reg: [u16; 2],
@@ -23,6 +23,12 @@ pub struct ModCPU {
     pub sp: u16,
     pub bp: u16,
     pub ip: u16,
+    pub backward_stack: bool,
+    pub interrupts_enabled: bool,
+    pub interrupt_mask: u16,
+    pub vector_table: u16,
+    pub hlt_on_overflow: bool,
+    pub max_clk: Option<usize>,
 }
 
 impl ModCPU {
@@ -34,11 +40,19 @@ impl ModCPU {
             .filter_map(|(i, element)| element.map(|value| (i as u16, value)))
             .collect();
 
+        ModCPU {
+            memory,
+            ..ModCPU::registers_from_cpu(origin)
+        }
+    }
+
+    /// Everything but memory
+    fn registers_from_cpu(origin: &CPU) -> ModCPU {
         ModCPU {
             int_reg: origin.int_reg,
             uint_reg: origin.uint_reg,
             float_reg: origin.float_reg,
-            memory,
+            memory: Vec::new(),
             pc: origin.pc,
             ir: origin.ir,
             running: origin.running,
@@ -49,36 +63,310 @@ impl ModCPU {
             sp: origin.sp,
             bp: origin.bp,
             ip: origin.ip,
+            backward_stack: origin.backward_stack,
+            interrupts_enabled: origin.interrupts.enabled,
+            interrupt_mask: origin.interrupts.mask,
+            vector_table: origin.interrupts.vector_table,
+            hlt_on_overflow: origin.hlt_on_overflow,
+            max_clk: origin.max_clk,
         }
     }
 }
 
-impl CPU {
-    pub fn record_state(&self) {
-        let mut state = CPU_STATE.lock().unwrap();
-        let clock = CLOCK.lock().unwrap();
-        while state.len() * std::mem::size_of::<(u32, Arc<ModCPU>)>() > MAX_MEMORY_LIMIT {
-            if let Some(key) = state.keys().next().cloned() {
-                state.remove(&key);
-                return;
+/// One clock cycle of a [`Trace`]
+#[derive(Debug, Clone)]
+struct Snapshot {
+    /// The registers, with `memory` left empty
+    registers: ModCPU,
+    /// Memory was recorded in full, rather than as changes
+    keyframe: bool,
+    /// On a keyframe every address in use, and otherwise the addresses
+    /// written on this cycle, with what they held afterwards
+    memory: Vec<(u16, Option<i16>)>,
+    /// The addresses written on this cycle, kept apart only on a keyframe
+    written: Vec<u16>,
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        std::mem::size_of::<(u32, Snapshot)>()
+            + self.memory.len() * std::mem::size_of::<(u16, Option<i16>)>()
+            + self.written.len() * std::mem::size_of::<u16>()
+    }
+
+    fn written(&self) -> Vec<u16> {
+        if self.keyframe {
+            self.written.clone()
+        } else {
+            self.memory.iter().map(|&(address, _)| address).collect()
+        }
+    }
+}
+
+/// Snapshots of a CPU, by the clock cycle they were taken on.
+///
+/// Memory is kept as the addresses each cycle wrote, with a full copy every
+/// so often, so a long run takes little room. If the snapshots still outgrow
+/// 128 MB, the oldest are dropped a stretch at a time, and
+/// [`Trace::first_clock`] says where the history now starts.
+#[derive(Debug, Clone, Default)]
+pub struct Trace {
+    snapshots: BTreeMap<u32, Snapshot>,
+    bytes: usize,
+    since_keyframe: usize,
+    /// The next snapshot has to be a keyframe
+    resync: bool,
+    /// Some of the history has been dropped
+    dropped: bool,
+}
+
+impl Trace {
+    #[must_use]
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    /// Takes a snapshot of `cpu` on its current clock cycle, replacing any
+    /// recorded on that cycle or after it. Returns whether history had to be
+    /// dropped for the first time to make room.
+    pub fn record(&mut self, cpu: &CPU) -> bool {
+        let clock = cpu.clock;
+        for (_, stale) in self.snapshots.split_off(&clock) {
+            self.bytes -= stale.size();
+        }
+        let follows = clock
+            .checked_sub(1)
+            .is_some_and(|previous| self.snapshots.contains_key(&previous));
+        let keyframe = self.resync || !follows || self.since_keyframe >= KEYFRAME_INTERVAL;
+        let mut written: Vec<u16> = cpu.stores.iter().map(|&address| address as u16).collect();
+        written.sort_unstable();
+        written.dedup();
+        let memory = if keyframe {
+            self.since_keyframe = 0;
+            cpu.memory
+                .iter()
+                .enumerate()
+                .filter_map(|(i, element)| element.map(|value| (i as u16, Some(value))))
+                .collect()
+        } else {
+            self.since_keyframe += 1;
+            std::mem::take(&mut written)
+                .into_iter()
+                .map(|address| (address, cpu.memory[usize::from(address)]))
+                .collect()
+        };
+        self.resync = false;
+        let snapshot = Snapshot {
+            registers: ModCPU::registers_from_cpu(cpu),
+            keyframe,
+            memory,
+            written,
+        };
+        self.bytes += snapshot.size();
+        self.snapshots.insert(clock, snapshot);
+        self.make_room()
+    }
+
+    /// Drops the oldest stretch of snapshots, up to the next keyframe, until
+    /// the trace fits
+    fn make_room(&mut self) -> bool {
+        let first_drop = !self.dropped;
+        let mut dropped = false;
+        while self.bytes > MAX_MEMORY_LIMIT && self.snapshots.len() > 1 {
+            let next_keyframe = self
+                .snapshots
+                .iter()
+                .skip(1)
+                .find(|(_, snapshot)| snapshot.keyframe)
+                .map(|(&clock, _)| clock);
+            let kept = match next_keyframe {
+                Some(clock) => self.snapshots.split_off(&clock),
+                // everything left depends on the oldest keyframe
+                None => break,
+            };
+            for snapshot in self.snapshots.values() {
+                self.bytes -= snapshot.size();
             }
+            self.snapshots = kept;
+            dropped = true;
         }
-        /*while &state.len() * std::mem::size_of::<(u32, Arc<ModCPU>)>() > MAX_MEMORY_LIMIT {
-            if let &Some(oldest_key) = &state.keys().next() {
-                state.remove(oldest_key);
-                return;
+        self.dropped |= dropped;
+        dropped && first_drop
+    }
+
+    /// Makes the next snapshot copy memory in full, after memory was changed
+    /// by something other than the program, such as loading it
+    pub fn resync(&mut self) {
+        self.resync = true;
+    }
+
+    #[must_use]
+    pub fn contains(&self, clock: u32) -> bool {
+        self.snapshots.contains_key(&clock)
+    }
+
+    /// The earliest cycle still recorded
+    #[must_use]
+    pub fn first_clock(&self) -> Option<u32> {
+        self.snapshots.keys().next().copied()
+    }
+
+    #[must_use]
+    pub fn last_clock(&self) -> Option<u32> {
+        self.snapshots.keys().next_back().copied()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Whether snapshots were dropped to stay under the size limit
+    #[must_use]
+    pub fn dropped(&self) -> bool {
+        self.dropped
+    }
+
+    /// The recorded cycles, oldest first
+    pub fn clocks(&self) -> impl Iterator<Item = u32> + '_ {
+        self.snapshots.keys().copied()
+    }
+
+    /// The addresses written on `clock`
+    #[must_use]
+    pub fn written(&self, clock: u32) -> Vec<u16> {
+        self.snapshots
+            .get(&clock)
+            .map(Snapshot::written)
+            .unwrap_or_default()
+    }
+
+    /// The state of the CPU on `clock`, rebuilt from the keyframe before it
+    #[must_use]
+    pub fn state(&self, clock: u32) -> Option<ModCPU> {
+        let last = self.snapshots.get(&clock)?;
+        let mut memory = BTreeMap::new();
+        for snapshot in self.since_keyframe(clock) {
+            if snapshot.keyframe {
+                memory.clear();
+            }
+            for &(address, value) in &snapshot.memory {
+                match value {
+                    Some(value) => memory.insert(address, value),
+                    None => memory.remove(&address),
+                };
             }
-        }*/
-        let modified = ModCPU::modcpu_from_cpu(self);
-        state.insert(*clock, Arc::new(modified));
+        }
+        Some(ModCPU {
+            memory: memory.into_iter().collect(),
+            ..last.registers.clone()
+        })
     }
 
-    pub fn display_state(clock: &u32) {
-        if !CONFIG.verbose && !CONFIG.debug {
-            return;
+    /// The snapshots from the last keyframe at or before `clock` up to `clock`
+    fn since_keyframe(&self, clock: u32) -> impl Iterator<Item = &Snapshot> {
+        let start = self
+            .snapshots
+            .range(..=clock)
+            .rev()
+            .find(|(_, snapshot)| snapshot.keyframe)
+            .map_or(clock, |(&clock, _)| clock);
+        self.snapshots
+            .range(start..=clock)
+            .map(|(_, snapshot)| snapshot)
+    }
+}
+
+impl CPU {
+    /// Keeps a snapshot of the CPU for the current clock cycle
+    pub fn record_state(&mut self) {
+        let mut trace = std::mem::take(&mut self.trace);
+        if trace.record(self) && !self.options.quiet {
+            eprintln!(
+                "Recorded history is over 128 MB, dropping the oldest clock cycles (now from {})",
+                trace.first_clock().unwrap_or(self.clock)
+            );
+        }
+        self.trace = trace;
+    }
+
+    /// Puts the CPU back the way it was on a recorded clock cycle, returning
+    /// whether that cycle is recorded. Devices on the bus are not rewound.
+    pub fn rewind(&mut self, clock: u32) -> bool {
+        if !self.trace.contains(clock) {
+            return false;
+        }
+        let trace = std::mem::take(&mut self.trace);
+        for snapshot in trace.since_keyframe(clock) {
+            self.apply(snapshot);
+        }
+        self.trace = trace;
+        self.clock = clock;
+        self.stores = self
+            .trace
+            .written(clock)
+            .into_iter()
+            .map(usize::from)
+            .collect();
+        true
+    }
+
+    /// The last recorded cycle before `clock` that `stops` picks, found by
+    /// rebuilding every recorded cycle in turn on a scratch CPU, whose
+    /// `stores` hold the addresses written on that cycle
+    pub fn find_before(&self, clock: u32, mut stops: impl FnMut(&CPU) -> bool) -> Option<u32> {
+        let mut scratch = CPU::new();
+        let mut found = None;
+        for (&at, snapshot) in self.trace.snapshots.range(..clock) {
+            scratch.apply(snapshot);
+            scratch.clock = at;
+            scratch.stores = snapshot.written().into_iter().map(usize::from).collect();
+            if stops(&scratch) {
+                found = Some(at);
+            }
+        }
+        found
+    }
+
+    fn apply(&mut self, snapshot: &Snapshot) {
+        if snapshot.keyframe {
+            self.memory.fill(None);
+        }
+        for &(address, value) in &snapshot.memory {
+            self.memory[usize::from(address)] = value;
         }
-        let state = CPU_STATE.lock().unwrap();
-        if let Some(cpu) = state.get(clock) {
+        self.restore_registers(&snapshot.registers);
+    }
+
+    fn restore_registers(&mut self, state: &ModCPU) {
+        self.int_reg = state.int_reg;
+        self.uint_reg = state.uint_reg;
+        self.float_reg = state.float_reg;
+        self.pc = state.pc;
+        self.ir = state.ir;
+        self.running = state.running;
+        self.zflag = state.zflag;
+        self.oflag = state.oflag;
+        self.rflag = state.rflag;
+        self.sflag = state.sflag;
+        self.sp = state.sp;
+        self.bp = state.bp;
+        self.ip = state.ip;
+        self.backward_stack = state.backward_stack;
+        self.interrupts.enabled = state.interrupts_enabled;
+        self.interrupts.mask = state.interrupt_mask;
+        self.interrupts.vector_table = state.vector_table;
+        self.hlt_on_overflow = state.hlt_on_overflow;
+        self.max_clk = state.max_clk;
+    }
+
+    pub fn display_state(&self, clock: u32) {
+        if let Some(cpu) = self.trace.state(clock) {
             println!("\nCPU State for clock cycle {clock}:");
             println!("  Signed Integer Registers : {:?}", cpu.int_reg);
             println!("  Uint registers           : {:?}", cpu.uint_reg);
@@ -95,31 +383,163 @@ impl CPU {
             let mut tmp = CPU::new();
             tmp.ir = cpu.ir;
             println!("  Disassembled Instruction : {}", tmp.parse_instruction());
+            if let Some(source) = self.describe_address(cpu.ip) {
+                println!("  Source                   : {source}");
+            }
             if let Some((_, n)) = cpu.memory.iter().find(|&&(first, _)| first == cpu.pc) {
                 let mut tmp = CPU::new();
                 tmp.ir = *n;
-                println!("  Next instruction         : {}\n", tmp.parse_instruction());
+                println!("  Next instruction         : {}", tmp.parse_instruction());
+                if let Some(source) = self.describe_address(cpu.pc) {
+                    println!("  Next source              : {source}");
+                }
+                println!();
             }
         } else {
             println!("No CPU state found for clock: {clock}");
         }
     }
-}
 
-pub fn display_mem(addr: &usize, clock: &u32) -> Option<i32> {
-    let state = CPU_STATE.lock().unwrap();
-    if let Some(cpu) = state.get(clock) {
-        if let Some((_, v)) = cpu
-            .memory
-            .iter()
-            .find(|&&(first, _)| first == (*addr as u16))
-        {
-            Some(*v as i32)
+    /// The value at `addr` in the snapshot taken on `clock`
+    pub fn display_mem(&self, addr: usize, clock: u32) -> Option<i32> {
+        if let Some(cpu) = self.trace.state(clock) {
+            if let Some((_, v)) = cpu
+                .memory
+                .iter()
+                .find(|&&(first, _)| first == (addr as u16))
+            {
+                Some(*v as i32)
+            } else {
+                eprintln!("Nothing in memory here on this clock cycle\n");
+                None
+            }
         } else {
-            eprintln!("Nothing in memory here on this clock cycle\n");
             None
         }
-    } else {
-        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `cycles` clock cycles by hand, each writing the clock to one of
+    /// eight addresses, and records every one. Returns the full state on
+    /// each of the `kept` cycles.
+    fn counting(cpu: &mut CPU, trace: &mut Trace, cycles: u32, kept: &[u32]) -> Vec<ModCPU> {
+        let mut states = Vec::new();
+        for clock in 1..=cycles {
+            cpu.stores.clear();
+            cpu.clock = clock;
+            cpu.pc = clock as u16;
+            cpu.write_memory(1000 + (clock % 8) as usize, clock as i16);
+            trace.record(cpu);
+            if kept.contains(&clock) {
+                states.push(ModCPU::modcpu_from_cpu(cpu));
+            }
+        }
+        states
+    }
+
+    #[test]
+    fn states_are_rebuilt_across_keyframes() {
+        let mut cpu = CPU::new();
+        cpu.memory[5] = Some(-5);
+        let mut trace = Trace::new();
+        trace.record(&cpu);
+        let interval = KEYFRAME_INTERVAL as u32 + 1;
+        let cycles = interval * 2 + 100;
+        let clocks = [1, 7, interval - 1, interval, interval + 3, cycles];
+        let expected = counting(&mut cpu, &mut trace, cycles, &clocks);
+        let keyframes: Vec<u32> = trace
+            .snapshots
+            .iter()
+            .filter(|(_, snapshot)| snapshot.keyframe)
+            .map(|(&clock, _)| clock)
+            .collect();
+        assert_eq!(keyframes, [0, interval, interval * 2]);
+        for (clock, expected) in clocks.into_iter().zip(expected) {
+            assert_eq!(trace.state(clock), Some(expected), "clock {clock}");
+            assert_eq!(trace.written(clock), [1000 + (clock % 8) as u16]);
+        }
+
+        // rewinding puts memory back as well as the registers
+        cpu.trace = trace;
+        assert!(cpu.rewind(interval + 3));
+        assert_eq!(cpu.pc, interval as u16 + 3);
+        assert_eq!(
+            cpu.memory[1000 + ((interval + 3) % 8) as usize],
+            Some(interval as i16 + 3)
+        );
+        assert_eq!(
+            cpu.memory[1000 + ((interval + 4) % 8) as usize],
+            Some(interval as i16 - 4)
+        );
+        assert_eq!(cpu.memory[5], Some(-5));
+        assert_eq!(cpu.stores, [1000 + ((interval + 3) % 8) as usize]);
+        assert!(!cpu.rewind(cycles + 1));
+    }
+
+    #[test]
+    fn recording_again_replaces_later_history() {
+        let mut cpu = CPU::new();
+        let mut trace = Trace::new();
+        counting(&mut cpu, &mut trace, 10, &[]);
+        cpu.clock = 4;
+        cpu.stores.clear();
+        trace.record(&cpu);
+        assert_eq!(trace.last_clock(), Some(4));
+        assert_eq!(trace.len(), 4);
+    }
+
+    #[test]
+    fn oldest_history_is_dropped_to_stay_under_the_limit() {
+        let mut cpu = CPU::new();
+        cpu.memory.fill(Some(1));
+        let mut trace = Trace::new();
+        // every cycle skipped makes the next snapshot a full keyframe
+        let mut clock = 0;
+        let mut first_drops = 0;
+        while !trace.dropped() || clock < 20 {
+            clock += 2;
+            cpu.clock = clock;
+            if trace.record(&cpu) {
+                first_drops += 1;
+            }
+            assert!(trace.bytes <= MAX_MEMORY_LIMIT);
+        }
+        assert_eq!(first_drops, 1);
+        assert!(trace.first_clock().unwrap() > 2);
+        assert_eq!(trace.last_clock(), Some(clock));
+        let oldest = trace.first_clock().unwrap();
+        assert_eq!(trace.state(oldest).unwrap().memory.len(), MEMORY_SIZE);
+        assert!(trace.state(2).is_none());
+        let total: usize = trace.snapshots.values().map(Snapshot::size).sum();
+        assert_eq!(trace.bytes, total);
+    }
+
+    #[test]
+    fn stepping_back_undoes_a_halt() {
+        let mut cpu = Machine::new().time_delay(0).record(true).build();
+        // mov r0, #1; hlt
+        cpu.load_binary(&vec![0xE101_u16 as i16, 0x0000]);
+        cpu.hlt_on_overflow = true;
+        cpu.run().unwrap();
+        assert_eq!(cpu.clock, 2);
+        assert_eq!(cpu.trace.last_clock(), Some(2));
+
+        cpu.hlt_on_overflow = false;
+        cpu.max_clk = Some(9);
+        assert!(cpu.rewind(cpu.clock - 1));
+        assert!(cpu.running);
+        assert_eq!(cpu.pc, 101);
+        assert_eq!(cpu.int_reg[0], 1);
+        assert!(cpu.hlt_on_overflow);
+        assert_eq!(cpu.max_clk, None);
+
+        // carrying on runs the hlt again rather than past it
+        cpu.run().unwrap();
+        assert_eq!(cpu.clock, 2);
+        assert!(!cpu.running);
     }
 }
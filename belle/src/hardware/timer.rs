@@ -0,0 +1,91 @@
+use crate::*;
+
+/// Where belle maps its timer
+pub const TIMER_ADDRESS: u16 = 500;
+/// The timer's interrupt line
+pub const TIMER_INTERRUPT: u8 = 0;
+
+/// Control register bits
+pub const TIMER_ENABLE: i16 = 1;
+pub const TIMER_INTERRUPT_ENABLE: i16 = 1 << 1;
+pub const TIMER_PERIODIC: i16 = 1 << 2;
+
+/// A timer that counts clock cycles, so programs can wait for a number of
+/// cycles no matter how fast the host runs them. It takes four addresses:
+///
+/// | Offset | Register | |
+/// | :----- | :------- | :- |
+/// | 0 | control | bit 0: counting, bit 1: interrupt on expiry, bit 2: periodic |
+/// | 1 | reload | the count the timer starts from |
+/// | 2 | counter | cycles left, counting down once per cycle |
+/// | 3 | status | bit 0: expired since last cleared, cleared by any store |
+///
+/// Storing to the reload register also loads the counter. A timer whose
+/// counter is already 0 does nothing. When the counter counts down to 0 the
+/// timer expires: it sets its status bit, raises its interrupt
+/// if asked to, and either starts again from the reload value (periodic) or
+/// stops counting.
+#[derive(Debug, Clone, Default)]
+pub struct TimerDevice {
+    pub control: i16,
+    pub reload: u16,
+    pub counter: u16,
+    pub expired: bool,
+    interrupt: Option<InterruptLine>,
+}
+
+impl TimerDevice {
+    #[must_use]
+    pub fn new(interrupt: Option<InterruptLine>) -> TimerDevice {
+        TimerDevice {
+            interrupt,
+            ..TimerDevice::default()
+        }
+    }
+}
+
+impl Device for TimerDevice {
+    fn read(&mut self, offset: u16) -> Option<i16> {
+        match offset {
+            0 => Some(self.control),
+            1 => Some(self.reload as i16),
+            2 => Some(self.counter as i16),
+            3 => Some(i16::from(self.expired)),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: i16) {
+        match offset {
+            0 => self.control = value,
+            1 => {
+                self.reload = value as u16;
+                self.counter = value as u16;
+            }
+            2 => self.counter = value as u16,
+            3 => self.expired = false,
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, _clock: u32) {
+        if self.control & TIMER_ENABLE == 0 || self.counter == 0 {
+            return;
+        }
+        self.counter -= 1;
+        if self.counter > 0 {
+            return;
+        }
+        self.expired = true;
+        if self.control & TIMER_INTERRUPT_ENABLE != 0 {
+            if let Some(interrupt) = &self.interrupt {
+                interrupt.raise();
+            }
+        }
+        if self.control & TIMER_PERIODIC != 0 && self.reload > 0 {
+            self.counter = self.reload;
+        } else {
+            self.control &= !TIMER_ENABLE;
+        }
+    }
+}
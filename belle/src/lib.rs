@@ -2,8 +2,18 @@ pub mod hardware;
 pub use hardware::*;
 pub mod consts_enums;
 pub use consts_enums::*;
-pub mod config;
-pub use config::*;
+pub mod breakpoints;
+pub use breakpoints::*;
 pub mod debugger;
 pub use debugger::*;
+pub mod gdb_stub;
+pub use gdb_stub::*;
+pub mod dap;
+pub use dap::*;
+pub mod debug_symbols;
+pub use debug_symbols::*;
+pub mod disassembler;
+pub use disassembler::*;
+pub mod expressions;
+pub use expressions::*;
 // tests
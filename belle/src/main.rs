@@ -4,11 +4,17 @@
  *
  * This code is licensed under the BSD 3-Clause License.
  */
-use belle::{bin_to_vec, run_bdb, EmuError, CONFIG, CPU};
+mod config;
+use belle::{
+    bin_to_vec, load_debug_symbols, run_bdb, run_dap, run_gdb_stub, BdbOptions, EmuError,
+    Framebuffer, KeyQueue, TerminalKeyboard,
+};
+use config::CONFIG;
 use std::fs::{self, File};
 use std::io;
 use std::path::Path;
 use std::process;
+use std::sync::{Arc, Mutex};
 
 fn main() -> io::Result<()> {
     if CONFIG.debug && CONFIG.verbose {
@@ -25,29 +31,88 @@ fn main() -> io::Result<()> {
         );
         process::exit(1);
     }
+    if CONFIG.dap {
+        let machine = CONFIG.machine(
+            &Arc::new(Mutex::new(Framebuffer::new())),
+            &Arc::new(KeyQueue::new()),
+        );
+        return run_dap(machine);
+    }
     let executable_path = &CONFIG.file;
 
     if let Ok(metadata) = fs::metadata(executable_path) {
         if metadata.is_dir() {
-            eprintln!("{}", EmuError::IsDirectory());
+            eprintln!("{}", EmuError::IsDirectory(executable_path.to_string()));
             process::exit(1);
         }
     }
     if File::open(Path::new(executable_path)).is_err() {
-        eprintln!("{}", EmuError::FileNotFound());
+        eprintln!("{}", EmuError::FileNotFound(executable_path.to_string()));
         process::exit(1);
     }
-    let bin = bin_to_vec(executable_path)?;
+    let bin = match bin_to_vec(executable_path, CONFIG.raw) {
+        Ok(bin) => bin,
+        Err(e) => {
+            eprintln!("{}", EmuError::InvalidExecutable(e.to_string()));
+            process::exit(1);
+        }
+    };
+    let symbols = load_debug_symbols(executable_path).map(Arc::new);
     if CONFIG.debug {
-        run_bdb(executable_path)?;
+        let machine = CONFIG
+            .machine(
+                &Arc::new(Mutex::new(Framebuffer::new())),
+                &Arc::new(KeyQueue::new()),
+            )
+            .symbols(symbols);
+        let options = BdbOptions {
+            raw: CONFIG.raw,
+            scripts: CONFIG.command.clone(),
+            batch: CONFIG.batch,
+        };
+        let status = run_bdb(executable_path, &machine, &options)?;
+        if status != 0 {
+            process::exit(status);
+        }
+        return Ok(());
     }
     if CONFIG.verbose {
         println!("CPU Initialized");
     }
-    let mut cpu = CPU::new();
+    let framebuffer = CONFIG.framebuffer();
+    let screen = Arc::clone(&framebuffer);
+    let _ = ctrlc::set_handler(move || halt(&screen));
+    let keys = Arc::new(KeyQueue::new());
+    let keyboard = if CONFIG.keyboard {
+        let screen = Arc::clone(&framebuffer);
+        Some(TerminalKeyboard::start(Arc::clone(&keys), move || {
+            halt(&screen)
+        })?)
+    } else {
+        None
+    };
+    let mut cpu = CONFIG.machine(&framebuffer, &keys).symbols(symbols).build();
     cpu.load_binary(&bin);
-    if let Err(e) = cpu.run() {
-        eprintln!("{e}");
+    if let Some(address) = &CONFIG.gdb {
+        let served = run_gdb_stub(address, &mut cpu);
+        drop(keyboard);
+        framebuffer.lock().unwrap().finish();
+        return served;
+    }
+    let result = cpu.run();
+    drop(keyboard);
+    let mut screen = framebuffer.lock().unwrap();
+    screen.finish();
+    if CONFIG.dump_screen {
+        println!("{}", screen.text());
+    }
+    if let Err(e) = result {
+        let error = e.located(cpu.symbols.as_deref());
+        if CONFIG.verbose {
+            eprintln!("{error:#}");
+        } else {
+            eprintln!("{error}");
+        }
         process::exit(1);
     }
     if cpu.err {
@@ -55,3 +120,12 @@ fn main() -> io::Result<()> {
     }
     Ok(())
 }
+
+/// Stops the emulator when it is interrupted, leaving the terminal usable
+fn halt(screen: &Mutex<Framebuffer>) -> ! {
+    if let Ok(mut screen) = screen.try_lock() {
+        screen.finish();
+    }
+    println!("Halting...");
+    process::exit(0);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use basm::{Assembler, AssemblerOptions};
+use common::{check, example};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Assembles fib with its symbols into a directory of its own, and runs
+/// `script` on it with `belle -d --batch` and `flags`
+fn run_script(name: &str, script: &str, flags: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("belle-bdb-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let assembly =
+        Assembler::new(AssemblerOptions::default()).assemble(example("fib").to_str().unwrap());
+    assert!(!assembly.has_errors());
+    let program: PathBuf = dir.join("fib");
+    fs::write(&program, assembly.to_bytes()).unwrap();
+    fs::write(dir.join("fib.bsym"), assembly.render_symbol_map()).unwrap();
+    let script_path = dir.join("script.bdb");
+    fs::write(&script_path, script).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_belle"))
+        .arg("-d")
+        .arg(&program)
+        .arg("-x")
+        .arg(&script_path)
+        .arg("--batch")
+        .args(flags)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+#[test]
+fn batch_script() {
+    let script = "\
+# stop at the end of the first trip round the loop
+l
+b @finish
+b 117
+bl
+c
+eval r5
+# the stack and the timer's reload register, which also loads its counter
+pk sp = 3
+p sp
+pk 501 = 42
+p 502
+del 2
+bl
+c
+";
+    let output = run_script("batch", script, &["--timer"]);
+    assert!(output.status.success());
+    check("bdb_batch", &String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn batch_exit_status() {
+    let output = run_script("status", "l\nspc 0\nc\n", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn program_counter_past_memory() {
+    let output = run_script("past", "l\nspc 65535\nw\ne\nn\nfinish\n", &[]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(stderr.matches("Nothing at PC 65535").count(), 2, "{stderr}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("Program Counter          : 65535"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("Next instruction"), "{stdout}");
+}
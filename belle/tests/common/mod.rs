@@ -0,0 +1,84 @@
+// Not every test uses every helper
+#![allow(dead_code)]
+
+use basm::{Assembler, AssemblerOptions, SourceFiles};
+use belle::DebugSymbols;
+use std::fs;
+use std::path::PathBuf;
+
+/// The programs in `examples/`, all of which are checked by these tests
+pub const EXAMPLES: &[&str] = &[
+    "count",
+    "fib",
+    "game",
+    "hello",
+    "keys",
+    "screen",
+    "spiral",
+    "timer",
+    "waste_time",
+];
+
+/// Path to the source of an example, by name
+pub fn example(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../examples")
+        .join(format!("{name}.asm"))
+}
+
+/// Assembles `text` with basm, as an executable or as a `--raw` image
+pub fn assemble(text: &str, raw: bool) -> Vec<i16> {
+    let options = AssemblerOptions {
+        raw,
+        ..AssemblerOptions::default()
+    };
+    let assembly = Assembler::with_files(options, SourceFiles::memory::<&str, &str>([]))
+        .assemble_source("main.asm", text);
+    assert!(
+        !assembly.has_errors(),
+        "{text}\ndoes not assemble: {:?}",
+        assembly.diagnostics
+    );
+    words(&assembly.to_bytes())
+}
+
+/// Assembles `text` as if it were read from a file called `name`, along
+/// with the symbols `basm -g` writes for it
+pub fn assemble_with_symbols(name: &str, text: &str) -> (Vec<i16>, DebugSymbols) {
+    let assembly = Assembler::with_files(
+        AssemblerOptions::default(),
+        SourceFiles::memory::<&str, &str>([]),
+    )
+    .assemble_source(name, text);
+    assert!(!assembly.has_errors(), "{:?}", assembly.diagnostics);
+    let symbols = DebugSymbols::parse(&assembly.render_symbol_map()).unwrap();
+    (words(&assembly.to_bytes()), symbols)
+}
+
+/// Big-endian bytes as words, the way belle reads a file
+pub fn words(bytes: &[u8]) -> Vec<i16> {
+    bytes
+        .chunks(2)
+        .map(|chunk| i16::from_be_bytes([chunk[0], chunk[1]]))
+        .collect()
+}
+
+/// Compares `output` with `tests/golden/<name>.txt`, or writes it there when
+/// `UPDATE_GOLDEN` is set
+pub fn check(name: &str, output: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.txt"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, output).unwrap();
+        return;
+    }
+    let golden =
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {e}", path.display()));
+    assert_eq!(
+        output,
+        golden,
+        "output of {name} differs from {}",
+        path.display()
+    );
+}
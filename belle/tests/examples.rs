@@ -0,0 +1,98 @@
+mod common;
+
+use belle::{
+    Framebuffer, KeyQueue, KeyboardDevice, Machine, MemoryConsole, TimerDevice,
+    FRAMEBUFFER_ADDRESS, FRAMEBUFFER_SIZE, KEYBOARD_ADDRESS, KEYBOARD_INTERRUPT, TIMER_ADDRESS,
+    TIMER_INTERRUPT,
+};
+use common::{assemble, check, example};
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+/// Runs an example until it halts, or for `clock_limit` cycles, giving it
+/// `input` for `int 9`, and returns what it printed
+fn run(name: &str, machine: Machine, input: &[u8], clock_limit: Option<usize>) -> String {
+    let binary = assemble(&fs::read_to_string(example(name)).unwrap(), false);
+    let console = Arc::new(Mutex::new(MemoryConsole::new(input)));
+    let mut cpu = machine.console(console.clone()).build();
+    cpu.load_binary(&binary);
+    cpu.max_clk = clock_limit;
+    cpu.run().unwrap();
+    assert!(!cpu.err, "{name} stopped with an error");
+    let output = console.lock().unwrap().output.clone();
+    output
+}
+
+#[test]
+fn hello() {
+    check("hello", &run("hello", Machine::new(), b"", None));
+}
+
+#[test]
+fn fib() {
+    check("fib", &run("fib", Machine::new(), b"", None));
+}
+
+#[test]
+fn waste_time() {
+    check(
+        "waste_time",
+        &run("waste_time", Machine::new(), b"", Some(200)),
+    );
+}
+
+#[test]
+fn count() {
+    let output = run("count", Machine::new(), b"", Some(20000));
+    let numbers: Vec<u32> = output.lines().map(|line| line.parse().unwrap()).collect();
+    assert!(numbers.len() > 1000, "only counted to {}", numbers.len());
+    assert_eq!(numbers, (1..=numbers.len() as u32).collect::<Vec<_>>());
+}
+
+#[test]
+fn spiral() {
+    check("spiral", &run("spiral", Machine::new(), b"", Some(2000)));
+}
+
+#[test]
+fn game() {
+    check("game", &run("game", Machine::new(), b"dddaq", None));
+}
+
+#[test]
+fn keys() {
+    let queue = Arc::new(KeyQueue::new());
+    for key in b"abq" {
+        queue.push((*key).into());
+    }
+    let machine = Machine::new();
+    let keyboard = KeyboardDevice::new(queue, Some(machine.interrupt_line(KEYBOARD_INTERRUPT)));
+    let machine = machine.device(
+        "keyboard",
+        KEYBOARD_ADDRESS,
+        2,
+        Arc::new(Mutex::new(keyboard)),
+    );
+    check("keys", &run("keys", machine, b"", None));
+}
+
+#[test]
+fn timer() {
+    let machine = Machine::new();
+    let timer = TimerDevice::new(Some(machine.interrupt_line(TIMER_INTERRUPT)));
+    let machine = machine.device("timer", TIMER_ADDRESS, 4, Arc::new(Mutex::new(timer)));
+    check("timer", &run("timer", machine, b"", None));
+}
+
+#[test]
+fn screen() {
+    let framebuffer = Arc::new(Mutex::new(Framebuffer::new()));
+    let machine = Machine::new().device(
+        "framebuffer",
+        FRAMEBUFFER_ADDRESS,
+        FRAMEBUFFER_SIZE,
+        framebuffer.clone(),
+    );
+    assert_eq!(run("screen", machine, b"", None), "");
+    check("screen", &framebuffer.lock().unwrap().text());
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use belle::{evaluate, Machine, MemoryConsole, CPU};
+use common::{assemble, assemble_with_symbols, example};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Runs an example on a CPU of its own, returning the CPU and what it printed
+fn run(name: &str, machine: Machine) -> (CPU, String) {
+    let binary = assemble(&fs::read_to_string(example(name)).unwrap(), false);
+    let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+    let mut cpu = machine.console(console.clone()).build();
+    cpu.load_binary(&binary);
+    cpu.run().unwrap();
+    let output = console.lock().unwrap().output.clone();
+    (cpu, output)
+}
+
+#[test]
+fn machines_run_in_parallel() {
+    let programs = ["fib", "hello"];
+    let expected: Vec<(u32, String)> = programs
+        .iter()
+        .map(|name| {
+            let (cpu, output) = run(name, Machine::new());
+            (cpu.clock, output)
+        })
+        .collect();
+
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let name = programs[i % programs.len()];
+            thread::spawn(move || {
+                let (cpu, output) = run(name, Machine::new().record(true));
+                (i, cpu, output)
+            })
+        })
+        .collect();
+    for handle in threads {
+        let (i, cpu, output) = handle.join().unwrap();
+        let (clock, expected_output) = &expected[i % programs.len()];
+        assert_eq!(cpu.clock, *clock, "clock of CPU {i}");
+        assert_eq!(&output, expected_output, "output of CPU {i}");
+        assert_eq!(cpu.trace.last_clock(), Some(*clock), "trace of CPU {i}");
+    }
+}
+
+#[test]
+fn machines_in_parallel_keep_their_own_symbols() {
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            thread::spawn(move || {
+                // every program divides by zero a different number of words in
+                let name = format!("program{i}.asm");
+                let text = format!(
+                    "entry{i}:\n{}    div r0, #0\n    hlt\n",
+                    "    nop\n".repeat(i)
+                );
+                let (binary, symbols) = assemble_with_symbols(&name, &text);
+                let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+                let mut cpu = Machine::new()
+                    .console(console)
+                    .symbols(Some(Arc::new(symbols)))
+                    .build();
+                cpu.load_binary(&binary);
+                let error = cpu.run().unwrap_err();
+                let report = error.located(cpu.symbols.as_deref()).to_string();
+                (i, cpu, report)
+            })
+        })
+        .collect();
+    for handle in threads {
+        let (i, cpu, report) = handle.join().unwrap();
+        let place = if i == 0 {
+            format!("entry{i} at program{i}.asm:{}", i + 2)
+        } else {
+            format!("entry{i}+{i} at program{i}.asm:{}", i + 2)
+        };
+        assert!(report.contains(&place), "CPU {i} reported {report}");
+        assert_eq!(evaluate(&format!("@entry{i}"), &cpu), Ok(100));
+        assert!(evaluate("@entry0", &cpu).is_err() || i == 0);
+    }
+}
+
+#[test]
+fn rewinding_one_cpu_leaves_another_alone() {
+    let (mut first, _) = run("fib", Machine::new().record(true));
+    let (second, _) = run("fib", Machine::new().record(true));
+    assert!(first.rewind(10));
+    assert_eq!(first.clock, 10);
+    assert_eq!(second.clock, second.trace.last_clock().unwrap());
+    assert!(!second.running);
+}
+
+#[test]
+fn cpus_built_by_one_machine_share_its_console() {
+    let binary = assemble(&fs::read_to_string(example("hello")).unwrap(), false);
+    let console = Arc::new(Mutex::new(MemoryConsole::new(b"")));
+    let machine = Machine::new().console(console.clone());
+    for _ in 0..2 {
+        let mut cpu = machine.build();
+        cpu.load_binary(&binary);
+        cpu.run().unwrap();
+    }
+    assert_eq!(
+        console.lock().unwrap().output,
+        "\x0chello, world!\n\n\x0chello, world!\n\n"
+    );
+}
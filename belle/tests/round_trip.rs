@@ -0,0 +1,50 @@
+mod common;
+
+use belle::{executable_source, raw_source, Executable};
+use common::{assemble, example, EXAMPLES};
+use std::fs;
+
+#[test]
+fn executables_disassemble_to_the_same_program() {
+    for name in EXAMPLES {
+        let source = fs::read_to_string(example(name)).unwrap();
+        let binary = assemble(&source, false);
+        let executable = Executable::parse(&binary).unwrap();
+        let disassembled = executable_source(&executable);
+        assert_eq!(
+            assemble(&disassembled, false),
+            binary,
+            "{name} disassembles to\n{disassembled}"
+        );
+    }
+}
+
+#[test]
+fn raw_images_disassemble_to_the_same_program() {
+    for name in EXAMPLES {
+        let source = fs::read_to_string(example(name)).unwrap();
+        let binary = assemble(&source, true);
+        let disassembled = raw_source(&binary);
+        assert_eq!(
+            assemble(&disassembled, true),
+            binary,
+            "{name} disassembles to\n{disassembled}"
+        );
+    }
+}
+
+#[test]
+fn data_words_are_written_as_plain_numbers() {
+    let binary = assemble(".start $100\nhlt\n.word -3, -32768, 7\n", true);
+    let disassembled = raw_source(&binary);
+    let data: Vec<&str> = disassembled
+        .lines()
+        .filter(|line| line.trim_start().starts_with(".word"))
+        .collect();
+    assert!(!data.is_empty(), "no data in\n{disassembled}");
+    assert!(
+        data.iter().all(|line| !line.contains('#')),
+        "{disassembled}"
+    );
+    assert_eq!(assemble(&disassembled, true), binary);
+}
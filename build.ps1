@@ -89,6 +89,7 @@ function Default-Build {
                     $PPid = $_.Id
                     Spinner $PPid "Building BELLE-asm..."
                     Copy-Item -Path "target\release\basm.exe" -Destination "../bin" -Force
+                    Copy-Item -Path "target\release\bld.exe" -Destination "../bin" -Force
                 }
                 Set-Location ..
             }
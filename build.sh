@@ -107,6 +107,7 @@ default_build() {
                     wait $pid
                 fi
                 cp -f target/release/basm ../bin
+                cp -f target/release/bld ../bin
                 cd ..
                 ;;
             bdump)
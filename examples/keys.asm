@@ -0,0 +1,21 @@
+    ; Prints the code of every key pressed until q is pressed, polling the
+    ; keyboard mapped at address 504 instead of waiting on int 9.
+    ; Run with belle --keyboard.
+.start [100]
+.ssp [20]
+.sbp [20]
+    jmp @poll
+poll:
+    pop r4
+    add r2, 1         ; anything else the program does goes here
+    ld r0, [504]      ; the number of keys waiting
+    cmp r0, 0
+    jz @poll
+    ld r1, [505]      ; the next key
+    int 1
+    cmp r1, 'q'
+    jz @end
+    jmp @poll
+end:
+    pop r4
+    hlt
@@ -0,0 +1,32 @@
+    ; Writes a message on the framebuffer, which belle maps at address 4096,
+    ; 80 cells to a row. Run with belle --screen to see it in colour, or with
+    ; --dump-screen to print it as text when the program halts.
+.start [100]
+    mov r5, 64        ; r5 points at row 1, column 2 (4096 + 82)
+    mul r5, 64
+    add r5, 82
+    mov r1, 30        ; yellow on blue, 0x1E, in the high byte
+    mul r1, 64
+    mul r1, 4
+    mov r0, r1
+    add r0, 'H'
+    st &r5, r0
+    add r5, 1
+    mov r0, r1
+    add r0, 'e'
+    st &r5, r0
+    add r5, 1
+    mov r0, r1
+    add r0, 'l'
+    st &r5, r0
+    add r5, 1
+    st &r5, r0
+    add r5, 1
+    mov r0, r1
+    add r0, 'o'
+    st &r5, r0
+    add r5, 1
+    mov r0, r1
+    add r0, '!'
+    st &r5, r0
+    hlt
@@ -0,0 +1,35 @@
+    ; Counts to 5, one number every 100 clock cycles, with the timer mapped at
+    ; address 500. The wait is the same however fast the host is, and
+    ; whatever --time-delay is set to. Run with belle --timer.
+.start [100]
+.ssp [20]
+.sbp [20]
+    mov r5, 100       ; r5 points at the timer registers, from 500
+    add r5, 100
+    add r5, 100
+    add r5, 100
+    add r5, 100
+    mov r2, 5         ; control: counting, periodic
+    st &r5, r2
+    add r5, 1
+    mov r1, 100       ; reload value, which also starts the counter
+    st &r5, r1
+    add r5, 2         ; r5 now points at the status register
+    jmp @wait
+wait:
+    pop r4
+    ld r3, [503]      ; status is 1 once the counter reaches 0
+    cmp r3, 1
+    jz @expired
+    jmp @wait
+expired:
+    pop r4
+    st &r5, r3        ; clear the status
+    add r0, 1
+    int 0
+    cmp r0, 5
+    jz @end
+    jmp @wait
+end:
+    pop r4
+    hlt
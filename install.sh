@@ -7,6 +7,7 @@ FILE1="basm"
 FILE2="bdump"
 FILE3="belle"
 FILE4="bfmt"
+FILE5="bld"
 print_message() {
     local message="$1"
     local color="$2"
@@ -35,6 +36,7 @@ install() {
     cp "$DIR/$FILE2" ~/.local/bin
     cp "$DIR/$FILE3" ~/.local/bin
     cp "$DIR/$FILE4" ~/.local/bin
+    cp "$DIR/$FILE5" ~/.local/bin
     print_message "Installation complete." green
 
     if ! echo "$PATH" | grep -q "$HOME/.local/bin"; then